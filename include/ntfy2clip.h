@@ -0,0 +1,31 @@
+#ifndef NTFY2CLIP_H
+#define NTFY2CLIP_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct n2c_client n2c_client;
+
+/* Called for every received message; the strings are only valid during the call. */
+typedef void (*n2c_message_cb)(const char *topic, const char *message, void *user_data);
+
+/* Starts a client in the background. server, token and callback may be NULL.
+ * Returns NULL on invalid arguments. */
+n2c_client *n2c_start(const char *server, const char *topic, const char *token,
+                      n2c_message_cb callback, void *user_data);
+
+/* Publishes text to the client's topic. Returns 0 on success, -1 on failure.
+ * Must not be called from inside the callback. */
+int n2c_publish(n2c_client *client, const char *text);
+
+/* Stops the client and frees the handle. Once it returns, the callback is not
+ * running and won't be called again, so user_data may be freed. Must not be
+ * called from inside the callback. */
+void n2c_stop(n2c_client *client);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,203 @@
+//! Local HTTP API: `POST /clip` with a text body feeds the pipeline, so local
+//! scripts get the same filtering and sinks as messages from the server.
+//! With UnifiedPush enabled, `POST /up/register` and `POST /up/unregister`
+//! take an app's callback URL as the body.
+
+use crate::channel::Sender;
+use crate::config::ApiConfig;
+use crate::message::WSMessage;
+use crate::unifiedpush;
+use log::{debug, error, info, warn};
+use std::io;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{self, Duration};
+use url::Url;
+
+const MAX_BODY: usize = 1024 * 1024;
+const MAX_HEADERS: usize = 64;
+const MAX_LINE: u64 = 8 * 1024;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+struct Request {
+    method: String,
+    path: String,
+    authorization: Option<String>,
+    content_length: usize,
+}
+
+pub(crate) fn spawn(config: &ApiConfig, topic: &str, pipeline: Sender<WSMessage>) {
+    let addr = config.addr;
+    let token = Arc::new(config.token.clone());
+    let topic = Arc::new(topic.to_string());
+    if !addr.ip().is_loopback() {
+        warn!("HTTP API is listening on non-loopback address {}", addr);
+    }
+    tokio::spawn(async move {
+        let listener = match TcpListener::bind(addr).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!("Failed to start HTTP API on {}: {}", addr, e);
+                return;
+            }
+        };
+        info!("HTTP API listening on {}", addr);
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(conn) => conn,
+                Err(e) => {
+                    error!("HTTP API accept failed: {}", e);
+                    continue;
+                }
+            };
+            let (token, topic, pipeline) = (token.clone(), topic.clone(), pipeline.clone());
+            tokio::spawn(async move {
+                match time::timeout(REQUEST_TIMEOUT, handle(stream, &token, &topic, &pipeline))
+                    .await
+                {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => debug!("HTTP API request from {} failed: {}", peer, e),
+                    Err(_) => debug!("HTTP API request from {} timed out", peer),
+                }
+            });
+        }
+    });
+}
+
+async fn handle(
+    stream: TcpStream,
+    token: &str,
+    topic: &str,
+    pipeline: &Sender<WSMessage>,
+) -> io::Result<()> {
+    let mut reader = BufReader::new(stream);
+    let Some(request) = read_head(&mut reader).await? else {
+        return respond(reader.get_mut(), "400 Bad Request").await;
+    };
+    if !matches!(
+        request.path.as_str(),
+        "/clip" | "/up/register" | "/up/unregister"
+    ) {
+        return respond(reader.get_mut(), "404 Not Found").await;
+    }
+    if request.method != "POST" {
+        return respond(reader.get_mut(), "405 Method Not Allowed").await;
+    }
+    let expected = format!("Bearer {token}");
+    if !request
+        .authorization
+        .is_some_and(|auth| constant_time_eq(auth.as_bytes(), expected.as_bytes()))
+    {
+        return respond(reader.get_mut(), "401 Unauthorized").await;
+    }
+    if request.content_length > MAX_BODY {
+        return respond(reader.get_mut(), "413 Payload Too Large").await;
+    }
+
+    let mut body = vec![0; request.content_length];
+    reader.read_exact(&mut body).await?;
+    let Ok(message) = String::from_utf8(body) else {
+        return respond(reader.get_mut(), "400 Bad Request").await;
+    };
+    match request.path.as_str() {
+        "/up/register" => {
+            if Url::parse(&message).is_err() {
+                return respond(reader.get_mut(), "400 Bad Request").await;
+            }
+            return match unifiedpush::register(&message)? {
+                Some(endpoint) => respond_with(reader.get_mut(), "200 OK", &endpoint).await,
+                None => respond(reader.get_mut(), "404 Not Found").await,
+            };
+        }
+        "/up/unregister" => {
+            let status = if unifiedpush::unregister(&message)? {
+                "204 No Content"
+            } else {
+                "404 Not Found"
+            };
+            return respond(reader.get_mut(), status).await;
+        }
+        _ => {}
+    }
+    debug!("HTTP API received {} bytes", message.len());
+    pipeline
+        .send(WSMessage {
+            id: None,
+            event: "message".to_string(),
+            topic: topic.to_string(),
+            message: Some(message),
+            ..Default::default()
+        })
+        .await;
+    respond(reader.get_mut(), "204 No Content").await
+}
+
+/// Reads the request line and headers. Returns `None` for malformed requests.
+async fn read_head(reader: &mut BufReader<TcpStream>) -> io::Result<Option<Request>> {
+    let mut line = String::new();
+    if !read_line(reader, &mut line).await? {
+        return Ok(None);
+    }
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Ok(None);
+    };
+    let mut request = Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        authorization: None,
+        content_length: 0,
+    };
+
+    for _ in 0..MAX_HEADERS {
+        line.clear();
+        if !read_line(reader, &mut line).await? {
+            return Ok(None);
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            return Ok(Some(request));
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            return Ok(None);
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("content-length") {
+            let Ok(length) = value.parse() else {
+                return Ok(None);
+            };
+            request.content_length = length;
+        } else if name.eq_ignore_ascii_case("authorization") {
+            request.authorization = Some(value.to_string());
+        }
+    }
+    Ok(None)
+}
+
+/// Reads a line of at most [`MAX_LINE`] bytes into `line`. Returns `false`
+/// at the end of the stream or for a longer line, which isn't read any further.
+pub(crate) async fn read_line(
+    reader: &mut BufReader<TcpStream>,
+    line: &mut String,
+) -> io::Result<bool> {
+    let read = reader.take(MAX_LINE).read_line(line).await?;
+    Ok(read > 0 && line.ends_with('\n'))
+}
+
+async fn respond(stream: &mut TcpStream, status: &str) -> io::Result<()> {
+    respond_with(stream, status, "").await
+}
+
+async fn respond_with(stream: &mut TcpStream, status: &str, body: &str) -> io::Result<()> {
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
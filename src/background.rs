@@ -0,0 +1,82 @@
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::events::{Event, EventBus};
+use crate::publish::publish;
+use crate::supervisor::{Supervisor, SupervisorHandle};
+use std::io;
+use tokio::runtime::Runtime;
+use tokio::sync::{broadcast, oneshot};
+
+/// A [`Supervisor`] running on a private Tokio runtime.
+///
+/// Every method, and the receivers returned by [`subscribe`](Self::subscribe),
+/// can be awaited from any executor (smol, async-std, a GUI main loop), so
+/// applications don't need to run Tokio themselves.
+pub struct BackgroundClient {
+    runtime: Option<Runtime>,
+    config: Config,
+    events: EventBus,
+    handle: SupervisorHandle,
+    done: Option<oneshot::Receiver<Result<()>>>,
+}
+
+impl BackgroundClient {
+    pub fn start(config: Config) -> io::Result<Self> {
+        let runtime = Runtime::new()?;
+        let events = EventBus::default();
+        let (supervisor, handle) = Supervisor::new(config.clone(), events.clone());
+        let (tx, rx) = oneshot::channel();
+        runtime.spawn(async move {
+            let _ = tx.send(supervisor.run().await);
+        });
+        Ok(BackgroundClient {
+            runtime: Some(runtime),
+            config,
+            events,
+            handle,
+            done: Some(rx),
+        })
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    pub fn pause(&self) {
+        self.handle.pause();
+    }
+
+    pub fn resume(&self) {
+        self.handle.resume();
+    }
+
+    pub async fn publish(&self, message: &str) -> Result<()> {
+        let (tx, rx) = oneshot::channel();
+        let config = self.config.clone();
+        let message = message.to_string();
+        self.runtime().spawn(async move {
+            let _ = tx.send(publish(&config, &message).await);
+        });
+        rx.await.expect("publish task dropped")
+    }
+
+    /// Waits until the supervisor gives up and returns the error that made it
+    /// stop, or `None` if this was already awaited.
+    pub async fn wait(&mut self) -> Option<Error> {
+        self.done.take()?.await.ok()?.err()
+    }
+
+    fn runtime(&self) -> &Runtime {
+        self.runtime
+            .as_ref()
+            .expect("runtime is only taken on drop")
+    }
+}
+
+impl Drop for BackgroundClient {
+    fn drop(&mut self) {
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
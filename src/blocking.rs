@@ -0,0 +1,104 @@
+//! Synchronous client for builds without Tokio, enabled with the `blocking`
+//! feature. Messages go through the same parsing and filtering as the async
+//! client but are written to the clipboard one at a time.
+
+use crate::clip::set_clip_blocking;
+use crate::config::{Config, Route};
+use crate::error::{ConnectError, ProtocolError, Result};
+use crate::filter;
+use crate::handshake;
+use crate::message;
+use crate::transform;
+use log::{debug, error, info};
+use std::io::ErrorKind;
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::{Message, WebSocket};
+
+/// Keeps the subscription alive, reconnecting after retryable errors.
+pub fn run(config: &Config) -> Result<()> {
+    loop {
+        match connect_and_run(config) {
+            Ok(()) => info!("Connection closed cleanly"),
+            Err(e) if e.is_retryable() => {
+                error!("Connection error: {}. Reconnecting...", e);
+                thread::sleep(Duration::from_secs(5));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+pub fn connect_and_run(config: &Config) -> Result<()> {
+    let request = handshake::request(config, None)?;
+    debug!("request: {:?}", &request);
+    let (mut socket, _) = tungstenite::connect(request).map_err(handshake::error)?;
+    set_read_timeout(&socket, config.timeout);
+    info!(
+        "connected to {} with topic={} and timeout={}",
+        config.server,
+        config.topic,
+        config.timeout.as_secs()
+    );
+
+    loop {
+        match socket.read() {
+            Ok(Message::Text(text)) => {
+                match message::decode(config, &text).and_then(|msg| message::unpack(config, msg)) {
+                    Ok(msg) => {
+                        // Only the clipboard is available here.
+                        let routed = *config.route(&msg.topic) == Route::Clipboard;
+                        let msg = (routed
+                            && filter::accepts(config, &msg)
+                            && filter::for_device(config, &msg))
+                        .then(|| transform::apply(config, msg))
+                        .flatten();
+                        if let Some(msg) = msg {
+                            debug!("WS received message: {:?}", &msg);
+                            if let Some(message) = &msg.message {
+                                if let Err(e) = set_clip_blocking(message) {
+                                    error!("Failed to set clipboard: {}", e);
+                                }
+                            }
+                        }
+                    }
+                    Err(e) => error!("Error in WebSocket connection: {}", e),
+                }
+            }
+            Ok(Message::Ping(_)) => {
+                // tungstenite queues the pong itself; flush it out.
+                socket.flush().map_err(ProtocolError::from)?;
+                debug!("WS received ping and sent pong");
+            }
+            Ok(Message::Close(_)) => {
+                debug!("WS received close message");
+                return Ok(());
+            }
+            Ok(_) => {}
+            Err(tungstenite::Error::Io(e))
+                if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+            {
+                return Err(ConnectError::Idle(config.timeout).into());
+            }
+            Err(e) => return Err(ProtocolError::from(e).into()),
+        }
+    }
+}
+
+/// Turns the idle timeout into a socket read timeout, since there is no
+/// timer to race the read against.
+fn set_read_timeout(socket: &WebSocket<MaybeTlsStream<TcpStream>>, timeout: Duration) {
+    let stream = match socket.get_ref() {
+        MaybeTlsStream::Plain(stream) => stream,
+        #[cfg(not(target_os = "macos"))]
+        MaybeTlsStream::NativeTls(stream) => stream.get_ref(),
+        #[cfg(target_os = "macos")]
+        MaybeTlsStream::Rustls(stream) => stream.get_ref(),
+        _ => return,
+    };
+    if let Err(e) = stream.set_read_timeout(Some(timeout)) {
+        error!("Failed to set read timeout: {}", e);
+    }
+}
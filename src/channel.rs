@@ -0,0 +1,231 @@
+use log::debug;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+/// What a [`Sender`] does when the channel is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Wait until the receiver makes room, applying backpressure upstream.
+    Block,
+    /// Discard the oldest queued item to make room for the new one.
+    DropOldest,
+    /// Keep only the newest item, replacing anything still queued.
+    Latest,
+}
+
+struct State<T> {
+    items: VecDeque<T>,
+    senders: usize,
+    receiver_alive: bool,
+}
+
+struct Shared<T> {
+    name: &'static str,
+    capacity: usize,
+    policy: OverflowPolicy,
+    state: Mutex<State<T>>,
+    readable: Notify,
+    writable: Notify,
+}
+
+pub(crate) struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+pub(crate) struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// Creates a bounded channel between two pipeline stages. `name` only shows
+/// up in logs.
+pub(crate) fn bounded<T>(
+    name: &'static str,
+    capacity: usize,
+    policy: OverflowPolicy,
+) -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        name,
+        capacity: capacity.max(1),
+        policy,
+        state: Mutex::new(State {
+            items: VecDeque::with_capacity(capacity),
+            senders: 1,
+            receiver_alive: true,
+        }),
+        readable: Notify::new(),
+        writable: Notify::new(),
+    });
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+impl<T> Sender<T> {
+    /// Queues `item` according to the channel's overflow policy. Returns
+    /// `false` if the receiver is gone.
+    pub async fn send(&self, item: T) -> bool {
+        let mut item = Some(item);
+        loop {
+            let writable = self.shared.writable.notified();
+            {
+                let mut state = self.shared.state.lock().unwrap();
+                if !state.receiver_alive {
+                    return false;
+                }
+                match self.shared.policy {
+                    OverflowPolicy::DropOldest if state.items.len() >= self.shared.capacity => {
+                        state.items.pop_front();
+                        debug!("{} queue full, dropped the oldest item", self.shared.name);
+                    }
+                    OverflowPolicy::Latest if !state.items.is_empty() => {
+                        state.items.clear();
+                        debug!("{} queue superseded by a newer item", self.shared.name);
+                    }
+                    _ => {}
+                }
+                if state.items.len() < self.shared.capacity {
+                    state.items.extend(item.take());
+                    drop(state);
+                    self.shared.readable.notify_one();
+                    return true;
+                }
+            }
+            writable.await;
+        }
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.state.lock().unwrap().senders += 1;
+        Sender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.senders -= 1;
+        if state.senders == 0 {
+            drop(state);
+            self.shared.readable.notify_one();
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Waits for the next item. Returns `None` once every sender is gone and
+    /// the queue is drained.
+    pub async fn recv(&mut self) -> Option<T> {
+        loop {
+            let readable = self.shared.readable.notified();
+            {
+                let mut state = self.shared.state.lock().unwrap();
+                if let Some(item) = state.items.pop_front() {
+                    drop(state);
+                    self.shared.writable.notify_one();
+                    return Some(item);
+                }
+                if state.senders == 0 {
+                    return None;
+                }
+            }
+            readable.await;
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    pub fn policy(&self) -> OverflowPolicy {
+        self.shared.policy
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().receiver_alive = false;
+        self.shared.writable.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::{self, Duration};
+
+    /// Receives everything queued, after every sender is gone.
+    async fn drain(mut rx: Receiver<u32>) -> Vec<u32> {
+        let mut items = Vec::new();
+        while let Some(item) = rx.recv().await {
+            items.push(item);
+        }
+        items
+    }
+
+    #[tokio::test]
+    async fn block_waits_for_room() {
+        let (tx, mut rx) = bounded("test", 2, OverflowPolicy::Block);
+        assert!(tx.send(1).await);
+        assert!(tx.send(2).await);
+        let blocked = tx.clone();
+        let third = tokio::spawn(async move { blocked.send(3).await });
+        time::sleep(Duration::from_millis(50)).await;
+        assert!(!third.is_finished());
+
+        assert_eq!(rx.recv().await, Some(1));
+        assert!(third.await.unwrap());
+        drop(tx);
+        assert_eq!(drain(rx).await, [2, 3]);
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_discards_the_front() {
+        let (tx, rx) = bounded("test", 2, OverflowPolicy::DropOldest);
+        for item in 1..=4 {
+            assert!(tx.send(item).await);
+        }
+        drop(tx);
+        assert_eq!(drain(rx).await, [3, 4]);
+    }
+
+    #[tokio::test]
+    async fn latest_keeps_only_the_newest() {
+        let (tx, mut rx) = bounded("test", 4, OverflowPolicy::Latest);
+        for item in 1..=3 {
+            assert!(tx.send(item).await);
+        }
+        assert_eq!(rx.recv().await, Some(3));
+        assert!(tx.send(4).await);
+        drop(tx);
+        assert_eq!(drain(rx).await, [4]);
+    }
+
+    #[tokio::test]
+    async fn send_fails_without_a_receiver() {
+        let (tx, rx) = bounded("test", 1, OverflowPolicy::Block);
+        assert!(tx.send(1).await);
+        let blocked = tx.clone();
+        let second = tokio::spawn(async move { blocked.send(2).await });
+        time::sleep(Duration::from_millis(50)).await;
+        drop(rx);
+        assert!(!second.await.unwrap());
+        assert!(!tx.send(3).await);
+    }
+
+    #[tokio::test]
+    async fn recv_ends_with_the_last_sender() {
+        let (tx, rx) = bounded("test", 4, OverflowPolicy::Block);
+        let other = tx.clone();
+        assert!(tx.send(1).await);
+        drop(tx);
+        assert!(other.send(2).await);
+        drop(other);
+        assert_eq!(drain(rx).await, [1, 2]);
+    }
+}
@@ -0,0 +1,144 @@
+//! Splitting long texts over several messages, and putting them back
+//! together.
+//!
+//! ntfy caps message bodies at 4 KiB by default. With
+//! [`Config::chunk_size`], longer texts are published as numbered pieces
+//! tagged `chunk:<id>:<n>/<count>`, and the subscriber buffers the pieces
+//! until all of them arrived, passing a single message on.
+
+use crate::message::WSMessage;
+use log::{debug, warn};
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use tokio::time::{Duration, Instant};
+
+const TAG_PREFIX: &str = "chunk:";
+/// How long the pieces of a text are waited for.
+const TIMEOUT: Duration = Duration::from_secs(60);
+/// Limits on what is buffered, against broken or hostile senders.
+const MAX_CHUNKS: usize = 4096;
+const MAX_PENDING: usize = 16;
+const MAX_TOTAL: usize = 16 * 1024 * 1024;
+
+/// The pieces `text` is published as, at most `size` bytes each, with their
+/// tags.
+pub(crate) fn split(text: &str, size: usize) -> Vec<(String, &str)> {
+    let mut pieces = Vec::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        let mut end = size.min(rest.len());
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        // A piece holds at least one character, even if it's over `size`.
+        if end == 0 {
+            end = rest.chars().next().map_or(rest.len(), char::len_utf8);
+        }
+        let (piece, after) = rest.split_at(end);
+        pieces.push(piece);
+        rest = after;
+    }
+    let id = new_id();
+    let count = pieces.len();
+    pieces
+        .into_iter()
+        .enumerate()
+        .map(|(i, piece)| (format!("{}{}:{}/{}", TAG_PREFIX, id, i + 1, count), piece))
+        .collect()
+}
+
+fn new_id() -> String {
+    format!("{:012x}", RandomState::new().build_hasher().finish() >> 16)
+}
+
+/// `(id, n, count)` of a chunk tag, with `n` counting from 1.
+fn parse(tag: &str) -> Option<(&str, usize, usize)> {
+    let (id, position) = tag.strip_prefix(TAG_PREFIX)?.rsplit_once(':')?;
+    let (n, count) = position.split_once('/')?;
+    let (n, count) = (n.parse().ok()?, count.parse().ok()?);
+    (!id.is_empty() && (1..=count).contains(&n)).then_some((id, n, count))
+}
+
+struct Pending {
+    started: Instant,
+    /// The first piece, for everything but the body.
+    msg: WSMessage,
+    pieces: Vec<Option<String>>,
+    size: usize,
+}
+
+/// Pieces of texts waiting for the rest.
+#[derive(Default)]
+pub(crate) struct Reassembler {
+    pending: HashMap<String, Pending>,
+}
+
+impl Reassembler {
+    /// Passes messages that aren't chunks through, and returns the whole
+    /// text once the last chunk of one arrived.
+    pub(crate) fn add(&mut self, mut msg: WSMessage) -> Option<WSMessage> {
+        self.expire();
+        let tags = msg.tags.as_deref().unwrap_or_default();
+        let Some((index, (id, n, count))) = tags
+            .iter()
+            .enumerate()
+            .find_map(|(i, tag)| Some((i, parse(tag)?)))
+        else {
+            return Some(msg);
+        };
+        let id = id.to_string();
+        let piece = msg.message.take().unwrap_or_default();
+        if let Some(tags) = &mut msg.tags {
+            tags.remove(index);
+        }
+        if count > MAX_CHUNKS {
+            warn!("Dropping text {} of {} chunks, too many", id, count);
+            return None;
+        }
+        if !self.pending.contains_key(&id) && self.pending.len() >= MAX_PENDING {
+            warn!("Dropping chunk of text {}, too many texts incomplete", id);
+            return None;
+        }
+        let pending = self.pending.entry(id.clone()).or_insert_with(|| Pending {
+            started: Instant::now(),
+            msg: msg.clone(),
+            pieces: vec![None; count],
+            size: 0,
+        });
+        if pending.pieces.len() != count {
+            warn!("Dropping text {}, its chunks disagree on their count", id);
+            self.pending.remove(&id);
+            return None;
+        }
+        if n == 1 {
+            pending.msg = msg;
+        }
+        pending.size += piece.len();
+        if pending.size > MAX_TOTAL {
+            warn!("Dropping text {}, over {} bytes", id, MAX_TOTAL);
+            self.pending.remove(&id);
+            return None;
+        }
+        pending.pieces[n - 1] = Some(piece);
+        debug!("received chunk {}/{} of text {}", n, count, id);
+        if pending.pieces.iter().any(Option::is_none) {
+            return None;
+        }
+        let pending = self.pending.remove(&id)?;
+        let mut msg = pending.msg;
+        msg.message = Some(pending.pieces.into_iter().flatten().collect());
+        Some(msg)
+    }
+
+    fn expire(&mut self) {
+        self.pending.retain(|id, pending| {
+            let expired = pending.started.elapsed() > TIMEOUT;
+            if expired {
+                let missing = pending.pieces.iter().filter(|p| p.is_none()).count();
+                warn!("Dropping text {}, {} chunks never arrived", id, missing);
+            }
+            !expired
+        });
+    }
+}
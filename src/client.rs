@@ -1,82 +1,31 @@
-use anyhow::{anyhow, Result};
-use futures_util::{SinkExt, StreamExt};
-use log::{debug, error, info};
+use clap::{Arg, CommandFactory, FromArgMatches};
+use log::{error, info, warn};
+use ntfy2clip::{Config, Sandbox, Vars};
+#[cfg(feature = "async")]
+use ntfy2clip::{EventBus, Supervisor, SupervisorHandle};
 #[cfg(target_os = "macos")]
 use oslog::OsLogger;
-use serde::Deserialize;
-use std::env;
-use std::io::Write;
-use std::process::{Command, Stdio};
-use tokio::spawn;
-use tokio::time::{self, Duration, Instant};
-use tokio_tungstenite::tungstenite::client::IntoClientRequest;
-use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
-use url::Url;
-
-#[derive(Deserialize, Debug)]
-struct WSMessage {
-    event: String,
-    topic: String,
-    message: Option<String>,
-}
-async fn set_clip(content: String) -> Result<()> {
-    info!("Setting clipboard to: {}", &content);
-
-    let (copy_command, cur_env, mut cmd) = match env::consts::FAMILY {
-        "unix" => {
-            if env::var("WSL_DISTRO_NAME").is_ok() {
-                (
-                    "clip.exe",
-                    "WSL",
-                    Command::new("/mnt/c/Windows/System32/clip.exe"),
-                )
-            } else if env::var("WAYLAND_DISPLAY").is_ok() {
-                ("wl-copy", "Wayland", Command::new("/usr/bin/wl-copy"))
-            } else if env::var("DISPLAY").is_ok() {
-                ("xclip", "Xorg", {
-                    let mut cmd = Command::new("/usr/bin/xclip");
-                    cmd.args(["-sel", "clip", "-r", "-in"]);
-                    cmd
-                })
-            } else if cfg!(target_os = "macos") {
-                ("pbcopy", "macOS", Command::new("/usr/bin/pbcopy"))
-            } else {
-                return Err(anyhow!("Unsupported Unix environment"));
-            }
-        }
-        _ => return Err(anyhow!("Unsupported operating system")),
-    };
+use std::fs;
+#[cfg(feature = "async")]
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process;
+#[cfg(feature = "async")]
+use std::time::{Duration, SystemTime};
 
-    info!(
-        "Running under {}, using copy command {}",
-        cur_env, copy_command
-    );
-
-    let mut child = cmd.stdin(Stdio::piped()).spawn()?;
-    let mut child_stdin = child
-        .stdin
-        .take()
-        .ok_or_else(|| anyhow!("Failed to open stdin"))?;
-    // io::copy(&mut Cursor::new(content.as_bytes()), child_stdin)?;
-    child_stdin.write_all(content.as_bytes())?;
-    child_stdin.flush()?;
-    drop(child_stdin);
-    child.wait()?;
-
-    Ok(())
-}
+/// How often the `--config` file is checked for changes.
+#[cfg(feature = "async")]
+const RELOAD_POLL: Duration = Duration::from_secs(2);
 
-#[tokio::main]
-async fn main() {
-    let dev = env::var("DEV").is_ok();
-    if dev {
-        env::set_var("RUST_LOG", "debug");
-    }
-    if env::var("RUST_LOG").is_err() {
-        env::set_var("RUST_LOG", "info");
-    }
+fn init(vars: &Vars) -> Config {
+    let filter = match vars.var("DEV") {
+        Ok(_) => "debug".to_string(),
+        Err(_) => vars.var("RUST_LOG").unwrap_or("info".to_string()),
+    };
     #[cfg(not(target_os = "macos"))]
-    pretty_env_logger::init();
+    pretty_env_logger::formatted_builder()
+        .parse_filters(&filter)
+        .init();
 
     #[cfg(target_os = "macos")]
     OsLogger::new("ntfyclip")
@@ -89,85 +38,403 @@ async fn main() {
         .install_default()
         .unwrap();
 
-    loop {
-        match connect_and_run().await {
-            Ok(()) => println!("Connection closed cleanly"),
+    match Config::from_vars(vars) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("{}", e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Logs the clipboard backends found at startup. They are probed again on
+/// every write; this only reports what was found.
+fn report_clipboard(config: &Config) {
+    if config.copyq.is_some() {
+        info!("clipboard: CopyQ");
+        return;
+    }
+    match ntfy2clip::clipboard_backend() {
+        Ok(backend) => info!("clipboard: {}", backend),
+        Err(e) => warn!("clipboard: {}", e),
+    }
+}
+
+/// `n2c doctor`: reports what the client would do in this environment.
+fn doctor() -> ! {
+    println!("n2c {}", env!("CARGO_PKG_VERSION"));
+    let sandbox = Sandbox::detect();
+    match &sandbox {
+        Sandbox::None => println!("sandbox: none"),
+        Sandbox::Flatpak { app_id } => println!("sandbox: Flatpak ({})", app_id),
+        Sandbox::Snap { name } => println!("sandbox: Snap ({})", name),
+    }
+    for restriction in sandbox.restrictions() {
+        println!("  - {}", restriction);
+    }
+    if sandbox.is_sandboxed() && !cfg!(feature = "portal") {
+        println!("  - built without the `portal` feature; the clipboard will likely fail");
+    }
+    let mut ok = true;
+    match ntfy2clip::clipboard_backend() {
+        Ok(backend) => println!("clipboard: {}", backend),
+        Err(e) => {
+            ok = false;
+            println!("clipboard: {}", e);
+        }
+    }
+    match Config::from_env() {
+        Ok(config) => println!("config: ok, topic {} on {}", config.topic, config.server),
+        Err(e) => {
+            ok = false;
+            println!("config: {}", e);
+        }
+    }
+    process::exit(if ok { 0 } else { 1 })
+}
+
+/// Flags are settings named like their environment variable, which they take
+/// precedence over. Every setting has one, see [`parse_args`].
+#[derive(clap::Parser)]
+#[command(name = "n2c", version, about = "Copies ntfy messages to the clipboard")]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Subcommand>,
+    /// ntfy server, ntfy.sh by default
+    #[arg(long, global = true, env = "SERVER")]
+    server: Option<String>,
+    /// wss, or ws for servers without TLS
+    #[arg(long, global = true, env = "SCHEME")]
+    scheme: Option<String>,
+    /// Topic to subscribe to
+    #[arg(long, global = true, env = "TOPIC")]
+    topic: Option<String>,
+    /// Access token
+    #[arg(long, global = true, env = "TOKEN", hide_env_values = true)]
+    token: Option<String>,
+    /// File to read the access token from, again on every reconnect
+    #[arg(long, global = true, env = "TOKEN_FILE", value_name = "PATH")]
+    token_file: Option<PathBuf>,
+    /// Command printing the access token, run on every reconnect
+    #[arg(long, global = true, env = "TOKEN_CMD", value_name = "COMMAND")]
+    token_cmd: Option<String>,
+    /// User for HTTP Basic auth, instead of a token
+    #[arg(long, global = true, env = "NTFY_USER")]
+    user: Option<String>,
+    /// Password for HTTP Basic auth
+    #[arg(long, global = true, env = "NTFY_PASS", hide_env_values = true)]
+    password: Option<String>,
+    /// Seconds without traffic before reconnecting
+    #[arg(long, global = true, env = "TIMEOUT")]
+    timeout: Option<u64>,
+    /// error, warn, info (default), debug or trace; a RUST_LOG filter
+    #[arg(long, global = true, env = "RUST_LOG")]
+    log_level: Option<String>,
+    /// Any setting from the README, as NAME=VALUE; each also has a flag,
+    /// e.g. --clip-template for CLIP_TEMPLATE
+    #[arg(short = 'e', long = "env", global = true, value_name = "NAME=VALUE")]
+    vars: Vec<String>,
+    /// File of NAME=VALUE settings, reloaded on SIGHUP or when it changes
+    #[arg(long, global = true, env = "CONFIG_FILE", value_name = "PATH")]
+    config: Option<PathBuf>,
+    /// The settings given with the flags added by [`parse_args`].
+    #[arg(skip)]
+    settings: Vec<(String, String)>,
+}
+
+/// Parses the arguments, with a hidden `--<name>` flag for every setting
+/// without a flag of its own, e.g. `--clip-template` for `CLIP_TEMPLATE`.
+fn parse_args() -> Args {
+    let mut command = Args::command();
+    for name in ntfy2clip::SETTINGS {
+        let long = name.to_lowercase().replace('_', "-");
+        if command
+            .get_arguments()
+            .any(|arg| arg.get_long() == Some(long.as_str()))
+        {
+            continue;
+        }
+        command = command.arg(
+            Arg::new(*name)
+                .long(long)
+                .global(true)
+                .value_name("VALUE")
+                .hide(true),
+        );
+    }
+    let matches = command.get_matches();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let subcommand = matches.subcommand().map(|(_, matches)| matches);
+    for name in ntfy2clip::SETTINGS {
+        let value = subcommand
+            .and_then(|matches| matches.try_get_one::<String>(name).ok().flatten())
+            .or_else(|| matches.try_get_one::<String>(name).ok().flatten());
+        if let Some(value) = value {
+            args.settings.push((name.to_string(), value.clone()));
+        }
+    }
+    args
+}
+
+#[derive(clap::Subcommand)]
+enum Subcommand {
+    /// Report what the client would do in this environment
+    Doctor,
+    /// Publish TEXT, or stdin, to the topic
+    Send { text: Vec<String> },
+    /// Manage the access token kept in the OS keyring
+    #[cfg(feature = "keyring")]
+    Auth {
+        #[command(subcommand)]
+        command: AuthCommand,
+    },
+}
+
+#[cfg(feature = "keyring")]
+#[derive(clap::Subcommand)]
+enum AuthCommand {
+    /// Store the token read from stdin for the server
+    Set,
+}
+
+/// `n2c auth`: the token is used when `TOKEN` isn't set.
+#[cfg(feature = "keyring")]
+fn auth(command: AuthCommand, vars: &Vars) -> ! {
+    use std::io::{BufRead, IsTerminal};
+    match command {
+        AuthCommand::Set => {
+            let server = vars
+                .var("SERVER")
+                .unwrap_or_else(|_| Config::new("").server);
+            if std::io::stdin().is_terminal() {
+                eprint!("Token for {}: ", server);
+            }
+            let mut token = String::new();
+            if let Err(e) = std::io::stdin().lock().read_line(&mut token) {
+                eprintln!("Failed to read the token: {}", e);
+                process::exit(1);
+            }
+            match ntfy2clip::store_token(&server, token.trim()) {
+                Ok(()) => println!("stored the token for {}", server),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                }
+            }
+        }
+    }
+    process::exit(0)
+}
+
+impl Args {
+    /// The settings from the flags, over the environment, over the
+    /// `--config` file.
+    fn vars(&self) -> Result<Vars, String> {
+        let mut vars = Vars::new();
+        if let Some(path) = &self.config {
+            for (name, value) in read_env_file(path)? {
+                vars.set_default(name, value);
+            }
+        }
+        let flags = [
+            ("SERVER", self.server.clone()),
+            ("SCHEME", self.scheme.clone()),
+            ("TOPIC", self.topic.clone()),
+            ("TOKEN", self.token.clone()),
+            (
+                "TOKEN_FILE",
+                self.token_file
+                    .as_ref()
+                    .map(|path| path.to_string_lossy().into_owned()),
+            ),
+            ("TOKEN_CMD", self.token_cmd.clone()),
+            ("NTFY_USER", self.user.clone()),
+            ("NTFY_PASS", self.password.clone()),
+            ("TIMEOUT", self.timeout.map(|t| t.to_string())),
+            ("RUST_LOG", self.log_level.clone()),
+        ];
+        for (name, value) in flags {
+            if let Some(value) = value {
+                vars.set(name, value);
+            }
+        }
+        for (name, value) in &self.settings {
+            vars.set(name, value);
+        }
+        for var in &self.vars {
+            let Some((name, value)) = var.split_once('=') else {
+                return Err(format!("--env takes NAME=VALUE, not {}", var));
+            };
+            vars.set(name, value);
+        }
+        Ok(vars)
+    }
+
+    /// Like [`vars`](Self::vars), exiting on errors, and installed for the
+    /// settings read outside of [`Config`].
+    fn vars_or_exit(&self) -> Vars {
+        match self.vars() {
+            Ok(vars) => {
+                vars.clone().install();
+                vars
+            }
             Err(e) => {
-                error!("Connection error: {:?}. Reconnecting...", e);
-                // Optionally add a delay before reconnecting
-                tokio::time::sleep(Duration::from_secs(5)).await;
+                eprintln!("{}", e);
+                process::exit(2);
             }
         }
     }
 }
 
-async fn connect_and_run() -> Result<()> {
-    let timeout = env::var("TIMEOUT")
-        .unwrap_or("120".to_string())
-        .parse::<u64>()
-        .unwrap();
-    let server = env::var("SERVER").unwrap_or("ntfy.sh".to_string());
-    let scheme = env::var("SCHEME").unwrap_or("wss".to_string());
-    let topic = env::var("TOPIC").expect("You must subscribe to a topic.");
-    let url =
-        Url::parse(format!("{}://{}/{}/ws", scheme, server, topic).as_str()).expect("Invalid URL");
-    let token = env::var("TOKEN").unwrap_or("".to_string());
-    let mut request = url.into_client_request().unwrap();
-    if !token.is_empty() {
-        request
-            .headers_mut()
-            .insert("Authorization", format!("Bearer {token}").parse().unwrap());
-    }
-
-    debug!("request: {:?}", &request);
-    let (mut ws_stream, _) = connect_async(request).await?;
-    info!("connected to {server} with topic={topic} and timeout={timeout}");
-
-    let mut ping_interval = time::interval(Duration::from_secs(timeout));
-    let mut last_traffic = Instant::now();
-
-    loop {
-        tokio::select! {
-            Some(msg) = ws_stream.next() => {
-                last_traffic = Instant::now();
-                match msg {
-                    Ok(Message::Text(text)) => {
-                        match serde_json::from_str::<WSMessage>(&text) {
-                            Ok(msg) => {
-                                if (msg.topic == topic) && (msg.event == "message") {
-                                    debug!("WS received message: {:?}", &msg);
-                                    if let Some(message) = msg.message {
-                                        spawn(set_clip(message));
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                error!("Error in WebSocket connection: {}", e);
-                            }
-                        }
-                    }
-                    Ok(Message::Ping(ping)) => {
-                        ws_stream.send(Message::Pong(ping)).await?;
-                        debug!("WS received ping and sent pong");
-                    }
-                    Ok(Message::Pong(_)) => {
-                        debug!("WS received pong");
-                    }
-                    Ok(Message::Close(_)) => {
-                        debug!("WS received close message");
-                        return Ok(());
+/// The variables of a `NAME=VALUE` file.
+fn read_env_file(path: &Path) -> Result<Vec<(String, String)>, String> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let mut vars = Vec::new();
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let Some((name, value)) = line.split_once('=') else {
+            return Err(format!(
+                "{}: expected NAME=VALUE, not {}",
+                path.display(),
+                line
+            ));
+        };
+        let value = value.trim();
+        let value = ['"', '\'']
+            .iter()
+            .find_map(|q| value.strip_prefix(*q)?.strip_suffix(*q))
+            .unwrap_or(value);
+        vars.push((name.trim().to_string(), value.to_string()));
+    }
+    Ok(vars)
+}
+
+/// Rebuilds the configuration on SIGHUP or when the `--config` file changes,
+/// and hands it to the running supervisor.
+#[cfg(feature = "async")]
+async fn reload_on_change(args: Args, handle: SupervisorHandle) {
+    let (tx, mut rx) = tokio::sync::mpsc::channel(1);
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let tx = tx.clone();
+        match signal(SignalKind::hangup()) {
+            Ok(mut hangup) => {
+                tokio::spawn(async move {
+                    while hangup.recv().await.is_some() {
+                        info!("SIGHUP received, reloading the configuration");
+                        let _ = tx.try_send(());
                     }
-                    Err(e) => {
-                        return Err(anyhow!(e.to_string()));
+                });
+            }
+            Err(e) => warn!("Failed to handle SIGHUP: {}", e),
+        }
+    }
+    if let Some(path) = args.config.clone() {
+        tokio::spawn(async move {
+            let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+            let mut last: Option<SystemTime> = modified(&path);
+            let mut poll = tokio::time::interval(RELOAD_POLL);
+            loop {
+                poll.tick().await;
+                let now = modified(&path);
+                if now != last {
+                    last = now;
+                    info!("{} changed, reloading the configuration", path.display());
+                    if tx.send(()).await.is_err() {
+                        return;
                     }
-                    _ => {}
-                }
-            },
-            _ = ping_interval.tick() => {
-                if last_traffic.elapsed() > Duration::from_secs(timeout) {
-                    return Err(anyhow!("No traffic in the last 120 seconds".to_string()));
                 }
             }
+        });
+    }
+    while rx.recv().await.is_some() {
+        let vars = match args.vars() {
+            Ok(vars) => vars,
+            Err(e) => {
+                error!("{}; keeping the current configuration", e);
+                continue;
+            }
+        };
+        match Config::from_vars(&vars) {
+            Ok(config) => {
+                vars.install();
+                handle.reload(config);
+            }
+            Err(e) => error!("{}; keeping the current configuration", e),
         }
     }
 }
+
+/// The text for `n2c send`: its arguments, or stdin without any.
+#[cfg(feature = "async")]
+fn send_text(text: Vec<String>) -> String {
+    if !text.is_empty() {
+        return text.join(" ");
+    }
+    let mut text = String::new();
+    if let Err(e) = io::stdin().read_to_string(&mut text) {
+        error!("Failed to read stdin: {}", e);
+        process::exit(1);
+    }
+    text
+}
+
+#[cfg(feature = "async")]
+#[tokio::main]
+async fn main() {
+    let mut args = parse_args();
+    let vars = args.vars_or_exit();
+    match args.command.take() {
+        Some(Subcommand::Doctor) => doctor(),
+        Some(Subcommand::Send { text }) => {
+            let config = init(&vars);
+            if let Err(e) = ntfy2clip::publish(&config, &send_text(text)).await {
+                error!("{}", e);
+                process::exit(1);
+            }
+        }
+        #[cfg(feature = "keyring")]
+        Some(Subcommand::Auth { command }) => auth(command, &vars),
+        None => {
+            let config = init(&vars);
+            report_clipboard(&config);
+            let (supervisor, handle) = Supervisor::new(config, EventBus::default());
+            tokio::spawn(reload_on_change(args, handle));
+            if let Err(e) = supervisor.run().await {
+                error!("{}. Giving up.", e);
+                process::exit(1);
+            }
+        }
+    }
+}
+
+#[cfg(all(feature = "blocking", not(feature = "async")))]
+fn main() {
+    let args = parse_args();
+    let vars = args.vars_or_exit();
+    match args.command {
+        Some(Subcommand::Doctor) => doctor(),
+        Some(Subcommand::Send { .. }) => {
+            eprintln!("n2c send needs the `async` feature");
+            process::exit(1);
+        }
+        #[cfg(feature = "keyring")]
+        Some(Subcommand::Auth { command }) => auth(command, &vars),
+        None => {
+            let config = init(&vars);
+            report_clipboard(&config);
+            if let Err(e) = ntfy2clip::blocking::run(&config) {
+                error!("{}. Giving up.", e);
+                process::exit(1);
+            }
+        }
+    }
+}
+
+#[cfg(not(any(feature = "async", feature = "blocking")))]
+compile_error!("n2c needs either the `async` or the `blocking` feature");
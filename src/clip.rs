@@ -0,0 +1,857 @@
+use crate::config;
+use crate::error::ClipboardError;
+use crate::session;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use log::{debug, info, warn};
+use std::env;
+use std::fs::OpenOptions;
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+#[cfg(feature = "async")]
+use std::sync::Arc;
+use std::sync::Mutex;
+
+#[cfg(feature = "async")]
+pub async fn set_clip(content: String) -> Result<(), ClipboardError> {
+    write(content.into()).await
+}
+
+/// [`set_clip`] with shared `content`, which the writers that keep it, like
+/// the portal or the in-process ones, take without copying.
+#[cfg(feature = "async")]
+pub(crate) async fn write(content: Arc<str>) -> Result<(), ClipboardError> {
+    mark_written(&content);
+    #[cfg(feature = "portal")]
+    if crate::portal::enabled() {
+        info!(
+            "Setting clipboard through the desktop portal to: {}",
+            content
+        );
+        // The portal keeps the content to answer later paste requests.
+        return crate::portal::set_clip(content).await;
+    }
+    info!("Setting clipboard to: {}", content);
+
+    match write_backends(&content).await {
+        Err(e)
+            if tokio::task::spawn_blocking(session::refresh)
+                .await
+                .unwrap_or(false) =>
+        {
+            info!(
+                "Clipboard write failed ({}), retrying in the new session",
+                e
+            );
+            write_backends(&content).await
+        }
+        result => result,
+    }
+}
+
+#[cfg(feature = "async")]
+async fn write_backends(content: &Arc<str>) -> Result<(), ClipboardError> {
+    for chain in commands()? {
+        let mut first_error = None;
+        for (copy_command, cur_env, writer) in chain {
+            info!(
+                "Running under {}, using copy command {}",
+                cur_env, copy_command
+            );
+            match write_with(copy_command, writer, content).await {
+                Ok(()) => {
+                    first_error = None;
+                    break;
+                }
+                Err(e) => {
+                    warn!("{} failed: {}", copy_command, e);
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        if let Some(e) = first_error {
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "async")]
+async fn write_with(
+    copy_command: &'static str,
+    writer: Writer,
+    content: &Arc<str>,
+) -> Result<(), ClipboardError> {
+    match writer {
+        Writer::Command(cmd) => run_async(copy_command, cmd, content).await?,
+        #[cfg(feature = "klipper")]
+        Writer::Klipper => crate::klipper::set_clip(content).await?,
+        // The in-process writers block until the content is handed over, so
+        // they run on their own thread, which a stalled one can be left on.
+        writer => {
+            let gone = || ClipboardError::Write {
+                command: copy_command,
+                source: std::io::Error::other("the clipboard thread is gone"),
+            };
+            let (reply, result) = tokio::sync::oneshot::channel();
+            let job = (copy_command, writer, content.clone(), reply);
+            if native_thread().send(job).is_err() {
+                NATIVE.lock().unwrap().take();
+                return Err(gone());
+            }
+            result.await.map_err(|_| gone())??
+        }
+    }
+    Ok(())
+}
+
+/// A write for the in-process writers' thread, answered on the channel.
+#[cfg(feature = "async")]
+type Job = (
+    &'static str,
+    Writer,
+    Arc<str>,
+    tokio::sync::oneshot::Sender<Result<(), ClipboardError>>,
+);
+
+/// The thread running the in-process writers. They keep state between
+/// writes on it, like the X11 selection owner, which goes with the thread
+/// when [`abandon`] leaves it behind.
+#[cfg(feature = "async")]
+static NATIVE: Mutex<Option<std::sync::mpsc::Sender<Job>>> = Mutex::new(None);
+
+#[cfg(feature = "async")]
+fn native_thread() -> std::sync::mpsc::Sender<Job> {
+    NATIVE
+        .lock()
+        .unwrap()
+        .get_or_insert_with(|| {
+            let (tx, rx) = std::sync::mpsc::channel::<Job>();
+            std::thread::spawn(move || {
+                for (copy_command, writer, content, reply) in rx {
+                    let _ = reply.send(write_with_blocking(copy_command, writer, &content));
+                }
+            });
+            tx
+        })
+        .clone()
+}
+
+/// Leaves a wedged in-process writer to itself: the next write starts over
+/// on a new thread, with new connections. Commands are killed by dropping
+/// the write instead.
+#[cfg(feature = "async")]
+pub(crate) fn abandon() {
+    if NATIVE.lock().unwrap().take().is_some() {
+        warn!("Left the stalled clipboard thread behind, starting over");
+    }
+}
+
+/// The content last written, so reading it back isn't taken for a change.
+#[cfg(feature = "async")]
+static WRITTEN: Mutex<Option<String>> = Mutex::new(None);
+
+#[cfg(feature = "async")]
+pub(crate) fn mark_written(content: &str) {
+    *WRITTEN.lock().unwrap() = Some(content.to_string());
+}
+
+/// Whether `content` is what was written last, rather than copied locally.
+/// Only answers `true` once, so copying the same content again later counts.
+#[cfg(feature = "async")]
+pub(crate) fn take_written(content: &str) -> bool {
+    let mut written = WRITTEN.lock().unwrap();
+    let own = written.as_deref() == Some(content);
+    if own {
+        *written = None;
+    }
+    own
+}
+
+/// Reads the clipboard as text; `None` if it is empty or holds something
+/// else, like an image.
+#[cfg(feature = "async")]
+pub(crate) async fn read() -> Result<Option<String>, ClipboardError> {
+    #[cfg(windows)]
+    return Ok(clipboard_win::get_clipboard_string().ok());
+    #[cfg(not(windows))]
+    {
+        let (paste_command, cmd) = reader()?;
+        let output = tokio::process::Command::from(cmd)
+            .envs(session::overrides())
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .output()
+            .await
+            .map_err(|source| ClipboardError::Spawn {
+                command: paste_command,
+                source,
+            })?;
+        // Most fail when there is no selection, or none in a text format.
+        if !output.status.success() {
+            return Ok(None);
+        }
+        let Ok(mut content) = String::from_utf8(output.stdout) else {
+            return Ok(None);
+        };
+        if paste_command == "powershell.exe" {
+            // Windows line endings, and a trailing one.
+            content = content.trim_end_matches("\r\n").replace("\r\n", "\n");
+        }
+        Ok(Some(content).filter(|c| !c.is_empty()))
+    }
+}
+
+/// The command printing the clipboard, like [`detect`] for writing.
+#[cfg(all(feature = "async", not(windows)))]
+fn reader() -> Result<(&'static str, Command), ClipboardError> {
+    if let Some(prefix) = termux_prefix() {
+        let cmd = Command::new(Path::new(&prefix).join("bin/termux-clipboard-get"));
+        return Ok(("termux-clipboard-get", cmd));
+    }
+    if env::var("WSL_DISTRO_NAME").is_ok() {
+        let mut cmd = Command::new(binary(
+            "powershell.exe",
+            "/mnt/c/Windows/System32/WindowsPowerShell/v1.0/powershell.exe",
+        ));
+        cmd.args(["-NoProfile", "-Command", "Get-Clipboard"]);
+        return Ok(("powershell.exe", cmd));
+    }
+    if session::var("WAYLAND_DISPLAY").is_some() {
+        let mut cmd = Command::new(binary("wl-paste", "/usr/bin/wl-paste"));
+        cmd.args(["--no-newline", "--type", "text"]);
+        return Ok(("wl-paste", cmd));
+    }
+    if session::var("DISPLAY").is_some() {
+        let xclip = binary("xclip", "/usr/bin/xclip");
+        let xsel = binary("xsel", "/usr/bin/xsel");
+        if !xclip.exists() && xsel.exists() {
+            let mut cmd = Command::new(xsel);
+            cmd.args(["--clipboard", "--output"]);
+            return Ok(("xsel", cmd));
+        }
+        let mut cmd = Command::new(xclip);
+        cmd.args(["-sel", "clip", "-t", "UTF8_STRING", "-o"]);
+        return Ok(("xclip", cmd));
+    }
+    if cfg!(target_os = "macos") {
+        return Ok((
+            "pbpaste",
+            Command::new(binary("pbpaste", "/usr/bin/pbpaste")),
+        ));
+    }
+    Err(ClipboardError::Unsupported(
+        "reading the clipboard without WSL, Wayland, X11 or macOS",
+    ))
+}
+
+/// Describes the backend `set_clip` would use here, e.g. `xclip (Xorg)`.
+pub fn clipboard_backend() -> Result<String, ClipboardError> {
+    #[cfg(all(feature = "async", feature = "portal"))]
+    if crate::portal::enabled() {
+        return Ok("desktop portal".to_string());
+    }
+    let chains: Vec<_> = commands()?
+        .into_iter()
+        .map(|chain| {
+            let backends: Vec<_> = chain
+                .into_iter()
+                .map(|(copy_command, cur_env, writer)| match writer {
+                    Writer::Command(cmd) => format!(
+                        "{} ({}, {})",
+                        copy_command,
+                        cur_env,
+                        Path::new(cmd.get_program()).display()
+                    ),
+                    _ => format!("{} ({})", copy_command, cur_env),
+                })
+                .collect();
+            backends.join(" or ")
+        })
+        .collect();
+    Ok(chains.join(" + "))
+}
+
+const TERMUX_PREFIX: &str = "/data/data/com.termux/files/usr";
+/// The controlling terminal.
+const TTY: &str = "/dev/tty";
+/// Only present inside the Crostini container.
+const CROS_MILESTONE: &str = "/dev/.cros_milestone";
+
+/// Binaries found by [`binary`]. Those not found are looked for again next
+/// time, so installing one later is noticed.
+static BINARIES: Mutex<Vec<(&'static str, PathBuf)>> = Mutex::new(Vec::new());
+
+/// Where to run `name` from: `<NAME>_PATH` if set, e.g. `XCLIP_PATH`, then the
+/// first match in `PATH`, for NixOS, Homebrew and the like, then `default`.
+fn binary(name: &'static str, default: &str) -> PathBuf {
+    let var = format!("{}_PATH", name.to_uppercase().replace(['-', '.'], "_"));
+    if let Some(path) = config::var(&var).ok().filter(|p| !p.is_empty()) {
+        return PathBuf::from(path);
+    }
+    let mut binaries = BINARIES.lock().unwrap();
+    if let Some((_, found)) = binaries.iter().find(|(binary, _)| *binary == name) {
+        return found.clone();
+    }
+    let found = env::var_os("PATH").and_then(|path| {
+        env::split_paths(&path)
+            .map(|dir| dir.join(name))
+            .find(|candidate| candidate.is_file())
+    });
+    match found {
+        Some(path) => {
+            info!("found {} at {}", name, path.display());
+            binaries.push((name, path.clone()));
+            path
+        }
+        None => {
+            debug!("{} not found in PATH, trying {}", name, default);
+            PathBuf::from(default)
+        }
+    }
+}
+
+/// How a backend writes the clipboard.
+enum Writer {
+    /// A command reading the content from stdin.
+    Command(Command),
+    #[cfg(feature = "x11")]
+    X11(Selection),
+    #[cfg(windows)]
+    Windows,
+    #[cfg(all(feature = "macos-native", target_os = "macos"))]
+    Pasteboard,
+    #[cfg(feature = "arboard")]
+    Arboard(Selection),
+    #[cfg(feature = "data-control")]
+    DataControl(Selection),
+    /// An OSC 52 escape sequence written to the controlling terminal.
+    Terminal,
+    #[cfg(feature = "klipper")]
+    Klipper,
+}
+
+/// The X11 and Wayland selection a backend writes.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Selection {
+    Clipboard,
+    /// Pasted with a middle click.
+    Primary,
+}
+
+type Backend = (&'static str, &'static str, Writer);
+
+/// Alternatives for one target, tried in order until one succeeds.
+type Chain = Vec<Backend>;
+
+fn commands() -> Result<Vec<Chain>, ClipboardError> {
+    let chains = detect().map(|mut chains| {
+        if config::var("COPY_PRIMARY").is_ok_and(|v| v == "1") {
+            chains.extend(primary());
+        }
+        chains.into_iter().map(probe).collect::<Vec<_>>()
+    });
+    let tmux = match config::var("TMUX_BUFFER").as_deref() {
+        Ok("1") => true,
+        Ok("0") => false,
+        // Inside tmux without a graphical session, as on a server.
+        _ => {
+            env::var_os("TMUX").is_some()
+                && chains.as_ref().map_or(true, |chains| {
+                    chains
+                        .iter()
+                        .all(|chain| chain.first().is_some_and(|(name, _, _)| *name == "osc52"))
+                })
+        }
+    };
+    if !tmux {
+        return chains;
+    }
+    let mut cmd = Command::new(binary("tmux", "tmux"));
+    cmd.args(["load-buffer", "-"]);
+    let mut chains = chains.unwrap_or_default();
+    chains.push(vec![("tmux", "tmux", Writer::Command(cmd))]);
+    Ok(chains)
+}
+
+/// Drops the alternatives whose command isn't installed, unless that leaves
+/// none, so the write still fails with a useful error.
+fn probe(chain: Chain) -> Chain {
+    let installed = |writer: &Writer| match writer {
+        Writer::Command(cmd) => {
+            let program = Path::new(cmd.get_program());
+            !program.is_absolute() || program.exists()
+        }
+        _ => true,
+    };
+    if !chain.iter().any(|(_, _, writer)| installed(writer)) {
+        return chain;
+    }
+    chain
+        .into_iter()
+        .filter(|(_, _, writer)| installed(writer))
+        .collect()
+}
+
+/// The system clipboard's backends.
+fn detect() -> Result<Vec<Chain>, ClipboardError> {
+    match env::consts::FAMILY {
+        "unix" => {
+            let mut chains = if let Some(prefix) = termux_prefix() {
+                vec![vec![termux(&prefix)]]
+            } else if env::var("WSL_DISTRO_NAME").is_ok() {
+                wsl()?
+            } else if Path::new(CROS_MILESTONE).exists() {
+                vec![vec![crostini()]]
+            } else if let Some(chain) = linux() {
+                vec![chain]
+            } else if cfg!(target_os = "macos") {
+                vec![vec![macos()]]
+            } else {
+                Vec::new()
+            };
+            match chains.first_mut() {
+                Some(chain) => chain.extend(fallbacks()),
+                None => chains.push(fallbacks()),
+            }
+            if chains[0].is_empty() {
+                return Err(ClipboardError::Unsupported(
+                    "Unix without WSL, Wayland, X11 or a terminal",
+                ));
+            }
+            Ok(chains)
+        }
+        #[cfg(windows)]
+        "windows" => {
+            let mut chain = vec![("win32", "Windows", Writer::Windows)];
+            chain.extend(custom());
+            Ok(vec![chain])
+        }
+        _ => Err(ClipboardError::Unsupported(env::consts::OS)),
+    }
+}
+
+/// What's left when the system clipboard can't be written: the terminal,
+/// e.g. over SSH, where the terminal emulator can still set the clipboard
+/// on the user's machine, and then `CLIP_COMMAND`.
+fn fallbacks() -> Chain {
+    let mut chain = Vec::new();
+    if OpenOptions::new().write(true).open(TTY).is_ok() {
+        chain.push(("osc52", "terminal", Writer::Terminal));
+    }
+    chain.extend(custom());
+    chain
+}
+
+/// `CLIP_COMMAND`, run by the shell with the content on stdin.
+fn custom() -> Option<Backend> {
+    let command = config::var("CLIP_COMMAND").ok().filter(|c| !c.is_empty())?;
+    let mut cmd = Command::new(if cfg!(windows) { "cmd" } else { "/bin/sh" });
+    cmd.args([if cfg!(windows) { "/C" } else { "-c" }, &command]);
+    Some(("CLIP_COMMAND", "custom", Writer::Command(cmd)))
+}
+
+/// With WSLg the Linux side has its own Wayland clipboard, which WSLg only
+/// partially syncs with Windows. `WSL_CLIPBOARD` picks `windows` (default),
+/// `linux` or `both`.
+fn wsl() -> Result<Vec<Chain>, ClipboardError> {
+    let windows = || {
+        (
+            "clip.exe",
+            "WSL",
+            Writer::Command(Command::new(binary(
+                "clip.exe",
+                "/mnt/c/Windows/System32/clip.exe",
+            ))),
+        )
+    };
+    let linux = || {
+        linux().ok_or(ClipboardError::Unsupported(
+            "WSL without WSLg, Wayland or X11",
+        ))
+    };
+    match config::var("WSL_CLIPBOARD").as_deref() {
+        Ok("windows" | "") | Err(_) => Ok(vec![vec![windows()]]),
+        Ok("linux") => Ok(vec![linux()?]),
+        Ok("both") => Ok(vec![vec![windows()], linux()?]),
+        Ok(_) => Err(ClipboardError::Unsupported(
+            "WSL_CLIPBOARD other than windows, linux or both",
+        )),
+    }
+}
+
+/// Termux's root, `$PREFIX`, when running inside it.
+fn termux_prefix() -> Option<String> {
+    let prefix = env::var("PREFIX").ok();
+    if env::var_os("TERMUX_VERSION").is_some() {
+        return Some(prefix.unwrap_or_else(|| TERMUX_PREFIX.to_string()));
+    }
+    prefix.filter(|p| p.contains("/com.termux/"))
+}
+
+/// Termux has no `/usr/bin`; its binaries live under `$PREFIX`. Writing the
+/// Android clipboard needs the Termux:API app and `pkg install termux-api`.
+fn termux(prefix: &str) -> Backend {
+    let cmd = Command::new(Path::new(prefix).join("bin/termux-clipboard-set"));
+    ("termux-clipboard-set", "Termux", Writer::Command(cmd))
+}
+
+/// Crostini forwards the clipboard to ChromeOS through sommelier, whose X11
+/// side loses selections once `xclip` exits. Its Wayland socket is reliable,
+/// and always there, even for services started without `WAYLAND_DISPLAY`.
+fn crostini() -> Backend {
+    let mut cmd = Command::new(binary("wl-copy", "/usr/bin/wl-copy"));
+    cmd.args(["--type", "text/plain;charset=utf-8"]);
+    if session::var("WAYLAND_DISPLAY").is_none() {
+        cmd.env("WAYLAND_DISPLAY", "wayland-0");
+    }
+    #[cfg(unix)]
+    if session::var("XDG_RUNTIME_DIR").is_none() {
+        if let Ok(meta) = std::fs::metadata("/proc/self") {
+            cmd.env("XDG_RUNTIME_DIR", format!("/run/user/{}", meta.uid()));
+        }
+    }
+    ("wl-copy", "Crostini", Writer::Command(cmd))
+}
+
+/// The session's backends, preferred first: Wayland's, then X11's, which
+/// also work through XWayland.
+fn linux() -> Option<Chain> {
+    let mut chain = Vec::new();
+    #[cfg(feature = "klipper")]
+    if crate::klipper::detected() {
+        chain.push(("klipper", "KDE", Writer::Klipper));
+    }
+    chain.extend(session_chain(Selection::Clipboard));
+    (!chain.is_empty()).then_some(chain)
+}
+
+/// With `COPY_PRIMARY=1`, the session's PRIMARY selection is set as well.
+fn primary() -> Option<Chain> {
+    let chain = session_chain(Selection::Primary);
+    (!chain.is_empty()).then_some(chain)
+}
+
+fn session_chain(selection: Selection) -> Chain {
+    let mut chain = Vec::new();
+    if session::var("WAYLAND_DISPLAY").is_some() {
+        chain.extend(wayland(selection));
+    }
+    if session::var("DISPLAY").is_some() {
+        chain.extend(xorg(selection));
+    }
+    chain
+}
+
+fn wayland(selection: Selection) -> Chain {
+    let mut wl_copy = Command::new(binary("wl-copy", "/usr/bin/wl-copy"));
+    if selection == Selection::Primary {
+        wl_copy.arg("--primary");
+    }
+    let env = match selection {
+        Selection::Clipboard => "Wayland",
+        Selection::Primary => "Wayland PRIMARY",
+    };
+    #[allow(unused_mut)]
+    let mut chain = vec![("wl-copy", env, Writer::Command(wl_copy))];
+    #[cfg(feature = "data-control")]
+    chain.insert(0, ("data-control", env, Writer::DataControl(selection)));
+    #[cfg(all(feature = "arboard", not(feature = "data-control")))]
+    chain.insert(0, ("arboard", env, Writer::Arboard(selection)));
+    chain
+}
+
+fn xorg(selection: Selection) -> Chain {
+    let (env, xclip_selection, xsel_selection) = match selection {
+        Selection::Clipboard => ("Xorg", "clip", "--clipboard"),
+        Selection::Primary => ("Xorg PRIMARY", "primary", "--primary"),
+    };
+    let mut chain = Vec::new();
+    #[cfg(feature = "x11")]
+    chain.push(("x11", env, Writer::X11(selection)));
+    #[cfg(all(feature = "arboard", not(feature = "x11")))]
+    chain.push(("arboard", env, Writer::Arboard(selection)));
+    let mut xclip = Command::new(binary("xclip", "/usr/bin/xclip"));
+    xclip.args(["-sel", xclip_selection, "-r", "-in"]);
+    chain.push(("xclip", env, Writer::Command(xclip)));
+    let mut xsel = Command::new(binary("xsel", "/usr/bin/xsel"));
+    xsel.args([xsel_selection, "--input"]);
+    chain.push(("xsel", env, Writer::Command(xsel)));
+    chain
+}
+
+#[cfg(all(feature = "macos-native", target_os = "macos"))]
+fn macos() -> Backend {
+    ("NSPasteboard", "macOS", Writer::Pasteboard)
+}
+
+#[cfg(all(
+    feature = "arboard",
+    not(all(feature = "macos-native", target_os = "macos"))
+))]
+fn macos() -> Backend {
+    ("arboard", "macOS", Writer::Arboard(Selection::Clipboard))
+}
+
+#[cfg(not(any(
+    feature = "arboard",
+    all(feature = "macos-native", target_os = "macos")
+)))]
+fn macos() -> Backend {
+    (
+        "pbcopy",
+        "macOS",
+        Writer::Command(Command::new(binary("pbcopy", "/usr/bin/pbcopy"))),
+    )
+}
+
+/// Writes the general pasteboard in-process, which also works where
+/// spawning `pbcopy` doesn't, like in sandboxed apps.
+#[cfg(all(feature = "macos-native", target_os = "macos"))]
+fn set_pasteboard(content: &str) -> Result<(), ClipboardError> {
+    use objc2_app_kit::{NSPasteboard, NSPasteboardTypeString};
+    use objc2_foundation::NSString;
+
+    let pasteboard = NSPasteboard::generalPasteboard();
+    pasteboard.clearContents();
+    // SAFETY: `NSPasteboardTypeString` is a constant AppKit initializes.
+    let string_type = unsafe { NSPasteboardTypeString };
+    if !pasteboard.setString_forType(&NSString::from_str(content), string_type) {
+        return Err(ClipboardError::Write {
+            command: "NSPasteboard",
+            source: std::io::Error::other("the pasteboard refused the string"),
+        });
+    }
+    Ok(())
+}
+
+/// The OSC 52 escape sequence that has a terminal set its clipboard to
+/// `content`, supported by kitty, alacritty, iTerm2, WezTerm and others.
+pub(crate) fn osc52(content: &str) -> String {
+    format!("\x1b]52;c;{}\x07", STANDARD.encode(content))
+}
+
+/// Writes [`osc52`] to the controlling terminal. tmux only forwards it to the
+/// terminal it runs in when wrapped in a passthrough sequence, and with
+/// `allow-passthrough` on.
+fn set_terminal(content: &str) -> Result<(), ClipboardError> {
+    let sequence = osc52(content);
+    let sequence = if env::var_os("TMUX").is_some() {
+        format!("\x1bPtmux;{}\x1b\\", sequence.replace('\x1b', "\x1b\x1b"))
+    } else {
+        sequence
+    };
+    let write_err = |source| ClipboardError::Write {
+        command: "osc52",
+        source,
+    };
+    let mut tty = OpenOptions::new()
+        .write(true)
+        .open(TTY)
+        .map_err(write_err)?;
+    tty.write_all(sequence.as_bytes()).map_err(write_err)?;
+    tty.flush().map_err(write_err)
+}
+
+/// Writes through the `arboard` crate. On X11 the content is served by the
+/// `Clipboard` as long as it lives, so it is kept for later writes too.
+#[cfg(feature = "arboard")]
+fn set_arboard(content: &str, selection: Selection) -> Result<(), ClipboardError> {
+    // Per thread, so a writer left behind by [`abandon`] takes it along.
+    thread_local! {
+        static CLIPBOARD: std::cell::RefCell<Option<arboard::Clipboard>> =
+            const { std::cell::RefCell::new(None) };
+    }
+
+    // Dropped on failure, like the `x11` owner, so the next write starts over.
+    let mut current = match CLIPBOARD.take() {
+        Some(current) => current,
+        None => arboard::Clipboard::new()?,
+    };
+    match selection {
+        Selection::Clipboard => current.set_text(content)?,
+        #[cfg(all(unix, not(any(target_os = "macos", target_os = "android"))))]
+        Selection::Primary => {
+            use arboard::{LinuxClipboardKind, SetExtLinux};
+            current
+                .set()
+                .clipboard(LinuxClipboardKind::Primary)
+                .text(content)?
+        }
+        #[cfg(not(all(unix, not(any(target_os = "macos", target_os = "android")))))]
+        Selection::Primary => return Err(ClipboardError::Unsupported("PRIMARY outside Linux")),
+    }
+    CLIPBOARD.set(Some(current));
+    Ok(())
+}
+
+/// Offers the content through the wlr/ext data-control protocol, served from
+/// a background thread until something else is copied. Unlike a regular
+/// client this needs no focused window, so it also works on the lock screen.
+#[cfg(feature = "data-control")]
+fn set_data_control(content: &str, selection: Selection) -> Result<(), ClipboardError> {
+    use wl_clipboard_rs::copy::{ClipboardType, MimeType, Options, Source};
+
+    let mut options = Options::new();
+    if selection == Selection::Primary {
+        options.clipboard(ClipboardType::Primary);
+    }
+    options.copy(Source::Bytes(content.as_bytes().into()), MimeType::Text)?;
+    Ok(())
+}
+
+/// Sets the clipboard directly as `CF_UNICODETEXT`. Unlike `clip.exe`, which
+/// mangles UTF-8 and appends a newline, this keeps the content as it is.
+#[cfg(windows)]
+fn set_windows(content: &str) -> Result<(), ClipboardError> {
+    clipboard_win::set_clipboard_string(content).map_err(|e| ClipboardError::Write {
+        command: "win32",
+        source: std::io::Error::from_raw_os_error(e.raw_code()),
+    })
+}
+
+pub fn set_clip_blocking(content: &str) -> Result<(), ClipboardError> {
+    info!("Setting clipboard to: {}", content);
+
+    match write_backends_blocking(content) {
+        Err(e) if session::refresh() => {
+            info!(
+                "Clipboard write failed ({}), retrying in the new session",
+                e
+            );
+            write_backends_blocking(content)
+        }
+        result => result,
+    }
+}
+
+fn write_backends_blocking(content: &str) -> Result<(), ClipboardError> {
+    for chain in commands()? {
+        let mut first_error = None;
+        for (copy_command, cur_env, writer) in chain {
+            info!(
+                "Running under {}, using copy command {}",
+                cur_env, copy_command
+            );
+            match write_with_blocking(copy_command, writer, content) {
+                Ok(()) => {
+                    first_error = None;
+                    break;
+                }
+                Err(e) => {
+                    warn!("{} failed: {}", copy_command, e);
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        if let Some(e) = first_error {
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+fn write_with_blocking(
+    copy_command: &'static str,
+    writer: Writer,
+    content: &str,
+) -> Result<(), ClipboardError> {
+    match writer {
+        Writer::Command(cmd) => run(copy_command, cmd, content)?,
+        #[cfg(feature = "x11")]
+        Writer::X11(selection) => crate::x11::set_clip(content, selection)?,
+        #[cfg(windows)]
+        Writer::Windows => set_windows(content)?,
+        #[cfg(all(feature = "macos-native", target_os = "macos"))]
+        Writer::Pasteboard => set_pasteboard(content)?,
+        #[cfg(feature = "arboard")]
+        Writer::Arboard(selection) => set_arboard(content, selection)?,
+        #[cfg(feature = "data-control")]
+        Writer::DataControl(selection) => set_data_control(content, selection)?,
+        Writer::Terminal => set_terminal(content)?,
+        #[cfg(feature = "klipper")]
+        Writer::Klipper => return Err(ClipboardError::Unsupported("Klipper from blocking code")),
+    }
+    Ok(())
+}
+
+fn run(copy_command: &'static str, mut cmd: Command, content: &str) -> Result<(), ClipboardError> {
+    let mut child = cmd
+        .envs(session::overrides())
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|source| ClipboardError::Spawn {
+            command: copy_command,
+            source,
+        })?;
+    let mut child_stdin = child.stdin.take().ok_or(ClipboardError::Write {
+        command: copy_command,
+        source: std::io::Error::other("failed to open stdin"),
+    })?;
+    let write_err = |source| ClipboardError::Write {
+        command: copy_command,
+        source,
+    };
+    child_stdin
+        .write_all(content.as_bytes())
+        .map_err(write_err)?;
+    child_stdin.flush().map_err(write_err)?;
+    drop(child_stdin);
+    let status = child.wait().map_err(write_err)?;
+    if !status.success() {
+        return Err(ClipboardError::Exit {
+            command: copy_command,
+            status,
+        });
+    }
+
+    Ok(())
+}
+
+/// Like [`run`], but kills the command if the future is dropped, so a write
+/// superseded by newer content can be abandoned midway.
+#[cfg(feature = "async")]
+pub(crate) async fn run_async(
+    copy_command: &'static str,
+    cmd: Command,
+    content: &str,
+) -> Result<(), ClipboardError> {
+    use tokio::io::AsyncWriteExt;
+
+    let mut child = tokio::process::Command::from(cmd)
+        .envs(session::overrides())
+        .stdin(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|source| ClipboardError::Spawn {
+            command: copy_command,
+            source,
+        })?;
+    let mut child_stdin = child.stdin.take().ok_or(ClipboardError::Write {
+        command: copy_command,
+        source: std::io::Error::other("failed to open stdin"),
+    })?;
+    let write_err = |source| ClipboardError::Write {
+        command: copy_command,
+        source,
+    };
+    child_stdin
+        .write_all(content.as_bytes())
+        .await
+        .map_err(write_err)?;
+    child_stdin.flush().await.map_err(write_err)?;
+    drop(child_stdin);
+    let status = child.wait().await.map_err(write_err)?;
+    if !status.success() {
+        return Err(ClipboardError::Exit {
+            command: copy_command,
+            status,
+        });
+    }
+
+    Ok(())
+}
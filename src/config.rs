@@ -0,0 +1,1659 @@
+#[cfg(feature = "async")]
+use crate::channel::OverflowPolicy;
+#[cfg(feature = "hickory")]
+use crate::dns::DnsConfig;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::error::AuthError;
+use crate::error::ConfigError;
+#[cfg(feature = "imap")]
+use crate::imap::ImapConfig;
+#[cfg(feature = "kdeconnect")]
+use crate::kdeconnect::KdeConnectConfig;
+#[cfg(feature = "mdns")]
+use crate::lan::LanConfig;
+#[cfg(feature = "mqtt")]
+use crate::mqtt::MqttConfig;
+#[cfg(feature = "neovim")]
+use crate::neovim::NeovimConfig;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use regex::Regex;
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+#[cfg(not(target_arch = "wasm32"))]
+use std::process::{self, Stdio};
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use url::Url;
+
+/// Local HTTP API accepting `POST /clip`, see [`Config::api`].
+#[derive(Debug, Clone)]
+pub struct ApiConfig {
+    pub addr: SocketAddr,
+    pub token: String,
+}
+
+/// LAN hub re-serving received messages to other instances, see [`Config::hub`].
+#[derive(Debug, Clone)]
+pub struct HubConfig {
+    pub addr: SocketAddr,
+    pub token: Option<String>,
+}
+
+/// Directory attachments are saved into, see [`Config::attachments`].
+#[derive(Debug, Clone)]
+pub struct AttachmentConfig {
+    pub dir: PathBuf,
+    /// Maximum total size of the directory in bytes.
+    pub quota: Option<u64>,
+}
+
+/// UnifiedPush distributor served on the HTTP API, see [`Config::unifiedpush`].
+#[derive(Debug, Clone)]
+pub struct UnifiedPushConfig {
+    /// File the app registrations are kept in.
+    pub state: PathBuf,
+}
+
+/// Extra Server-Sent Events source, see [`Config::sse`].
+#[derive(Debug, Clone)]
+pub struct SseConfig {
+    pub url: Url,
+    /// Where the message is inside each event's JSON data.
+    pub field: Option<JsonPath>,
+    pub token: Option<String>,
+}
+
+/// Remote hosts received content is forwarded to, see [`Config::ssh`].
+#[derive(Debug, Clone)]
+pub struct SshConfig {
+    /// `ssh` destinations, anything from `~/.ssh/config` works.
+    pub hosts: Vec<String>,
+    /// Remote command the content is piped into; `None` writes an OSC 52
+    /// sequence to the user's terminals on the host instead.
+    pub command: Option<String>,
+}
+
+/// File every received message is appended to, see [`Config::file`].
+#[derive(Debug, Clone)]
+pub struct FileConfig {
+    pub path: PathBuf,
+    /// What is appended for each message; a line break is added if it
+    /// doesn't end with one.
+    pub format: Template,
+    /// Messages are still copied to the clipboard too.
+    pub clipboard: bool,
+}
+
+/// Emacs daemon received content is pushed onto the kill ring of, see
+/// [`Config::emacs`].
+#[derive(Debug, Clone)]
+pub struct EmacsConfig {
+    /// Server socket name or path passed to `emacsclient -s`.
+    pub socket: Option<String>,
+    /// Only messages of these topics are used; `None` means all of them.
+    pub topics: Option<Vec<String>>,
+}
+
+/// CopyQ session received content is added to instead of only being set as
+/// the clipboard, see [`Config::copyq`].
+#[derive(Debug, Clone)]
+pub struct CopyqConfig {
+    /// Tab the items go into; CopyQ's clipboard tab if `None`.
+    pub tab: Option<String>,
+}
+
+/// Copying only the part of each message that matches, see
+/// [`Config::extract`].
+#[derive(Debug, Clone)]
+pub struct Extract {
+    /// What is copied: the first capture group, or the whole match if there
+    /// is none.
+    pub pattern: Regex,
+    /// Copy the whole message when nothing matches, instead of dropping it.
+    pub fallback_all: bool,
+}
+
+/// Which titles messages are accepted with, see [`Config::title_match`].
+#[derive(Debug, Clone)]
+pub struct TitleMatch {
+    pub pattern: Regex,
+    /// What `strip` removes from the title: a glob's text up to its first
+    /// wildcard, or a regex's match.
+    pub prefix: Regex,
+    /// Removes the prefix from the title, e.g. for [`Config::template`].
+    pub strip: bool,
+}
+
+impl FromStr for TitleMatch {
+    type Err = ();
+
+    /// Parses a glob with `*` and `?` matching the whole title, or a regex
+    /// between slashes, `/^clip:/`, matching any part of it.
+    fn from_str(s: &str) -> Result<Self, ()> {
+        if let Some(regex) = s.strip_prefix('/').and_then(|r| r.strip_suffix('/')) {
+            let pattern = Regex::new(regex).map_err(|_| ())?;
+            return Ok(TitleMatch {
+                prefix: pattern.clone(),
+                pattern,
+                strip: false,
+            });
+        }
+        let glob = |glob: &str| {
+            glob.chars()
+                .map(|c| match c {
+                    '*' => ".*".to_string(),
+                    '?' => ".".to_string(),
+                    c => regex::escape(c.encode_utf8(&mut [0; 4])),
+                })
+                .collect::<String>()
+        };
+        let literal = &s[..s.find(['*', '?']).unwrap_or(s.len())];
+        Ok(TitleMatch {
+            pattern: Regex::new(&format!("^{}$", glob(s))).map_err(|_| ())?,
+            prefix: Regex::new(&format!("^{}", regex::escape(literal))).map_err(|_| ())?,
+            strip: false,
+        })
+    }
+}
+
+/// Where in a JSON message the part to copy is, see [`Config::extract_json`]:
+/// jq-style `.data.url`, `.items[0]` or `.["odd key"]`, and `.` for all of it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonPath(pub(crate) Vec<JsonStep>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum JsonStep {
+    Key(String),
+    Index(usize),
+}
+
+impl FromStr for JsonPath {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        let mut rest = s.trim().strip_prefix('.').ok_or(())?;
+        let mut steps = Vec::new();
+        while !rest.is_empty() {
+            if let Some(inner) = rest.strip_prefix('[') {
+                let (index, after) = match inner.strip_prefix('"') {
+                    Some(quoted) => {
+                        let (key, after) = quoted.split_once("\"]").ok_or(())?;
+                        steps.push(JsonStep::Key(key.to_string()));
+                        ("", after)
+                    }
+                    None => inner.split_once(']').ok_or(())?,
+                };
+                if !index.is_empty() {
+                    steps.push(JsonStep::Index(index.parse().map_err(|_| ())?));
+                }
+                rest = after.strip_prefix('.').unwrap_or(after);
+                continue;
+            }
+            let end = rest.find(['.', '[']).unwrap_or(rest.len());
+            if end == 0 {
+                return Err(());
+            }
+            steps.push(JsonStep::Key(rest[..end].to_string()));
+            rest = &rest[end..];
+            rest = rest.strip_prefix('.').unwrap_or(rest);
+        }
+        Ok(JsonPath(steps))
+    }
+}
+
+impl JsonPath {
+    /// The text at this path in `json`: strings as they are, other values as
+    /// JSON, and `None` where there is nothing or `null`.
+    pub(crate) fn text(&self, json: &serde_json::Value) -> Option<String> {
+        let value = self.0.iter().try_fold(json, |value, step| match step {
+            JsonStep::Key(key) => value.get(key),
+            JsonStep::Index(index) => value.get(index),
+        })?;
+        match value {
+            serde_json::Value::String(s) => Some(s.clone()),
+            serde_json::Value::Null => None,
+            other => Some(other.to_string()),
+        }
+    }
+}
+
+/// End-to-end encryption of messages, see [`Config::e2e`].
+#[cfg(feature = "e2e")]
+#[derive(Clone)]
+pub struct E2eConfig {
+    /// Derived from the passphrase.
+    pub(crate) key: [u8; 32],
+    /// Unencrypted messages are passed through instead of dropped.
+    pub accept_plaintext: bool,
+}
+
+#[cfg(feature = "e2e")]
+impl std::fmt::Debug for E2eConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("E2eConfig")
+            .field("accept_plaintext", &self.accept_plaintext)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Authentication of messages, see [`Config::hmac`].
+#[cfg(feature = "hmac")]
+#[derive(Clone)]
+pub struct HmacConfig {
+    pub(crate) secret: String,
+}
+
+#[cfg(feature = "hmac")]
+impl std::fmt::Debug for HmacConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HmacConfig").finish_non_exhaustive()
+    }
+}
+
+/// What happens to messages over [`Config::max_size`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OversizePolicy {
+    /// Dropped.
+    #[default]
+    Reject,
+    /// Cut off at the limit.
+    Truncate,
+    /// Cut off, ending with a note on how much is missing, within the limit.
+    TruncateWithMarker,
+}
+
+/// Layout of the text the sinks get, see [`Config::template`]: literal text
+/// and `{field}` placeholders, with `{{` and `}}` for literal braces.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template(pub(crate) Vec<TemplatePart>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum TemplatePart {
+    Text(String),
+    Field(TemplateField),
+}
+
+/// Message fields a [`Template`] can refer to; missing ones are empty.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TemplateField {
+    Id,
+    Topic,
+    Title,
+    Message,
+    /// Comma-separated.
+    Tags,
+    /// Unix seconds.
+    Time,
+    /// RFC 3339 in UTC, like `2024-05-01T12:00:00Z`.
+    Date,
+    /// 1 to 5, 3 if the message has none.
+    Priority,
+    Click,
+}
+
+impl FromStr for Template {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut rest = s;
+        while let Some(i) = rest.find(['{', '}']) {
+            text.push_str(&rest[..i]);
+            let (brace, after) = rest[i..].split_at(1);
+            if let Some(after) = after.strip_prefix(brace) {
+                text.push_str(brace);
+                rest = after;
+                continue;
+            }
+            if brace == "}" {
+                return Err(());
+            }
+            let (name, after) = after.split_once('}').ok_or(())?;
+            let field = match name {
+                "id" => TemplateField::Id,
+                "topic" => TemplateField::Topic,
+                "title" => TemplateField::Title,
+                "message" => TemplateField::Message,
+                "tags" => TemplateField::Tags,
+                "time" => TemplateField::Time,
+                "date" => TemplateField::Date,
+                "priority" => TemplateField::Priority,
+                "click" => TemplateField::Click,
+                _ => return Err(()),
+            };
+            if !text.is_empty() {
+                parts.push(TemplatePart::Text(std::mem::take(&mut text)));
+            }
+            parts.push(TemplatePart::Field(field));
+            rest = after;
+        }
+        text.push_str(rest);
+        if !text.is_empty() {
+            parts.push(TemplatePart::Text(text));
+        }
+        Ok(Template(parts))
+    }
+}
+
+/// Publishing local clipboard changes to the topic, see [`Config::watch`].
+#[derive(Debug, Clone)]
+pub struct WatchConfig {
+    /// How often the clipboard is read.
+    pub interval: Duration,
+}
+
+/// Where messages of a topic go, see [`Config::routes`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Route {
+    Clipboard,
+    /// Appended to the file, one message per line.
+    File(PathBuf),
+    /// Opened in the default browser, if the message is an http(s) URL.
+    Open,
+}
+
+impl FromStr for Route {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "clipboard" => Ok(Route::Clipboard),
+            "open" => Ok(Route::Open),
+            _ => match s.strip_prefix("file:") {
+                Some(path) if !path.is_empty() => Ok(Route::File(PathBuf::from(path))),
+                _ => Err(()),
+            },
+        }
+    }
+}
+
+/// Where the token is read from again before every connection, see
+/// [`Config::token_source`].
+#[derive(Debug, Clone)]
+pub enum TokenSource {
+    File(PathBuf),
+    /// Shell command printing the token.
+    Command(String),
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl TokenSource {
+    pub(crate) fn read(&self) -> Result<String, AuthError> {
+        let token = match self {
+            TokenSource::File(path) => {
+                fs::read_to_string(path).map_err(|source| AuthError::TokenFile {
+                    path: path.clone(),
+                    source,
+                })?
+            }
+            TokenSource::Command(command) => {
+                let output =
+                    shell(command)
+                        .stderr(Stdio::inherit())
+                        .output()
+                        .map_err(|source| AuthError::TokenSpawn {
+                            command: command.clone(),
+                            source,
+                        })?;
+                if !output.status.success() {
+                    return Err(AuthError::TokenExit {
+                        command: command.clone(),
+                        status: output.status,
+                    });
+                }
+                String::from_utf8_lossy(&output.stdout).into_owned()
+            }
+        };
+        Ok(token.trim().to_string())
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn shell(command: &str) -> process::Command {
+    #[cfg(windows)]
+    let (shell, flag) = ("cmd", "/C");
+    #[cfg(not(windows))]
+    let (shell, flag) = ("/bin/sh", "-c");
+    let mut cmd = process::Command::new(shell);
+    cmd.args([flag, command]);
+    cmd
+}
+
+/// How the connection to the server is opened, see [`Config::socket`].
+#[derive(Debug, Clone, Default)]
+pub struct SocketConfig {
+    /// Local address connections are made from; only servers of the same
+    /// address family are tried.
+    pub bind_addr: Option<IpAddr>,
+    /// Network interface connections are made through (Linux only).
+    pub interface: Option<String>,
+    /// Disables Nagle's algorithm.
+    pub nodelay: bool,
+    /// Idle time before the OS starts sending TCP keepalive probes.
+    pub keepalive: Option<Duration>,
+    /// Time between keepalive probes.
+    pub keepalive_interval: Option<Duration>,
+    /// Unanswered keepalive probes before the connection is dropped.
+    pub keepalive_retries: Option<u32>,
+    /// Limit on each connection attempt, instead of the OS's.
+    pub connect_timeout: Option<Duration>,
+    /// Proxy connections are tunneled through.
+    pub proxy: Option<Proxy>,
+    /// Address families the server is connected over.
+    pub ip_family: IpFamily,
+    /// Addresses used for hosts instead of resolving them, like curl's
+    /// `--resolve`.
+    pub resolve: Vec<Resolve>,
+}
+
+/// Which of the server's addresses are tried, see [`SocketConfig::ip_family`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IpFamily {
+    /// Both, starting with the resolver's first choice.
+    #[default]
+    Any,
+    /// Both, starting with IPv4, for networks whose IPv6 is broken.
+    PreferV4,
+    PreferV6,
+    OnlyV4,
+    OnlyV6,
+}
+
+impl FromStr for IpFamily {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "any" => Ok(IpFamily::Any),
+            "prefer-4" => Ok(IpFamily::PreferV4),
+            "prefer-6" => Ok(IpFamily::PreferV6),
+            "4" => Ok(IpFamily::OnlyV4),
+            "6" => Ok(IpFamily::OnlyV6),
+            _ => Err(()),
+        }
+    }
+}
+
+/// A static address for connections to `host:port`, see
+/// [`SocketConfig::resolve`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Resolve {
+    pub host: String,
+    pub port: u16,
+    pub ip: IpAddr,
+}
+
+impl FromStr for Resolve {
+    type Err = ();
+
+    /// Parses `host:port:ip`, with IPv6 addresses optionally in brackets.
+    fn from_str(s: &str) -> Result<Self, ()> {
+        let mut parts = s.splitn(3, ':');
+        let (Some(host), Some(port), Some(ip)) = (parts.next(), parts.next(), parts.next()) else {
+            return Err(());
+        };
+        if host.is_empty() {
+            return Err(());
+        }
+        Ok(Resolve {
+            host: host.to_ascii_lowercase(),
+            port: port.parse().map_err(|_| ())?,
+            ip: ip
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .parse()
+                .map_err(|_| ())?,
+        })
+    }
+}
+
+/// TLS settings for connections to the server, see [`Config::tls`].
+#[derive(Debug, Clone, Default)]
+pub struct TlsConfig {
+    /// PEM file with certificates trusted in addition to the system's, for
+    /// servers with an internal CA.
+    pub ca_file: Option<PathBuf>,
+    /// Certificate presented to servers that ask for one.
+    pub client_cert: Option<ClientCert>,
+    /// SHA-256 hashes of the server keys (SubjectPublicKeyInfo) accepted;
+    /// any other is refused even with a valid certificate.
+    pub pins: Vec<[u8; 32]>,
+    /// Accepts any certificate, for development servers with a self-signed
+    /// one; pins are still checked.
+    pub insecure: bool,
+}
+
+/// PEM files of a TLS client certificate, see [`TlsConfig::client_cert`].
+#[derive(Debug, Clone)]
+pub struct ClientCert {
+    /// The certificate, optionally followed by intermediates.
+    pub cert: PathBuf,
+    /// Its PKCS#8 private key; may be the same file as `cert`.
+    pub key: PathBuf,
+}
+
+/// A proxy connections are tunneled through, see [`SocketConfig::proxy`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Proxy {
+    pub kind: ProxyKind,
+    pub host: String,
+    pub port: u16,
+    /// Username and password, if the proxy wants them.
+    pub auth: Option<(String, String)>,
+    /// Servers reached directly instead, from `NO_PROXY`: host names that
+    /// also cover their subdomains, or `*` for all.
+    pub bypass: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyKind {
+    /// E.g. `ssh -D`.
+    Socks5,
+    /// An HTTP proxy, asked for a tunnel with `CONNECT`.
+    Http,
+}
+
+impl Proxy {
+    /// Whether connections to `host` go through the proxy; this machine is
+    /// always connected to directly.
+    pub fn applies_to(&self, host: &str) -> bool {
+        let host = host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_ascii_lowercase();
+        let loopback =
+            host == "localhost" || host.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback());
+        !loopback
+            && !self.bypass.iter().any(|excluded| {
+                let excluded = excluded.trim_start_matches('.').to_ascii_lowercase();
+                excluded == "*" || host == excluded || host.ends_with(&format!(".{}", excluded))
+            })
+    }
+}
+
+impl FromStr for Proxy {
+    type Err = ();
+
+    /// Parses `socks5://[user:pass@]host[:port]`, with the port defaulting
+    /// to 1080, or `http://[user:pass@]host[:port]`, where the scheme may be
+    /// left out like in most `HTTPS_PROXY` settings.
+    fn from_str(s: &str) -> Result<Self, ()> {
+        let url = match s.contains("://") {
+            true => Url::parse(s),
+            false => Url::parse(&format!("http://{}", s)),
+        }
+        .map_err(|_| ())?;
+        let (kind, default_port) = match url.scheme() {
+            "socks5" | "socks5h" => (ProxyKind::Socks5, 1080),
+            "http" => (ProxyKind::Http, 80),
+            _ => return Err(()),
+        };
+        let host = url.host_str().filter(|h| !h.is_empty()).ok_or(())?;
+        let auth = (!url.username().is_empty()).then(|| {
+            (
+                url.username().to_string(),
+                url.password().unwrap_or_default().to_string(),
+            )
+        });
+        Ok(Proxy {
+            kind,
+            host: host.to_string(),
+            port: url.port().unwrap_or(default_port),
+            auth,
+            bypass: Vec::new(),
+        })
+    }
+}
+
+/// The kind of server [`Config::server`] points at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ServerKind {
+    #[default]
+    Ntfy,
+    /// Gotify's `/stream` endpoint, authenticated with a client token.
+    Gotify,
+}
+
+/// How the subscription is carried, see [`Config::transport`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Transport {
+    /// WebSockets, switching to SSE after repeated failures.
+    #[default]
+    Auto,
+    WebSocket,
+    /// ntfy's Server-Sent Events endpoint, for networks that block
+    /// WebSockets.
+    Sse,
+    /// ntfy's line-delimited JSON endpoint, plain chunked HTTP.
+    Json,
+    /// Asking the JSON endpoint for new messages every
+    /// [`Config::poll_interval`] instead of keeping a connection open.
+    Poll,
+}
+
+/// Which of the messages sent while disconnected are delivered on
+/// reconnecting, see [`Config::catch_up`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CatchUp {
+    /// Only the newest, the only one that would stay on the clipboard.
+    #[default]
+    Latest,
+    All,
+    Off,
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub kind: ServerKind,
+    pub server: String,
+    pub scheme: String,
+    pub topic: String,
+    /// Further ntfy topics subscribed to on the same connection; `topic` is
+    /// the one published to.
+    pub topics: Vec<String>,
+    pub token: Option<String>,
+    /// Takes the place of `token` when set.
+    pub token_source: Option<TokenSource>,
+    /// HTTP Basic credentials, used when there is no token.
+    pub user: Option<String>,
+    pub password: Option<String>,
+    /// Sends the credentials in ntfy's `auth` query parameter instead of a
+    /// header when subscribing.
+    pub auth_query: bool,
+    pub timeout: Duration,
+    /// Derives the idle timeout from the server's keepalive cadence once it
+    /// is known, instead of always using `timeout`.
+    pub adaptive_timeout: bool,
+    pub transport: Transport,
+    pub poll_interval: Duration,
+    pub catch_up: CatchUp,
+    /// This machine's name; messages tagged `dev:<name>` for other devices
+    /// are ignored.
+    pub device: String,
+    /// Only messages with at least one of these tags are accepted, if any.
+    pub tags_any: Vec<String>,
+    /// Messages with any of these tags are ignored.
+    pub tags_none: Vec<String>,
+    /// Messages of a lower ntfy priority, 1 to 5, are ignored.
+    pub min_priority: Option<u8>,
+    /// Only messages with a matching title are accepted; untitled ones count
+    /// as titled with an empty string.
+    pub title_match: Option<TitleMatch>,
+    /// Messages sent longer ago than this are ignored, against replays and
+    /// old secrets from a catch-up.
+    pub replay_window: Option<Duration>,
+    /// Topics whose messages are rendered from markdown to plain text, `*`
+    /// for all of them.
+    pub markdown: Vec<String>,
+    /// ANSI escape sequences, e.g. the colors of CI logs, are removed.
+    pub strip_ansi: bool,
+    /// Control characters other than tabs and line breaks are removed, with
+    /// ANSI escape sequences.
+    pub strip_control: bool,
+    /// Topic a small acknowledgement is published to after each clipboard
+    /// write, so senders can tell it arrived.
+    pub ack_topic: Option<String>,
+    /// Message ids remembered to drop messages delivered twice.
+    pub dedup_size: usize,
+    /// File the remembered ids are kept in across restarts.
+    pub dedup_state: Option<PathBuf>,
+    /// Topics handled by something other than the clipboard; the rest go to
+    /// the clipboard.
+    pub routes: Vec<(String, Route)>,
+    /// Every message body is base64 encoded, not only those tagged `b64`.
+    pub base64: bool,
+    /// Pattern messages are cut down to before reaching the sinks.
+    pub extract: Option<Extract>,
+    /// Field of JSON messages they are cut down to, before `extract`;
+    /// messages that aren't JSON or lack it are dropped.
+    pub extract_json: Option<JsonPath>,
+    /// What the sinks get instead of the bare message, applied after
+    /// `extract`.
+    pub template: Option<Template>,
+    /// Longer texts are published in pieces of this many bytes, which the
+    /// subscribers put back together.
+    pub chunk_size: Option<usize>,
+    /// Largest text in bytes the sinks get, after the other transforms.
+    pub max_size: Option<usize>,
+    pub oversize: OversizePolicy,
+    /// Server a second connection is kept open to as a warm standby; may be
+    /// `server` itself, for a spare connection.
+    pub standby: Option<(ServerKind, String)>,
+    /// Servers tried in turn after `server` when connecting fails, then
+    /// `server` again.
+    pub fallback_servers: Vec<(ServerKind, String)>,
+    pub socket: SocketConfig,
+    pub tls: TlsConfig,
+    #[cfg(feature = "hickory")]
+    pub dns: Option<DnsConfig>,
+    /// What happens to clipboard writes that arrive faster than they finish.
+    #[cfg(feature = "async")]
+    pub clipboard_overflow: OverflowPolicy,
+    pub api: Option<ApiConfig>,
+    pub hub: Option<HubConfig>,
+    pub attachments: Option<AttachmentConfig>,
+    pub sse: Option<SseConfig>,
+    pub unifiedpush: Option<UnifiedPushConfig>,
+    pub ssh: Option<SshConfig>,
+    pub file: Option<FileConfig>,
+    pub emacs: Option<EmacsConfig>,
+    pub copyq: Option<CopyqConfig>,
+    pub watch: Option<WatchConfig>,
+    #[cfg(feature = "imap")]
+    pub imap: Option<ImapConfig>,
+    #[cfg(feature = "kdeconnect")]
+    pub kdeconnect: Option<KdeConnectConfig>,
+    #[cfg(feature = "mqtt")]
+    pub mqtt: Option<MqttConfig>,
+    /// Messages are encrypted when published and decrypted when received.
+    #[cfg(feature = "e2e")]
+    pub e2e: Option<E2eConfig>,
+    /// Messages are signed when published, and dropped when received
+    /// without a valid signature.
+    #[cfg(feature = "hmac")]
+    pub hmac: Option<HmacConfig>,
+    #[cfg(feature = "neovim")]
+    pub neovim: Option<NeovimConfig>,
+    #[cfg(feature = "mdns")]
+    pub lan: Option<LanConfig>,
+}
+
+impl Config {
+    pub fn new(topic: impl Into<String>) -> Self {
+        Config {
+            kind: ServerKind::Ntfy,
+            server: "ntfy.sh".to_string(),
+            scheme: "wss".to_string(),
+            topic: topic.into(),
+            topics: Vec::new(),
+            token: None,
+            token_source: None,
+            user: None,
+            password: None,
+            auth_query: false,
+            device: String::new(),
+            tags_any: Vec::new(),
+            tags_none: Vec::new(),
+            min_priority: None,
+            title_match: None,
+            replay_window: None,
+            markdown: Vec::new(),
+            strip_ansi: false,
+            strip_control: false,
+            ack_topic: None,
+            dedup_size: 64,
+            dedup_state: None,
+            routes: Vec::new(),
+            base64: false,
+            extract: None,
+            extract_json: None,
+            template: None,
+            chunk_size: None,
+            max_size: None,
+            oversize: OversizePolicy::Reject,
+            timeout: Duration::from_secs(120),
+            adaptive_timeout: false,
+            transport: Transport::Auto,
+            poll_interval: Duration::from_secs(60),
+            catch_up: CatchUp::Latest,
+            standby: None,
+            fallback_servers: Vec::new(),
+            socket: SocketConfig::default(),
+            tls: TlsConfig::default(),
+            #[cfg(feature = "hickory")]
+            dns: None,
+            // Only the newest clipboard content matters, so never stall the
+            // pipeline on it.
+            #[cfg(feature = "async")]
+            clipboard_overflow: OverflowPolicy::Latest,
+            api: None,
+            hub: None,
+            attachments: None,
+            sse: None,
+            unifiedpush: None,
+            ssh: None,
+            file: None,
+            emacs: None,
+            copyq: None,
+            watch: None,
+            #[cfg(feature = "imap")]
+            imap: None,
+            #[cfg(feature = "kdeconnect")]
+            kdeconnect: None,
+            #[cfg(feature = "mqtt")]
+            mqtt: None,
+            #[cfg(feature = "e2e")]
+            e2e: None,
+            #[cfg(feature = "hmac")]
+            hmac: None,
+            #[cfg(feature = "neovim")]
+            neovim: None,
+            #[cfg(feature = "mdns")]
+            lan: None,
+        }
+    }
+
+    /// The configuration from the environment, or the [`Vars`] installed.
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Self::from_vars(&Vars::installed())
+    }
+
+    /// The configuration from `vars`, named like the environment variables
+    /// in the README.
+    pub fn from_vars(vars: &Vars) -> Result<Self, ConfigError> {
+        let timeout = vars.var("TIMEOUT").unwrap_or("120".to_string());
+        let timeout = timeout
+            .parse::<u64>()
+            .map_err(|_| ConfigError::InvalidValue {
+                name: "TIMEOUT",
+                value: timeout,
+            })?;
+        let default_kind = match vars.var("SERVER_TYPE") {
+            Ok(kind) => match kind.as_str() {
+                "ntfy" => ServerKind::Ntfy,
+                "gotify" => ServerKind::Gotify,
+                _ => {
+                    return Err(ConfigError::InvalidValue {
+                        name: "SERVER_TYPE",
+                        value: kind,
+                    })
+                }
+            },
+            Err(_) => ServerKind::Ntfy,
+        };
+        // A `gotify://` or `ntfy://` prefix overrides `SERVER_TYPE`.
+        let entry = |entry: &str| match entry.split_once("://") {
+            Some(("gotify", server)) => (ServerKind::Gotify, server.to_string()),
+            Some(("ntfy", server)) => (ServerKind::Ntfy, server.to_string()),
+            _ => (default_kind, entry.to_string()),
+        };
+        let mut servers: Vec<_> = vars.list("SERVERS").iter().map(|s| entry(s)).collect();
+        if servers.is_empty() {
+            if let Ok(server) = vars.var("SERVER") {
+                servers.push(entry(&server));
+            }
+        }
+        let standby = vars
+            .var("STANDBY_SERVER")
+            .ok()
+            .filter(|s| !s.is_empty())
+            .map(|s| entry(&s));
+        let kind = servers.first().map_or(default_kind, |(kind, _)| *kind);
+
+        // Gotify has no topics; the name only labels its messages.
+        let ntfy = servers
+            .iter()
+            .chain(&standby)
+            .map(|(kind, _)| *kind)
+            .chain([kind])
+            .any(|kind| kind == ServerKind::Ntfy);
+        let topic = match vars.var("TOPIC") {
+            Ok(topic) => topic,
+            Err(_) if !ntfy => "gotify".to_string(),
+            Err(_) => return Err(ConfigError::MissingTopic),
+        };
+
+        // A comma-separated list subscribes to each of them.
+        let mut topics = topic
+            .split(',')
+            .map(str::trim)
+            .filter(|t| !t.is_empty())
+            .map(str::to_string);
+        let mut config = Config::new(topics.next().ok_or(ConfigError::MissingTopic)?);
+        config.topics = topics.collect();
+        config.kind = kind;
+        if !servers.is_empty() {
+            config.server = servers.remove(0).1;
+            config.fallback_servers = servers;
+        }
+        config.standby = standby;
+        if let Ok(scheme) = vars.var("SCHEME") {
+            config.scheme = scheme;
+        }
+        config.token = vars.var("TOKEN").ok().filter(|t| !t.is_empty());
+        config.token_source = match (vars.var("TOKEN_CMD"), vars.var_os("TOKEN_FILE")) {
+            (Ok(command), _) if !command.is_empty() => Some(TokenSource::Command(command)),
+            (_, Some(path)) if !path.is_empty() => Some(TokenSource::File(PathBuf::from(path))),
+            _ => None,
+        };
+        config.user = vars.var("NTFY_USER").ok().filter(|u| !u.is_empty());
+        config.password = vars.var("NTFY_PASS").ok();
+        config.auth_query = vars.var("AUTH_QUERY").is_ok_and(|v| v == "1");
+        #[cfg(feature = "keyring")]
+        if config.token.is_none() && config.token_source.is_none() && config.user.is_none() {
+            config.token = crate::credentials::stored_token(&config.server).unwrap_or_else(|e| {
+                log::warn!("Failed to read the token from the keyring: {}", e);
+                None
+            });
+        }
+        config.device = vars
+            .var("DEVICE_NAME")
+            .ok()
+            .filter(|d| !d.is_empty())
+            .unwrap_or_else(hostname);
+        config.tags_any = vars.list("TAGS_ANY");
+        config.tags_none = vars.list("TAGS_NONE");
+        config.min_priority = match vars.parse::<u8>("MIN_PRIORITY")? {
+            Some(priority) if !(1..=5).contains(&priority) => {
+                return Err(ConfigError::InvalidValue {
+                    name: "MIN_PRIORITY",
+                    value: priority.to_string(),
+                })
+            }
+            priority => priority,
+        };
+        config.title_match = vars
+            .parse::<TitleMatch>("TITLE_MATCH")?
+            .map(|title| TitleMatch {
+                strip: vars.var("TITLE_STRIP").is_ok_and(|v| v == "1"),
+                ..title
+            });
+        config.replay_window = vars.parse("REPLAY_WINDOW")?.map(Duration::from_secs);
+        config.markdown = vars.list("MARKDOWN");
+        config.strip_ansi = vars.var("STRIP_ANSI").is_ok_and(|v| v == "1");
+        config.strip_control = vars.var("STRIP_CONTROL").is_ok_and(|v| v == "1");
+        config.ack_topic = match vars.var("ACK_TOPIC") {
+            Ok(topic) if !topic.is_empty() => Some(topic),
+            _ if vars.var("ACK").is_ok_and(|v| v == "1") => Some(format!("{}-ack", config.topic)),
+            _ => None,
+        };
+        if let Ok(pattern) = vars.var("EXTRACT") {
+            config.extract = Some(Extract {
+                pattern: Regex::new(&pattern).map_err(|_| ConfigError::InvalidValue {
+                    name: "EXTRACT",
+                    value: pattern.clone(),
+                })?,
+                fallback_all: match vars.var("EXTRACT_FALLBACK") {
+                    Ok(fallback) => match fallback.as_str() {
+                        "all" => true,
+                        "skip" => false,
+                        _ => {
+                            return Err(ConfigError::InvalidValue {
+                                name: "EXTRACT_FALLBACK",
+                                value: fallback,
+                            })
+                        }
+                    },
+                    Err(_) => true,
+                },
+            });
+        }
+        config.base64 = vars.var("BASE64").is_ok_and(|v| v == "1");
+        config.extract_json = vars.parse("EXTRACT_JSON")?;
+        config.template = vars.parse("CLIP_TEMPLATE")?;
+        config.chunk_size = vars.parse("CHUNK_SIZE")?.filter(|&size| size > 0);
+        config.max_size = vars.parse("MAX_SIZE")?;
+        if let Ok(policy) = vars.var("MAX_SIZE_POLICY") {
+            config.oversize = match policy.as_str() {
+                "reject" => OversizePolicy::Reject,
+                "truncate" => OversizePolicy::Truncate,
+                "truncate-with-marker" => OversizePolicy::TruncateWithMarker,
+                _ => {
+                    return Err(ConfigError::InvalidValue {
+                        name: "MAX_SIZE_POLICY",
+                        value: policy,
+                    })
+                }
+            };
+        }
+        if let Some(size) = vars.parse("DEDUP_SIZE")? {
+            config.dedup_size = size;
+        }
+        if vars.var("DEDUP_PERSIST").is_ok_and(|v| v == "1") {
+            config.dedup_state = Some(match vars.var_os("DEDUP_STATE") {
+                Some(path) => PathBuf::from(path),
+                None => state_dir().join("seen-ids"),
+            });
+        }
+        if let Ok(routes) = vars.var("ROUTES") {
+            for rule in routes.split(',').map(str::trim).filter(|r| !r.is_empty()) {
+                let invalid = || ConfigError::InvalidValue {
+                    name: "ROUTES",
+                    value: rule.to_string(),
+                };
+                let (topic, route) = rule.split_once('=').ok_or_else(invalid)?;
+                let route = route.trim().parse().map_err(|_| invalid())?;
+                config.routes.push((topic.trim().to_string(), route));
+            }
+        }
+        config.timeout = Duration::from_secs(timeout);
+        config.adaptive_timeout = vars.var("ADAPTIVE_TIMEOUT").is_ok_and(|v| v == "1");
+        if let Ok(transport) = vars.var("TRANSPORT") {
+            config.transport = match (transport.as_str(), kind) {
+                ("auto", _) => Transport::Auto,
+                ("ws", _) => Transport::WebSocket,
+                // Gotify only streams over WebSockets, which Gotify servers
+                // mixed in with ntfy ones use whatever this says.
+                ("sse", ServerKind::Ntfy) => Transport::Sse,
+                ("json", ServerKind::Ntfy) => Transport::Json,
+                ("poll", ServerKind::Ntfy) => Transport::Poll,
+                _ => {
+                    return Err(ConfigError::InvalidValue {
+                        name: "TRANSPORT",
+                        value: transport,
+                    })
+                }
+            };
+        }
+        if let Ok(catch_up) = vars.var("CATCH_UP") {
+            config.catch_up = match catch_up.as_str() {
+                "latest" => CatchUp::Latest,
+                "all" => CatchUp::All,
+                "off" => CatchUp::Off,
+                _ => {
+                    return Err(ConfigError::InvalidValue {
+                        name: "CATCH_UP",
+                        value: catch_up,
+                    })
+                }
+            };
+        }
+        if let Some(interval) = vars.parse("POLL_INTERVAL")? {
+            config.poll_interval = Duration::from_secs(interval);
+        }
+        config.socket.bind_addr = vars.parse("BIND_ADDR")?;
+        config.socket.interface = vars.var("BIND_INTERFACE").ok().filter(|i| !i.is_empty());
+        config.socket.nodelay = vars.var("TCP_NODELAY").is_ok_and(|v| v == "1");
+        config.socket.keepalive = vars.parse("TCP_KEEPALIVE")?.map(Duration::from_secs);
+        config.socket.keepalive_interval = vars
+            .parse("TCP_KEEPALIVE_INTERVAL")?
+            .map(Duration::from_secs);
+        config.socket.keepalive_retries = vars.parse("TCP_KEEPALIVE_RETRIES")?;
+        config.socket.connect_timeout = vars.parse("CONNECT_TIMEOUT")?.map(Duration::from_secs);
+        config.socket.ip_family = vars.parse("IP_FAMILY")?.unwrap_or_default();
+        if let Ok(resolve) = vars.var("RESOLVE") {
+            for entry in resolve.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+                let entry = entry.parse().map_err(|_| ConfigError::InvalidValue {
+                    name: "RESOLVE",
+                    value: entry.to_string(),
+                })?;
+                config.socket.resolve.push(entry);
+            }
+        }
+        config.tls.ca_file = vars.var_os("CA_FILE").map(PathBuf::from);
+        config.tls.client_cert = match (vars.var_os("CLIENT_CERT"), vars.var_os("CLIENT_KEY")) {
+            (Some(cert), key) => Some(ClientCert {
+                key: PathBuf::from(key.unwrap_or_else(|| cert.clone())),
+                cert: PathBuf::from(cert),
+            }),
+            (None, Some(_)) => {
+                return Err(ConfigError::InvalidValue {
+                    name: "CLIENT_CERT",
+                    value: String::new(),
+                })
+            }
+            (None, None) => None,
+        };
+        if let Ok(pins) = vars.var("PIN_SHA256") {
+            config.tls.pins = pins
+                .split(',')
+                .map(|pin| {
+                    let pin = pin.trim();
+                    STANDARD
+                        .decode(pin.strip_prefix("sha256//").unwrap_or(pin))
+                        .ok()
+                        .and_then(|hash| <[u8; 32]>::try_from(hash).ok())
+                        .ok_or_else(|| ConfigError::InvalidValue {
+                            name: "PIN_SHA256",
+                            value: pin.to_string(),
+                        })
+                })
+                .collect::<Result<_, _>>()?;
+        }
+        config.tls.insecure = vars.var("INSECURE_SKIP_VERIFY").is_ok_and(|v| v == "1");
+        if config.tls.insecure {
+            log::warn!(
+                "INSECURE_SKIP_VERIFY=1: TLS certificates are not verified, \
+                 only use this with development servers"
+            );
+        }
+        config.socket.proxy = vars.parse("PROXY")?;
+        if config.socket.proxy.is_none() {
+            // The variables curl and most other HTTP clients use.
+            let https_proxy = ["HTTPS_PROXY", "https_proxy"]
+                .into_iter()
+                .find_map(|name| Some(name).zip(vars.var(name).ok()))
+                .filter(|(_, value)| !value.is_empty());
+            if let Some((name, value)) = https_proxy {
+                let mut proxy: Proxy = value
+                    .parse()
+                    .map_err(|_| ConfigError::InvalidValue { name, value })?;
+                proxy.bypass = ["NO_PROXY", "no_proxy"]
+                    .into_iter()
+                    .find_map(|name| vars.var(name).ok())
+                    .map(|hosts| {
+                        hosts
+                            .split(',')
+                            .map(str::trim)
+                            .filter(|host| !host.is_empty())
+                            .map(str::to_string)
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                config.socket.proxy = Some(proxy);
+            }
+        }
+        #[cfg(feature = "async")]
+        if let Ok(policy) = vars.var("CLIPBOARD_OVERFLOW") {
+            config.clipboard_overflow = match policy.as_str() {
+                "latest" => OverflowPolicy::Latest,
+                "drop-oldest" => OverflowPolicy::DropOldest,
+                "block" => OverflowPolicy::Block,
+                _ => {
+                    return Err(ConfigError::InvalidValue {
+                        name: "CLIPBOARD_OVERFLOW",
+                        value: policy,
+                    })
+                }
+            };
+        }
+        if let Ok(addr) = vars.var("HTTP_API") {
+            let token = vars
+                .var("HTTP_API_TOKEN")
+                .ok()
+                .filter(|t| !t.is_empty())
+                .ok_or(ConfigError::InvalidValue {
+                    name: "HTTP_API_TOKEN",
+                    value: String::new(),
+                })?;
+            config.api = Some(ApiConfig {
+                addr: addr.parse().map_err(|_| ConfigError::InvalidValue {
+                    name: "HTTP_API",
+                    value: addr,
+                })?,
+                token,
+            });
+        }
+        if let Ok(addr) = vars.var("HUB") {
+            config.hub = Some(HubConfig {
+                addr: addr.parse().map_err(|_| ConfigError::InvalidValue {
+                    name: "HUB",
+                    value: addr,
+                })?,
+                token: vars.var("HUB_TOKEN").ok().filter(|t| !t.is_empty()),
+            });
+        }
+        if let Some(dir) = vars.var_os("ATTACHMENTS_DIR") {
+            let quota = match vars.var("ATTACHMENTS_QUOTA") {
+                Ok(quota) => Some(quota.parse().map_err(|_| ConfigError::InvalidValue {
+                    name: "ATTACHMENTS_QUOTA",
+                    value: quota,
+                })?),
+                Err(_) => None,
+            };
+            config.attachments = Some(AttachmentConfig {
+                dir: PathBuf::from(dir),
+                quota,
+            });
+        }
+        if vars.var("UNIFIEDPUSH").is_ok_and(|v| v == "1") {
+            // Apps register through the HTTP API, so it has to be enabled.
+            if config.api.is_none() {
+                return Err(ConfigError::InvalidValue {
+                    name: "HTTP_API",
+                    value: String::new(),
+                });
+            }
+            let state = match vars.var_os("UNIFIEDPUSH_STATE") {
+                Some(path) => PathBuf::from(path),
+                None => state_dir().join("unifiedpush.json"),
+            };
+            config.unifiedpush = Some(UnifiedPushConfig { state });
+        }
+        if let Ok(url) = vars.var("SSE_URL") {
+            config.sse = Some(SseConfig {
+                url: url.parse().map_err(|_| ConfigError::InvalidValue {
+                    name: "SSE_URL",
+                    value: url,
+                })?,
+                field: match vars.var("SSE_FIELD") {
+                    Ok(field) if !field.is_empty() => {
+                        Some(field.parse().map_err(|_| ConfigError::InvalidValue {
+                            name: "SSE_FIELD",
+                            value: field,
+                        })?)
+                    }
+                    _ => None,
+                },
+                token: vars.var("SSE_TOKEN").ok().filter(|t| !t.is_empty()),
+            });
+        }
+        if let Ok(hosts) = vars.var("SSH_HOSTS") {
+            let command = match vars.var("SSH_COMMAND") {
+                Ok(command) if command == "osc52" => None,
+                Ok(command) if !command.is_empty() => Some(command),
+                _ => Some("wl-copy".to_string()),
+            };
+            config.ssh = Some(SshConfig {
+                hosts: hosts
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|h| !h.is_empty())
+                    .map(str::to_string)
+                    .collect(),
+                command,
+            });
+        }
+        if let Some(path) = vars.var_os("FILE_PATH").filter(|p| !p.is_empty()) {
+            config.file = Some(FileConfig {
+                path: PathBuf::from(path),
+                format: vars.parse("FILE_FORMAT")?.unwrap_or_else(|| {
+                    Template::from_str("[{date}] {topic}\n{message}\n").expect("valid template")
+                }),
+                clipboard: !vars.var("FILE_ONLY").is_ok_and(|v| v == "1"),
+            });
+        }
+        if vars.var("EMACS").is_ok_and(|v| v == "1") {
+            config.emacs = Some(EmacsConfig {
+                socket: vars.var("EMACS_SOCKET").ok().filter(|s| !s.is_empty()),
+                topics: vars
+                    .var("EMACS_TOPICS")
+                    .ok()
+                    .filter(|t| !t.is_empty())
+                    .map(|t| t.split(',').map(|t| t.trim().to_string()).collect()),
+            });
+        }
+        if vars.var("COPYQ").is_ok_and(|v| v == "1") {
+            config.copyq = Some(CopyqConfig {
+                tab: vars.var("COPYQ_TAB").ok().filter(|t| !t.is_empty()),
+            });
+        }
+        if vars.var("PUBLISH_CLIPBOARD").is_ok_and(|v| v == "1") {
+            config.watch = Some(WatchConfig {
+                interval: Duration::from_secs(vars.parse("PUBLISH_INTERVAL")?.unwrap_or(1)),
+            });
+        }
+        #[cfg(feature = "hickory")]
+        {
+            config.dns = DnsConfig::from_vars(vars)?;
+        }
+        #[cfg(feature = "imap")]
+        {
+            config.imap = ImapConfig::from_vars(vars)?;
+        }
+        #[cfg(feature = "kdeconnect")]
+        {
+            config.kdeconnect = KdeConnectConfig::from_vars(vars)?;
+        }
+        #[cfg(feature = "mqtt")]
+        {
+            config.mqtt = MqttConfig::from_vars(vars)?;
+        }
+        #[cfg(feature = "neovim")]
+        {
+            config.neovim = NeovimConfig::from_vars(vars)?;
+        }
+        #[cfg(feature = "mdns")]
+        {
+            config.lan = LanConfig::from_vars(vars)?;
+        }
+        #[cfg(feature = "e2e")]
+        if let Some(passphrase) = vars.var("E2E_PASSPHRASE").ok().filter(|p| !p.is_empty()) {
+            config.e2e = Some(E2eConfig {
+                // The passphrase itself stays out of the error.
+                key: crate::e2e::derive_key(&passphrase).ok_or(ConfigError::InvalidValue {
+                    name: "E2E_PASSPHRASE",
+                    value: String::new(),
+                })?,
+                accept_plaintext: vars.var("E2E_ACCEPT_PLAINTEXT").is_ok_and(|v| v == "1"),
+            });
+        }
+        #[cfg(feature = "hmac")]
+        {
+            config.hmac = vars
+                .var("HMAC_SECRET")
+                .ok()
+                .filter(|secret| !secret.is_empty())
+                .map(|secret| HmacConfig { secret });
+        }
+        Ok(config)
+    }
+
+    pub fn url(&self) -> Result<Url, url::ParseError> {
+        self.subscription_url(&[])
+    }
+
+    /// Like [`url`](Self::url), also subscribing to `extra` ntfy topics.
+    pub(crate) fn subscription_url(&self, extra: &[String]) -> Result<Url, url::ParseError> {
+        match self.kind {
+            ServerKind::Ntfy => Url::parse(
+                format!(
+                    "{}://{}/{}/ws",
+                    self.scheme,
+                    self.server,
+                    self.topic_list(extra)
+                )
+                .as_str(),
+            ),
+            ServerKind::Gotify => {
+                Url::parse(format!("{}://{}/stream", self.scheme, self.server).as_str())
+            }
+        }
+    }
+
+    /// Like [`subscription_url`](Self::subscription_url), for ntfy's HTTP
+    /// `endpoint` of the topics, e.g. `sse`.
+    #[cfg(feature = "async")]
+    pub(crate) fn stream_url(
+        &self,
+        extra: &[String],
+        endpoint: &str,
+    ) -> Result<Url, url::ParseError> {
+        let topics = self.topic_list(extra);
+        Url::parse(
+            format!(
+                "{}://{}/{}/{}",
+                self.http_scheme(),
+                self.server,
+                topics,
+                endpoint
+            )
+            .as_str(),
+        )
+    }
+
+    /// The subscribed topics, comma-separated.
+    fn topic_list(&self, extra: &[String]) -> String {
+        let mut topics = self.topic.clone();
+        for topic in self.topics.iter().chain(extra) {
+            topics.push(',');
+            topics.push_str(topic);
+        }
+        topics
+    }
+
+    fn http_scheme(&self) -> &'static str {
+        match self.scheme.as_str() {
+            "ws" => "http",
+            _ => "https",
+        }
+    }
+
+    /// Whether messages of `topic` are for this client.
+    pub fn subscribes(&self, topic: &str) -> bool {
+        self.topic == topic || self.topics.iter().any(|t| t == topic)
+    }
+
+    /// Whether messages of `topic` are rendered from markdown, see
+    /// [`markdown`](Self::markdown).
+    pub(crate) fn renders_markdown(&self, topic: &str) -> bool {
+        self.markdown.iter().any(|t| t == "*" || t == topic)
+    }
+
+    /// The token to send now, read again if it comes from a
+    /// [`TokenSource`].
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn current_token(&self) -> Result<Option<String>, AuthError> {
+        match &self.token_source {
+            Some(source) => source.read().map(Some),
+            None => Ok(self.token.clone()),
+        }
+    }
+
+    /// The `Authorization` header for ntfy: the token, or else the user and
+    /// password.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn authorization(&self) -> Result<Option<String>, AuthError> {
+        if let Some(token) = self.current_token()? {
+            return Ok(Some(format!("Bearer {token}")));
+        }
+        Ok(self.user.as_ref().map(|user| {
+            let password = self.password.as_deref().unwrap_or_default();
+            format!("Basic {}", STANDARD.encode(format!("{user}:{password}")))
+        }))
+    }
+
+    /// Where messages of `topic` go.
+    pub fn route(&self, topic: &str) -> &Route {
+        self.routes
+            .iter()
+            .find(|(t, _)| t == topic)
+            .map_or(&Route::Clipboard, |(_, route)| route)
+    }
+
+    /// The plain HTTP(S) endpoint of the topic, used for publishing.
+    pub fn http_url(&self) -> Result<Url, url::ParseError> {
+        Url::parse(format!("{}://{}/{}", self.http_scheme(), self.server, self.topic).as_str())
+    }
+}
+
+/// Settings by the name of their environment variable, so they can come from
+/// elsewhere than the environment. Values [`set`](Self::set) take precedence
+/// over the environment, which takes precedence over those
+/// [`set_default`](Self::set_default). The process environment itself is
+/// only ever read.
+#[derive(Debug, Clone, Default)]
+pub struct Vars {
+    overrides: HashMap<String, String>,
+    defaults: HashMap<String, String>,
+}
+
+/// The name of every setting, as read by [`Config::from_vars`] and the
+/// clipboard backends.
+pub const SETTINGS: &[&str] = &[
+    "ACK",
+    "ACK_TOPIC",
+    "ADAPTIVE_TIMEOUT",
+    "ATTACHMENTS_DIR",
+    "ATTACHMENTS_QUOTA",
+    "AUTH_QUERY",
+    "BASE64",
+    "BIND_ADDR",
+    "BIND_INTERFACE",
+    "CATCH_UP",
+    "CA_FILE",
+    "CHUNK_SIZE",
+    "CLIENT_CERT",
+    "CLIENT_KEY",
+    "CLIPBOARD_OVERFLOW",
+    "CLIPBOARD_PORTAL",
+    "CLIP_COMMAND",
+    "CLIP_EXE_PATH",
+    "CLIP_TEMPLATE",
+    "CONNECT_TIMEOUT",
+    "COPYQ",
+    "COPYQ_TAB",
+    "COPY_PRIMARY",
+    "DEDUP_PERSIST",
+    "DEDUP_SIZE",
+    "DEDUP_STATE",
+    "DEVICE_NAME",
+    "DNS_PROTOCOL",
+    "DNS_SERVERS",
+    "DNS_TLS_NAME",
+    "E2E_ACCEPT_PLAINTEXT",
+    "E2E_PASSPHRASE",
+    "EMACS",
+    "EMACS_SOCKET",
+    "EMACS_TOPICS",
+    "EXTRACT",
+    "EXTRACT_FALLBACK",
+    "EXTRACT_JSON",
+    "FILE_FORMAT",
+    "FILE_ONLY",
+    "FILE_PATH",
+    "HMAC_SECRET",
+    "HTTP_API",
+    "HTTP_API_TOKEN",
+    "HUB",
+    "HUB_TOKEN",
+    "IMAP_FROM",
+    "IMAP_SUBJECT",
+    "IMAP_URL",
+    "INSECURE_SKIP_VERIFY",
+    "IP_FAMILY",
+    "KDECONNECT",
+    "KDECONNECT_DEVICES",
+    "KDECONNECT_REPUBLISH",
+    "LAN_PORT",
+    "LAN_SECRET",
+    "LAN_THRESHOLD",
+    "MARKDOWN",
+    "MAX_SIZE",
+    "MAX_SIZE_POLICY",
+    "MIN_PRIORITY",
+    "MQTT_HOMEASSISTANT",
+    "MQTT_PUBLISH",
+    "MQTT_SUBSCRIBE",
+    "MQTT_URL",
+    "NEOVIM_REGISTER",
+    "NEOVIM_SOCKET",
+    "NTFY_PASS",
+    "NTFY_USER",
+    "PBCOPY_PATH",
+    "PIN_SHA256",
+    "POLL_INTERVAL",
+    "PROXY",
+    "PUBLISH_CLIPBOARD",
+    "PUBLISH_INTERVAL",
+    "REPLAY_WINDOW",
+    "RESOLVE",
+    "ROUTES",
+    "SCHEME",
+    "SERVER",
+    "SERVERS",
+    "SERVER_TYPE",
+    "SSE_FIELD",
+    "SSE_TOKEN",
+    "SSE_URL",
+    "SSH_COMMAND",
+    "SSH_HOSTS",
+    "STANDBY_SERVER",
+    "STRIP_ANSI",
+    "STRIP_CONTROL",
+    "TAGS_ANY",
+    "TAGS_NONE",
+    "TCP_KEEPALIVE",
+    "TCP_KEEPALIVE_INTERVAL",
+    "TCP_KEEPALIVE_RETRIES",
+    "TCP_NODELAY",
+    "TIMEOUT",
+    "TITLE_MATCH",
+    "TITLE_STRIP",
+    "TMUX_BUFFER",
+    "TMUX_PATH",
+    "TOKEN",
+    "TOKEN_CMD",
+    "TOKEN_FILE",
+    "TOPIC",
+    "TRANSPORT",
+    "UNIFIEDPUSH",
+    "UNIFIEDPUSH_STATE",
+    "WL_COPY_PATH",
+    "WSL_CLIPBOARD",
+    "XCLIP_PATH",
+    "XSEL_PATH",
+];
+
+/// The settings in use, for those read outside of [`Config`] such as the
+/// clipboard backends'.
+static INSTALLED: RwLock<Option<Arc<Vars>>> = RwLock::new(None);
+
+impl Vars {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets `name`, e.g. from a command-line flag.
+    pub fn set(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.overrides.insert(name.into(), value.into());
+    }
+
+    /// Sets `name` unless it's in the environment, e.g. from a config file.
+    pub fn set_default(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.defaults.insert(name.into(), value.into());
+    }
+
+    /// Like [`env::var`].
+    pub fn var(&self, name: &str) -> Result<String, env::VarError> {
+        if let Some(value) = self.overrides.get(name) {
+            return Ok(value.clone());
+        }
+        match env::var(name) {
+            Err(env::VarError::NotPresent) => self
+                .defaults
+                .get(name)
+                .cloned()
+                .ok_or(env::VarError::NotPresent),
+            result => result,
+        }
+    }
+
+    /// Like [`env::var_os`].
+    pub fn var_os(&self, name: &str) -> Option<OsString> {
+        if let Some(value) = self.overrides.get(name) {
+            return Some(value.into());
+        }
+        env::var_os(name).or_else(|| self.defaults.get(name).map(OsString::from))
+    }
+
+    /// Makes these the settings read outside of [`Config`], and by
+    /// [`Config::from_env`]. Takes effect for every later read.
+    pub fn install(self) {
+        *INSTALLED.write().unwrap() = Some(Arc::new(self));
+    }
+
+    fn installed() -> Arc<Vars> {
+        INSTALLED.read().unwrap().clone().unwrap_or_default()
+    }
+
+    /// Parses `name` if it is set.
+    pub(crate) fn parse<T: FromStr>(&self, name: &'static str) -> Result<Option<T>, ConfigError> {
+        match self.var(name) {
+            Ok(value) => match value.parse() {
+                Ok(parsed) => Ok(Some(parsed)),
+                Err(_) => Err(ConfigError::InvalidValue { name, value }),
+            },
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// The entries of a comma-separated variable, empty if it's unset.
+    fn list(&self, name: &str) -> Vec<String> {
+        self.var(name)
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+}
+
+/// A setting from the installed [`Vars`], or the environment.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn var(name: &str) -> Result<String, env::VarError> {
+    Vars::installed().var(name)
+}
+
+/// The machine's name, used to tell instances apart.
+pub(crate) fn hostname() -> String {
+    env::var("HOSTNAME")
+        .ok()
+        .or_else(|| fs::read_to_string("/etc/hostname").ok())
+        .map(|h| h.trim().to_string())
+        .filter(|h| !h.is_empty())
+        .unwrap_or("ntfy2clip".to_string())
+}
+
+/// `$XDG_STATE_HOME/ntfy2clip`, for files that should survive restarts. Old
+/// Flatpak versions don't set `XDG_STATE_HOME`, so fall back to the app's own
+/// directory there; Snap already points `HOME` at the snap's data.
+pub(crate) fn state_dir() -> PathBuf {
+    let home = |rest: &str| env::var_os("HOME").map(|home| PathBuf::from(home).join(rest));
+    env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| match env::var("FLATPAK_ID") {
+            Ok(app_id) => home(&format!(".var/app/{app_id}/.local/state")),
+            Err(_) => None,
+        })
+        .or_else(|| home(".local/state"))
+        .unwrap_or_default()
+        .join("ntfy2clip")
+}
@@ -0,0 +1,32 @@
+//! Access tokens kept in the OS keyring (Secret Service, macOS Keychain or
+//! Windows Credential Manager) instead of plain environment or unit files.
+//! There is one entry per server.
+
+use crate::error::AuthError;
+use keyring::Entry;
+use std::thread;
+
+const SERVICE: &str = "ntfy2clip";
+
+/// The token stored for `server`, if any.
+pub(crate) fn stored_token(server: &str) -> Result<Option<String>, AuthError> {
+    off_runtime(|| match Entry::new(SERVICE, server)?.get_password() {
+        Ok(token) => Ok(Some(token)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(e.into()),
+    })
+}
+
+/// Stores `token` for `server`, replacing the previous one.
+pub fn store_token(server: &str, token: &str) -> Result<(), AuthError> {
+    off_runtime(|| {
+        Entry::new(SERVICE, server)?.set_password(token)?;
+        Ok(())
+    })
+}
+
+/// Runs `f` on its own thread: the Secret Service client blocks on a Tokio
+/// runtime of its own, which can't start on a thread already running one.
+fn off_runtime<T: Send>(f: impl FnOnce() -> T + Send) -> T {
+    thread::scope(|scope| scope.spawn(f).join().expect("keyring thread panicked"))
+}
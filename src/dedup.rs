@@ -0,0 +1,103 @@
+//! Dropping messages that were already delivered.
+//!
+//! Catching up with `since=`, the warm standby and failing over between
+//! servers can all deliver a message twice, which would overwrite newer
+//! clipboard content with an old one. The ids of the last
+//! [`Config::dedup_size`] messages are remembered, and with
+//! [`Config::dedup_state`] kept in a file across restarts, one id per line.
+//! New ids are appended to it, and only once it has grown to twice the ids
+//! remembered is it rewritten with just those.
+
+use crate::config::Config;
+use log::error;
+use std::collections::VecDeque;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+pub(crate) struct SeenIds {
+    /// Least recently seen first.
+    ids: VecDeque<String>,
+    capacity: usize,
+    state: Option<PathBuf>,
+    /// Lines in the state file.
+    saved: usize,
+}
+
+impl SeenIds {
+    /// The ids remembered, loaded from the state file if there is one.
+    pub(crate) fn load(config: &Config) -> Self {
+        let capacity = config.dedup_size;
+        let mut seen = SeenIds {
+            ids: VecDeque::with_capacity(capacity),
+            capacity,
+            state: config.dedup_state.clone(),
+            saved: 0,
+        };
+        if let Some(state) = &config.dedup_state {
+            match fs::read_to_string(state) {
+                Ok(saved) => {
+                    // Later lines are more recent, an id seen again included.
+                    for id in saved.lines().filter(|id| !id.is_empty()) {
+                        seen.saved += 1;
+                        seen.ids.retain(|seen| seen != id);
+                        seen.ids.push_back(id.to_string());
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => error!("Failed to read seen message ids from {:?}: {}", state, e),
+            }
+        }
+        while seen.ids.len() > capacity {
+            seen.ids.pop_front();
+        }
+        seen
+    }
+
+    /// Remembers `id`; `false` if it was seen before.
+    pub(crate) fn first_time(&mut self, id: &str) -> bool {
+        if let Some(index) = self.ids.iter().position(|seen| seen == id) {
+            if let Some(seen) = self.ids.remove(index) {
+                self.ids.push_back(seen);
+            }
+            return false;
+        }
+        if self.capacity == 0 {
+            return true;
+        }
+        if self.ids.len() == self.capacity {
+            self.ids.pop_front();
+        }
+        self.ids.push_back(id.to_string());
+        if let Err(e) = self.save(id) {
+            error!("Failed to save seen message ids: {}", e);
+        }
+        true
+    }
+
+    /// Appends `id`, which was just remembered, to the state file.
+    fn save(&mut self, id: &str) -> io::Result<()> {
+        let Some(state) = &self.state else {
+            return Ok(());
+        };
+        if self.saved == 0 {
+            if let Some(dir) = state.parent() {
+                fs::create_dir_all(dir)?;
+            }
+        }
+        if self.saved >= 2 * self.capacity {
+            let mut saved = String::new();
+            for id in &self.ids {
+                saved.push_str(id);
+                saved.push('\n');
+            }
+            fs::write(state, saved)?;
+            self.saved = self.ids.len();
+            return Ok(());
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(state)?;
+        file.write_all(format!("{id}\n").as_bytes())?;
+        self.saved += 1;
+        Ok(())
+    }
+}
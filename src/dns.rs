@@ -0,0 +1,144 @@
+//! Resolving the server through hickory, enabled with the `hickory` feature.
+//!
+//! One resolver is shared by every connection, so its cache spares the
+//! frequent reconnects a lookup each, and it can bypass a broken local
+//! resolver with fixed nameservers, DNS-over-TLS or DNS-over-HTTPS.
+
+use crate::config::Vars;
+use crate::error::ConfigError;
+use hickory_resolver::config::{LookupIpStrategy, NameServerConfig, ResolverConfig};
+use hickory_resolver::name_server::TokioConnectionProvider;
+use hickory_resolver::proto::xfer::Protocol;
+use hickory_resolver::TokioResolver;
+use log::debug;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// Cached answers are kept at least this long, whatever their TTL.
+const MIN_TTL: Duration = Duration::from_secs(60);
+
+static RESOLVER: OnceLock<TokioResolver> = OnceLock::new();
+
+#[derive(Debug, Clone)]
+pub struct DnsConfig {
+    /// Nameservers to ask; empty means the system's.
+    pub servers: Vec<SocketAddr>,
+    pub protocol: DnsProtocol,
+    /// Name on the nameservers' certificate, for TLS and HTTPS.
+    pub tls_name: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DnsProtocol {
+    Udp,
+    Tls,
+    Https,
+}
+
+impl DnsProtocol {
+    fn port(self) -> u16 {
+        match self {
+            DnsProtocol::Udp => 53,
+            DnsProtocol::Tls => 853,
+            DnsProtocol::Https => 443,
+        }
+    }
+}
+
+impl DnsConfig {
+    /// Reads `DNS_SERVERS`, `DNS_PROTOCOL` and `DNS_TLS_NAME`.
+    pub(crate) fn from_vars(vars: &Vars) -> Result<Option<Self>, ConfigError> {
+        let Ok(servers) = vars.var("DNS_SERVERS") else {
+            return Ok(None);
+        };
+        let protocol = match vars.var("DNS_PROTOCOL") {
+            Ok(value) => match value.as_str() {
+                "udp" => DnsProtocol::Udp,
+                "tls" => DnsProtocol::Tls,
+                "https" => DnsProtocol::Https,
+                _ => {
+                    return Err(ConfigError::InvalidValue {
+                        name: "DNS_PROTOCOL",
+                        value,
+                    })
+                }
+            },
+            Err(_) => DnsProtocol::Udp,
+        };
+        let servers = if servers == "system" {
+            Vec::new()
+        } else {
+            servers
+                .split(',')
+                .map(|s| {
+                    let s = s.trim();
+                    s.parse::<SocketAddr>()
+                        .or_else(|_| s.parse::<IpAddr>().map(|ip| (ip, protocol.port()).into()))
+                        .map_err(|_| ConfigError::InvalidValue {
+                            name: "DNS_SERVERS",
+                            value: s.to_string(),
+                        })
+                })
+                .collect::<Result<_, _>>()?
+        };
+        let tls_name = vars.var("DNS_TLS_NAME").ok().filter(|n| !n.is_empty());
+        // Encrypted transports verify the nameserver's certificate.
+        if protocol != DnsProtocol::Udp && (servers.is_empty() || tls_name.is_none()) {
+            return Err(ConfigError::InvalidValue {
+                name: "DNS_TLS_NAME",
+                value: tls_name.unwrap_or_default(),
+            });
+        }
+        Ok(Some(DnsConfig {
+            servers,
+            protocol,
+            tls_name,
+        }))
+    }
+}
+
+/// Looks up the addresses of `host`.
+pub(crate) async fn resolve(dns: &DnsConfig, host: &str) -> io::Result<Vec<IpAddr>> {
+    if let Ok(ip) = host.trim_start_matches('[').trim_end_matches(']').parse() {
+        return Ok(vec![ip]);
+    }
+    if RESOLVER.get().is_none() {
+        // A racing connection may have set it first; either one works.
+        let _ = RESOLVER.set(build(dns)?);
+    }
+    let resolver = RESOLVER.get().unwrap();
+    let lookup = resolver.lookup_ip(host).await.map_err(io::Error::other)?;
+    let addrs: Vec<_> = lookup.iter().collect();
+    debug!("resolved {} to {:?}", host, addrs);
+    Ok(addrs)
+}
+
+fn build(dns: &DnsConfig) -> io::Result<TokioResolver> {
+    let provider = TokioConnectionProvider::default();
+    let mut builder = if dns.servers.is_empty() {
+        TokioResolver::builder(provider).map_err(io::Error::other)?
+    } else {
+        let protocols: &[Protocol] = match dns.protocol {
+            DnsProtocol::Udp => &[Protocol::Udp, Protocol::Tcp],
+            DnsProtocol::Tls => &[Protocol::Tls],
+            DnsProtocol::Https => &[Protocol::Https],
+        };
+        let mut servers = Vec::new();
+        for &addr in &dns.servers {
+            for &protocol in protocols {
+                let mut server = NameServerConfig::new(addr, protocol);
+                server.tls_dns_name = dns.tls_name.clone();
+                servers.push(server);
+            }
+        }
+        let config = ResolverConfig::from_parts(None, Vec::new(), servers);
+        TokioResolver::builder_with_config(config, provider)
+    };
+    let options = builder.options_mut();
+    options.positive_min_ttl = Some(MIN_TTL);
+    // Both families, for racing them.
+    options.ip_strategy = LookupIpStrategy::Ipv4AndIpv6;
+    Ok(builder.build())
+}
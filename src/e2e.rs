@@ -0,0 +1,86 @@
+//! End-to-end encryption with a shared passphrase, so the server only ever
+//! sees ciphertext.
+//!
+//! The key is derived once from the passphrase with Argon2id and a fixed
+//! salt, so every instance sharing the passphrase arrives at the same key.
+//! Each message is sealed with XChaCha20-Poly1305 under a random nonce, and
+//! published as base64 of `nonce || ciphertext` tagged [`TAG`]. Encryption
+//! happens before chunking, and decryption after reassembly.
+
+use crate::config::{Config, E2eConfig};
+use crate::message::WSMessage;
+use argon2::Argon2;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use log::warn;
+
+/// Tag of encrypted messages.
+pub(crate) const TAG: &str = "e2e";
+/// Same for every instance, so they derive the same key; the passphrase
+/// has to carry the entropy.
+const SALT: &[u8] = b"ntfy2clip e2e v1";
+const NONCE_SIZE: usize = 24;
+
+/// Derives the key from `passphrase`, which takes a moment on purpose.
+pub(crate) fn derive_key(passphrase: &str) -> Option<[u8; 32]> {
+    let mut key = [0; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), SALT, &mut key)
+        .ok()?;
+    Some(key)
+}
+
+/// `text` encrypted for the body of a message tagged [`TAG`].
+pub(crate) fn seal(config: &E2eConfig, text: &str) -> Option<String> {
+    let mut nonce = [0; NONCE_SIZE];
+    getrandom::fill(&mut nonce).ok()?;
+    let cipher = XChaCha20Poly1305::new(&config.key.into());
+    let sealed = cipher.encrypt(&XNonce::from(nonce), text.as_bytes()).ok()?;
+    Some(STANDARD.encode([nonce.as_slice(), &sealed].concat()))
+}
+
+/// Decrypts a message tagged [`TAG`], dropping the tag. Messages without it
+/// are passed through only with [`E2eConfig::accept_plaintext`], or without
+/// [`Config::e2e`].
+pub(crate) fn open(config: &Config, mut msg: WSMessage) -> Option<WSMessage> {
+    let Some(config) = &config.e2e else {
+        return Some(msg);
+    };
+    let tags = msg.tags.as_deref().unwrap_or_default();
+    let Some(index) = tags.iter().position(|tag| tag == TAG) else {
+        if !config.accept_plaintext {
+            warn!(
+                "Dropping unencrypted message {}",
+                msg.id.as_deref().unwrap_or_default()
+            );
+            return None;
+        }
+        return Some(msg);
+    };
+    if let Some(tags) = &mut msg.tags {
+        tags.remove(index);
+    }
+    let body = msg.message.as_deref().unwrap_or_default();
+    let Some(text) = decrypt(config, body) else {
+        warn!(
+            "Dropping message {} that doesn't decrypt with E2E_PASSPHRASE",
+            msg.id.as_deref().unwrap_or_default()
+        );
+        return None;
+    };
+    msg.message = Some(text);
+    Some(msg)
+}
+
+fn decrypt(config: &E2eConfig, body: &str) -> Option<String> {
+    let sealed = STANDARD.decode(body.trim()).ok()?;
+    if sealed.len() < NONCE_SIZE {
+        return None;
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_SIZE);
+    let nonce: [u8; NONCE_SIZE] = nonce.try_into().ok()?;
+    let cipher = XChaCha20Poly1305::new(&config.key.into());
+    let text = cipher.decrypt(&XNonce::from(nonce), ciphertext).ok()?;
+    String::from_utf8(text).ok()
+}
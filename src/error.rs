@@ -0,0 +1,230 @@
+use std::io;
+use std::path::PathBuf;
+use std::process::ExitStatus;
+use std::time::Duration;
+use thiserror::Error;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Top-level error returned by the library API.
+///
+/// Each variant wraps a more specific error; use [`Error::is_retryable`] to
+/// decide whether reconnecting can help.
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    #[error(transparent)]
+    Connect(#[from] ConnectError),
+    #[error(transparent)]
+    Auth(#[from] AuthError),
+    #[error(transparent)]
+    Clipboard(#[from] ClipboardError),
+    #[error(transparent)]
+    Protocol(#[from] ProtocolError),
+    #[error(transparent)]
+    Storage(#[from] StorageError),
+    /// A sink's write hung past its deadline and was abandoned.
+    #[cfg(feature = "async")]
+    #[error("write hung for {0:?} and was abandoned")]
+    Stalled(std::time::Duration),
+}
+
+impl Error {
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Config(_) => false,
+            Error::Auth(e) => e.is_retryable(),
+            Error::Connect(e) => e.is_retryable(),
+            Error::Clipboard(e) => e.is_retryable(),
+            Error::Protocol(e) => e.is_retryable(),
+            Error::Storage(e) => e.is_retryable(),
+            #[cfg(feature = "async")]
+            Error::Stalled(_) => true,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("you must subscribe to a topic (TOPIC is not set)")]
+    MissingTopic,
+    #[error("invalid value {value:?} for {name}")]
+    InvalidValue { name: &'static str, value: String },
+}
+
+#[derive(Debug, Error)]
+pub enum ConnectError {
+    #[error("invalid server URL: {0}")]
+    InvalidUrl(#[from] url::ParseError),
+    #[cfg(not(target_arch = "wasm32"))]
+    #[error("WebSocket handshake failed: {0}")]
+    Handshake(#[source] Box<tungstenite::Error>),
+    #[cfg(target_arch = "wasm32")]
+    #[error("browser WebSocket error: {0}")]
+    Web(String),
+    #[error("no traffic in the last {} seconds", .0.as_secs())]
+    Idle(Duration),
+    #[cfg(feature = "async")]
+    #[error("HTTP request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("server responded with HTTP {0}")]
+    Status(u16),
+    #[cfg(feature = "e2e")]
+    #[error("failed to encrypt the message")]
+    Encryption,
+    #[cfg(feature = "async")]
+    #[error("can't use {}: {reason}", path.display())]
+    TlsFile { path: PathBuf, reason: String },
+    #[cfg(feature = "async")]
+    #[error("TLS setup failed: {0}")]
+    Tls(String),
+    #[cfg(feature = "async")]
+    #[error("the server's public key doesn't match PIN_SHA256")]
+    PinMismatch,
+    #[cfg(feature = "mqtt")]
+    #[error("MQTT client error: {0}")]
+    Mqtt(#[from] rumqttc::ClientError),
+}
+
+impl ConnectError {
+    pub fn is_retryable(&self) -> bool {
+        !matches!(self, ConnectError::InvalidUrl(_))
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum AuthError {
+    #[error("token contains characters that are not allowed in an HTTP header")]
+    InvalidToken,
+    #[error("server rejected the credentials (HTTP {0})")]
+    Rejected(u16),
+    #[cfg(feature = "keyring")]
+    #[error("keyring error: {0}")]
+    Keyring(#[from] keyring::Error),
+    #[error("failed to read the token from {}: {source}", path.display())]
+    TokenFile {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("failed to run token command `{command}`: {source}")]
+    TokenSpawn {
+        command: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("token command `{command}` exited with {status}")]
+    TokenExit { command: String, status: ExitStatus },
+}
+
+impl AuthError {
+    /// Token files and commands may just not be ready yet, e.g. while a
+    /// secret is being rotated.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            AuthError::TokenFile { .. }
+                | AuthError::TokenSpawn { .. }
+                | AuthError::TokenExit { .. }
+        )
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ClipboardError {
+    #[error("unsupported environment: {0}")]
+    Unsupported(&'static str),
+    #[error("failed to spawn {command}: {source}")]
+    Spawn {
+        command: &'static str,
+        #[source]
+        source: io::Error,
+    },
+    #[error("failed to write to {command}: {source}")]
+    Write {
+        command: &'static str,
+        #[source]
+        source: io::Error,
+    },
+    #[error("{command} exited with {status}")]
+    Exit {
+        command: &'static str,
+        status: ExitStatus,
+    },
+    #[cfg(target_arch = "wasm32")]
+    #[error("browser clipboard error: {0}")]
+    Web(String),
+    #[cfg(any(feature = "kdeconnect", feature = "klipper"))]
+    #[error("D-Bus error: {0}")]
+    DBus(#[from] zbus::Error),
+    #[cfg(feature = "portal")]
+    #[error("desktop portal error: {0}")]
+    Portal(#[from] ashpd::Error),
+    #[cfg(feature = "x11")]
+    #[error("failed to connect to X11: {0}")]
+    X11Connect(#[from] x11rb::errors::ConnectError),
+    #[cfg(feature = "x11")]
+    #[error("X11 error: {0}")]
+    X11(#[from] x11rb::errors::ReplyOrIdError),
+    #[cfg(feature = "arboard")]
+    #[error("arboard error: {0}")]
+    Arboard(#[from] arboard::Error),
+    #[cfg(feature = "data-control")]
+    #[error("Wayland data-control error: {0}")]
+    DataControl(#[from] wl_clipboard_rs::copy::Error),
+}
+
+impl ClipboardError {
+    pub fn is_retryable(&self) -> bool {
+        !matches!(self, ClipboardError::Unsupported(_))
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ProtocolError {
+    #[cfg(not(target_arch = "wasm32"))]
+    #[error("WebSocket error: {0}")]
+    WebSocket(#[source] Box<tungstenite::Error>),
+    #[error("malformed message: {0}")]
+    InvalidMessage(#[from] serde_json::Error),
+    #[error("message of {0} bytes is too large")]
+    TooLarge(usize),
+    #[error("message nested deeper than {0} levels")]
+    TooDeep(usize),
+    #[error("invalid compressed message: {0}")]
+    InvalidCompression(String),
+    #[error("invalid base64 message: {0}")]
+    InvalidBase64(String),
+}
+
+impl ProtocolError {
+    pub fn is_retryable(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl From<tungstenite::Error> for ProtocolError {
+    fn from(e: tungstenite::Error) -> Self {
+        ProtocolError::WebSocket(Box::new(e))
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum StorageError {
+    #[error("failed to write {}: {source}", path.display())]
+    Write {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("{size} more bytes would exceed the {quota} byte quota")]
+    Quota { size: u64, quota: u64 },
+}
+
+impl StorageError {
+    pub fn is_retryable(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,84 @@
+use tokio::sync::broadcast;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+/// States of the [`Supervisor`](crate::Supervisor).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    /// Waiting the given delay before the next connection attempt.
+    Backoff(Duration),
+    Paused,
+    GivingUp,
+}
+
+#[derive(Debug, Clone)]
+pub enum Event {
+    StateChanged(ConnectionState),
+    /// Connections now go to another server, after a failover.
+    ServerChanged {
+        server: String,
+    },
+    MessageReceived {
+        topic: String,
+        message: String,
+    },
+    ClipboardWritten {
+        content: Arc<str>,
+    },
+    Error {
+        message: String,
+        retryable: bool,
+    },
+    /// A sink write hung past the watchdog's deadline and was abandoned.
+    SinkStalled {
+        sink: &'static str,
+        deadline: Duration,
+    },
+    /// The server sent a frame that was rejected instead of parsed.
+    ProtocolAnomaly {
+        reason: String,
+        size: usize,
+    },
+}
+
+/// Fan-out of [`Event`]s to any number of subscribers.
+///
+/// Emitting never blocks; subscribers that fall behind by more than the
+/// channel capacity miss the oldest events.
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    tx: broadcast::Sender<Event>,
+}
+
+impl EventBus {
+    pub fn new(capacity: usize) -> Self {
+        let (tx, _) = broadcast::channel(capacity);
+        EventBus { tx }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.tx.subscribe()
+    }
+
+    pub fn emit(&self, event: Event) {
+        // No subscribers is not an error.
+        let _ = self.tx.send(event);
+    }
+
+    /// Like [`emit`](Self::emit), but only builds the event if anyone is
+    /// subscribed, which saves copying message content nobody reads.
+    pub(crate) fn emit_with(&self, event: impl FnOnce() -> Event) {
+        if self.tx.receiver_count() > 0 {
+            self.emit(event());
+        }
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        EventBus::new(64)
+    }
+}
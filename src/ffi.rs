@@ -0,0 +1,147 @@
+//! C API for embedding the bridge, enabled with the `ffi` feature.
+//!
+//! The matching declarations live in `include/ntfy2clip.h`.
+
+use crate::{publish, run, Config, Event, EventBus};
+use log::{debug, error};
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use tokio::runtime::Runtime;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task::JoinHandle;
+
+/// Called with the topic and text of every received message. Both strings
+/// are only valid for the duration of the call.
+pub type MessageCallback =
+    extern "C" fn(topic: *const c_char, message: *const c_char, user_data: *mut c_void);
+
+pub struct Client {
+    runtime: Runtime,
+    config: Config,
+    /// Calls the message callback; waited for on stop.
+    callbacks: Option<JoinHandle<()>>,
+}
+
+struct UserData(*mut c_void);
+
+// The pointer is never dereferenced here, only handed back to the callback.
+unsafe impl Send for UserData {}
+
+unsafe fn to_string(ptr: *const c_char) -> Option<String> {
+    if ptr.is_null() {
+        return None;
+    }
+    CStr::from_ptr(ptr).to_str().ok().map(String::from)
+}
+
+/// Starts a client in the background and returns a handle to it, or NULL if
+/// the arguments are invalid. `server`, `token` and `callback` may be NULL.
+///
+/// # Safety
+///
+/// All non-NULL string arguments must point to valid NUL-terminated strings.
+/// `user_data` must stay valid until `n2c_stop` returns.
+#[no_mangle]
+pub unsafe extern "C" fn n2c_start(
+    server: *const c_char,
+    topic: *const c_char,
+    token: *const c_char,
+    callback: Option<MessageCallback>,
+    user_data: *mut c_void,
+) -> *mut Client {
+    let Some(topic) = to_string(topic) else {
+        return std::ptr::null_mut();
+    };
+    let mut config = Config::new(topic);
+    if let Some(server) = to_string(server) {
+        config.server = server;
+    }
+    config.token = to_string(token).filter(|t| !t.is_empty());
+
+    let Ok(runtime) = Runtime::new() else {
+        return std::ptr::null_mut();
+    };
+
+    let events = EventBus::default();
+    let callbacks = callback.map(|callback| {
+        let mut rx = events.subscribe();
+        let user_data = UserData(user_data);
+        runtime.spawn(async move {
+            let user_data = user_data;
+            loop {
+                let event = match rx.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(n)) => {
+                        debug!("message callback lagged behind, skipped {} events", n);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return,
+                };
+                if let Event::MessageReceived { topic, message } = event {
+                    let (Ok(topic), Ok(message)) = (CString::new(topic), CString::new(message))
+                    else {
+                        continue;
+                    };
+                    callback(topic.as_ptr(), message.as_ptr(), user_data.0);
+                }
+            }
+        })
+    });
+
+    let run_config = config.clone();
+    runtime.spawn(async move {
+        if let Err(e) = run(&run_config, &events).await {
+            error!("{}. Giving up.", e);
+        }
+    });
+
+    Box::into_raw(Box::new(Client {
+        runtime,
+        config,
+        callbacks,
+    }))
+}
+
+/// Publishes `text` to the client's topic, blocking until the server has
+/// accepted it. Returns 0 on success and -1 on failure.
+///
+/// # Safety
+///
+/// `client` must be a handle returned by `n2c_start` that has not been
+/// stopped, and `text` a valid NUL-terminated string. Must not be called from
+/// inside the message callback.
+#[no_mangle]
+pub unsafe extern "C" fn n2c_publish(client: *mut Client, text: *const c_char) -> c_int {
+    let (Some(client), Some(text)) = (client.as_ref(), to_string(text)) else {
+        return -1;
+    };
+    match client.runtime.block_on(publish(&client.config, &text)) {
+        Ok(()) => 0,
+        Err(e) => {
+            error!("Failed to publish: {}", e);
+            -1
+        }
+    }
+}
+
+/// Stops the client and frees the handle. Passing NULL is a no-op. Once it
+/// returns, the callback is not running and won't be called again.
+///
+/// # Safety
+///
+/// `client` must be NULL or a handle returned by `n2c_start` that has not
+/// been stopped yet. Must not be called from inside the message callback.
+#[no_mangle]
+pub unsafe extern "C" fn n2c_stop(client: *mut Client) {
+    if client.is_null() {
+        return;
+    }
+    let Client {
+        runtime, callbacks, ..
+    } = *Box::from_raw(client);
+    if let Some(callbacks) = callbacks {
+        callbacks.abort();
+        // Waits out a call in progress; the task stops before the next.
+        let _ = runtime.block_on(callbacks);
+    }
+    runtime.shutdown_background();
+}
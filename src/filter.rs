@@ -0,0 +1,104 @@
+use crate::config::Config;
+use crate::message::WSMessage;
+use log::{debug, info};
+use std::time::Duration;
+
+/// What ntfy means by a message without a priority.
+pub(crate) const DEFAULT_PRIORITY: u8 = 3;
+
+/// Decides whether a message should reach the sinks. Shared by every
+/// transport, including the browser build.
+pub(crate) fn accepts(config: &Config, msg: &WSMessage) -> bool {
+    msg.event == "message"
+        && config.subscribes(&msg.topic)
+        && msg.message.is_some()
+        && matches_tags(config, msg)
+        && urgent_enough(config, msg)
+        && matches_title(config, msg)
+        && recent(config, msg)
+}
+
+/// [`Config::replay_window`]. ntfy stamps every message; those from other
+/// sources without a time are let through.
+fn recent(config: &Config, msg: &WSMessage) -> bool {
+    let (Some(window), Some(time)) = (config.replay_window, msg.time) else {
+        return true;
+    };
+    if !within(window, time) {
+        info!(
+            "Ignoring message {} sent at {}, outside REPLAY_WINDOW",
+            msg.id.as_deref().unwrap_or_default(),
+            time
+        );
+        return false;
+    }
+    true
+}
+
+/// Whether `time`, in Unix seconds, is at most `window` from now, either
+/// way, to allow for clocks that are off.
+pub(crate) fn within(window: Duration, time: u64) -> bool {
+    now().abs_diff(time) <= window.as_secs()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// `SystemTime` isn't available in the browser.
+#[cfg(target_arch = "wasm32")]
+fn now() -> u64 {
+    (js_sys::Date::now() / 1000.0) as u64
+}
+
+fn matches_title(config: &Config, msg: &WSMessage) -> bool {
+    let Some(title_match) = &config.title_match else {
+        return true;
+    };
+    let title = msg.title.as_deref().unwrap_or_default();
+    if !title_match.pattern.is_match(title) {
+        debug!("message titled {:?} filtered out", title);
+        return false;
+    }
+    true
+}
+
+fn urgent_enough(config: &Config, msg: &WSMessage) -> bool {
+    let Some(min) = config.min_priority else {
+        return true;
+    };
+    let priority = msg.priority.unwrap_or(DEFAULT_PRIORITY);
+    if priority < min {
+        debug!("message of priority {} filtered out", priority);
+        return false;
+    }
+    true
+}
+
+/// `TAGS_ANY` and `TAGS_NONE`; tags compare case-insensitively.
+fn matches_tags(config: &Config, msg: &WSMessage) -> bool {
+    let tags = msg.tags.as_deref().unwrap_or_default();
+    let has = |wanted: &String| tags.iter().any(|tag| tag.eq_ignore_ascii_case(wanted));
+    let accepted = (config.tags_any.is_empty() || config.tags_any.iter().any(has))
+        && !config.tags_none.iter().any(has);
+    if !accepted {
+        debug!("message with tags {:?} filtered out", tags);
+    }
+    accepted
+}
+
+/// Messages tagged `dev:<name>` are only for the devices named; untagged
+/// ones are for all of them. Unlike the other filters, this one only keeps
+/// messages from the local sinks; relays pass on messages for any device.
+pub(crate) fn for_device(config: &Config, msg: &WSMessage) -> bool {
+    let mut targets = msg
+        .tags
+        .iter()
+        .flatten()
+        .filter_map(|tag| tag.strip_prefix("dev:"))
+        .peekable();
+    targets.peek().is_none() || targets.any(|device| device.eq_ignore_ascii_case(&config.device))
+}
@@ -0,0 +1,50 @@
+use crate::config::{Config, ServerKind};
+use crate::error::{AuthError, ConnectError, Error, Result};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use tungstenite::client::IntoClientRequest;
+use tungstenite::handshake::client::Request;
+
+/// Builds the WebSocket upgrade request, including credentials. With
+/// `since`, ntfy first sends the messages after that id or time.
+pub(crate) fn request(config: &Config, since: Option<&str>) -> Result<Request> {
+    #[cfg(feature = "async")]
+    let url = config.subscription_url(&crate::unifiedpush::topics());
+    #[cfg(not(feature = "async"))]
+    let url = config.url();
+    let mut url = url.map_err(ConnectError::from)?;
+    if let (Some(since), ServerKind::Ntfy) = (since, config.kind) {
+        url.query_pairs_mut().append_pair("since", since);
+    }
+    let mut header = match config.kind {
+        ServerKind::Ntfy => config
+            .authorization()?
+            .map(|value| ("Authorization", value)),
+        ServerKind::Gotify => config.current_token()?.map(|token| ("X-Gotify-Key", token)),
+    };
+    // For proxies that strip the header from upgrade requests.
+    if config.auth_query && config.kind == ServerKind::Ntfy {
+        if let Some((_, value)) = header.take() {
+            url.query_pairs_mut()
+                .append_pair("auth", &URL_SAFE_NO_PAD.encode(value));
+        }
+    }
+    let mut request = url.into_client_request().map_err(error)?;
+    if let Some((name, value)) = header {
+        request
+            .headers_mut()
+            .insert(name, value.parse().map_err(|_| AuthError::InvalidToken)?);
+    }
+    Ok(request)
+}
+
+/// Classifies a handshake failure, treating 401/403 responses as auth errors.
+pub(crate) fn error(e: tungstenite::Error) -> Error {
+    if let tungstenite::Error::Http(response) = &e {
+        let status = response.status().as_u16();
+        if status == 401 || status == 403 {
+            return AuthError::Rejected(status).into();
+        }
+    }
+    ConnectError::Handshake(Box::new(e)).into()
+}
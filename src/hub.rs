@@ -0,0 +1,378 @@
+//! LAN hub: re-serves received messages over the same protocols as ntfy, so
+//! other instances can point `SERVER` at this machine instead of each holding
+//! their own connection to the upstream server.
+//!
+//! Messages are relayed as they arrived, before reassembly, decryption,
+//! filters and transforms, so each client applies its own like it would
+//! upstream. Besides the WebSocket, the `/json` and `/sse` streams and
+//! `/json?poll=1` are served, and `since=` replays the recent messages, so
+//! catching up and falling back to SSE work like against ntfy.
+
+use crate::api::{self, constant_time_eq};
+use crate::config::HubConfig;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use futures_util::{SinkExt, StreamExt};
+use log::{debug, error, info};
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio::time::{self, Duration};
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+use tokio_tungstenite::tungstenite::protocol::{Message, Role};
+use tokio_tungstenite::WebSocketStream;
+
+const CLIENT_BUFFER: usize = 16;
+/// Messages kept for `since=`.
+const RECENT: usize = 64;
+const MAX_HEADERS: usize = 64;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+// Well below the default client TIMEOUT so idle clients don't reconnect.
+const PING_INTERVAL: Duration = Duration::from_secs(45);
+
+/// A serialized message, sent to the clients subscribed to its topic.
+pub(crate) struct Frame {
+    pub topic: String,
+    pub id: Option<String>,
+    pub time: Option<u64>,
+    pub text: String,
+}
+
+struct Hub {
+    topics: Vec<String>,
+    token: Option<String>,
+    recent: Mutex<VecDeque<Arc<Frame>>>,
+    tx: broadcast::Sender<Arc<Frame>>,
+}
+
+/// The parts of a request the hub looks at.
+struct Request {
+    /// The requested topics.
+    topics: Vec<String>,
+    /// `ws`, `json` or `sse`.
+    endpoint: String,
+    since: Option<String>,
+    poll: bool,
+    authorization: Option<String>,
+    websocket_key: Option<String>,
+}
+
+/// Starts the hub serving `topics` and returns the channel that messages
+/// should be broadcast on.
+pub(crate) fn spawn(config: &HubConfig, topics: Vec<String>) -> broadcast::Sender<Arc<Frame>> {
+    let (tx, mut recorded) = broadcast::channel(CLIENT_BUFFER);
+    let addr = config.addr;
+    let hub = Arc::new(Hub {
+        topics,
+        token: config.token.clone(),
+        recent: Mutex::new(VecDeque::new()),
+        tx: tx.clone(),
+    });
+    let recorder = hub.clone();
+    tokio::spawn(async move {
+        loop {
+            match recorded.recv().await {
+                Ok(frame) => {
+                    let mut recent = recorder.recent.lock().unwrap();
+                    if recent.len() >= RECENT {
+                        recent.pop_front();
+                    }
+                    recent.push_back(frame);
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(broadcast::error::RecvError::Closed) => return,
+            }
+        }
+    });
+    tokio::spawn(async move {
+        let listener = match TcpListener::bind(addr).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!("Failed to start hub on {}: {}", addr, e);
+                return;
+            }
+        };
+        info!("hub listening on {}", addr);
+        loop {
+            match listener.accept().await {
+                Ok((stream, peer)) => {
+                    let hub = hub.clone();
+                    tokio::spawn(async move {
+                        match hub.serve(stream).await {
+                            Ok(()) => debug!("hub client {} disconnected", peer),
+                            Err(e) => debug!("hub client {} failed: {}", peer, e),
+                        }
+                    });
+                }
+                Err(e) => error!("hub accept failed: {}", e),
+            }
+        }
+    });
+    tx
+}
+
+impl Hub {
+    async fn serve(&self, stream: TcpStream) -> io::Result<()> {
+        let mut reader = BufReader::new(stream);
+        let head = time::timeout(REQUEST_TIMEOUT, read_head(&mut reader)).await;
+        let Ok(Some(request)) = head.unwrap_or(Ok(None)) else {
+            return respond(reader.get_mut(), "400 Bad Request").await;
+        };
+        if !request
+            .topics
+            .iter()
+            .all(|topic| self.topics.contains(topic))
+        {
+            return respond(reader.get_mut(), "404 Not Found").await;
+        }
+        if let Some(token) = &self.token {
+            let expected = format!("Bearer {token}");
+            let authorized = request
+                .authorization
+                .as_ref()
+                .is_some_and(|auth| constant_time_eq(auth.as_bytes(), expected.as_bytes()));
+            if !authorized {
+                return respond(reader.get_mut(), "401 Unauthorized").await;
+            }
+        }
+        // Subscribed before looking at the recent ones, so nothing falls in
+        // between; clients drop what they get twice.
+        let rx = self.tx.subscribe();
+        let missed = request
+            .since
+            .as_deref()
+            .map_or(Vec::new(), |since| self.missed(&request.topics, since));
+        // Everything but the WebSocket is written as is after the header.
+        let mut stream = reader.into_inner();
+        match (request.endpoint.as_str(), request.poll) {
+            ("ws", _) => {
+                let Some(key) = &request.websocket_key else {
+                    return respond(&mut stream, "400 Bad Request").await;
+                };
+                let response = format!(
+                    "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\
+                     Connection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+                    derive_accept_key(key.as_bytes())
+                );
+                stream.write_all(response.as_bytes()).await?;
+                let ws_stream = WebSocketStream::from_raw_socket(stream, Role::Server, None).await;
+                info!("hub client connected");
+                serve_ws(ws_stream, &request.topics, missed, rx)
+                    .await
+                    .map_err(io::Error::other)
+            }
+            ("json", true) => {
+                let body: String = missed
+                    .iter()
+                    .map(|frame| format!("{}\n", frame.text))
+                    .collect();
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/x-ndjson\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                stream.write_all(response.as_bytes()).await?;
+                stream.shutdown().await
+            }
+            ("json" | "sse", false) => {
+                let sse = request.endpoint == "sse";
+                let content_type = if sse {
+                    "text/event-stream"
+                } else {
+                    "application/x-ndjson"
+                };
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: {content_type}\r\n\
+                     Cache-Control: no-cache\r\nConnection: close\r\n\r\n"
+                );
+                stream.write_all(response.as_bytes()).await?;
+                info!("hub client connected over {}", request.endpoint);
+                serve_stream(stream, sse, &request.topics, missed, rx).await
+            }
+            _ => respond(&mut stream, "404 Not Found").await,
+        }
+    }
+
+    /// The recent messages on `topics` after `since`, a message id or a
+    /// Unix time, or all of them for `all` or an id no longer kept.
+    fn missed(&self, topics: &[String], since: &str) -> Vec<Arc<Frame>> {
+        let recent = self.recent.lock().unwrap();
+        let start = match recent
+            .iter()
+            .position(|frame| frame.id.as_deref() == Some(since))
+        {
+            Some(at) => at + 1,
+            None => 0,
+        };
+        let after = since.parse::<u64>().ok();
+        recent
+            .iter()
+            .skip(start)
+            .filter(|frame| topics.contains(&frame.topic))
+            .filter(|frame| after.is_none_or(|after| frame.time.is_some_and(|t| t >= after)))
+            .cloned()
+            .collect()
+    }
+}
+
+async fn serve_ws(
+    mut ws_stream: WebSocketStream<TcpStream>,
+    topics: &[String],
+    missed: Vec<Arc<Frame>>,
+    mut rx: broadcast::Receiver<Arc<Frame>>,
+) -> Result<(), tokio_tungstenite::tungstenite::Error> {
+    ws_stream
+        .send(Message::text(event("open", topics).to_string()))
+        .await?;
+    for frame in missed {
+        ws_stream.send(Message::text(frame.text.clone())).await?;
+    }
+
+    let mut ping_interval = time::interval(PING_INTERVAL);
+    loop {
+        tokio::select! {
+            msg = rx.recv() => match msg {
+                Ok(frame) if topics.contains(&frame.topic) => {
+                    ws_stream.send(Message::text(frame.text.clone())).await?;
+                }
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    debug!("hub client lagged behind, skipped {} messages", n);
+                }
+                Err(broadcast::error::RecvError::Closed) => return Ok(()),
+            },
+            msg = ws_stream.next() => match msg {
+                Some(Ok(Message::Close(_))) | None => return Ok(()),
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e),
+            },
+            _ = ping_interval.tick() => {
+                ws_stream.send(Message::Ping(Default::default())).await?;
+            }
+        }
+    }
+}
+
+/// Like [`serve_ws`] for ntfy's `/json` and `/sse` streams, which have no
+/// pings, so keepalive events take their place.
+async fn serve_stream(
+    mut stream: TcpStream,
+    sse: bool,
+    topics: &[String],
+    missed: Vec<Arc<Frame>>,
+    mut rx: broadcast::Receiver<Arc<Frame>>,
+) -> io::Result<()> {
+    let line = |text: &str| {
+        if sse {
+            format!("data: {text}\n\n")
+        } else {
+            format!("{text}\n")
+        }
+    };
+    stream
+        .write_all(line(&event("open", topics).to_string()).as_bytes())
+        .await?;
+    for frame in missed {
+        stream.write_all(line(&frame.text).as_bytes()).await?;
+    }
+
+    let mut keepalive_interval = time::interval(PING_INTERVAL);
+    keepalive_interval.reset();
+    loop {
+        let text = tokio::select! {
+            msg = rx.recv() => match msg {
+                Ok(frame) if topics.contains(&frame.topic) => line(&frame.text),
+                Ok(_) => continue,
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    debug!("hub client lagged behind, skipped {} messages", n);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => return Ok(()),
+            },
+            _ = keepalive_interval.tick() => line(&event("keepalive", topics).to_string()),
+        };
+        stream.write_all(text.as_bytes()).await?;
+    }
+}
+
+/// Reads the request line and headers. Returns `None` for malformed requests.
+async fn read_head(reader: &mut BufReader<TcpStream>) -> io::Result<Option<Request>> {
+    let mut line = String::new();
+    if !api::read_line(reader, &mut line).await? {
+        return Ok(None);
+    }
+    let mut parts = line.split_whitespace();
+    let (Some("GET"), Some(target)) = (parts.next(), parts.next()) else {
+        return Ok(None);
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    // Like ntfy, `/<topic>,<topic>/ws` subscribes to several at once.
+    let Some((list, endpoint)) = path.strip_prefix('/').and_then(|path| path.split_once('/'))
+    else {
+        return Ok(None);
+    };
+    let mut request = Request {
+        topics: list.split(',').map(str::to_string).collect(),
+        endpoint: endpoint.to_string(),
+        since: None,
+        poll: false,
+        authorization: None,
+        websocket_key: None,
+    };
+    for (name, value) in url::form_urlencoded::parse(query.as_bytes()) {
+        match &*name {
+            "since" => request.since = Some(value.into_owned()),
+            "poll" => request.poll = value == "1",
+            // Like ntfy, for `AUTH_QUERY`: the header's value in base64.
+            "auth" => {
+                request.authorization = URL_SAFE_NO_PAD
+                    .decode(value.as_bytes())
+                    .ok()
+                    .and_then(|auth| String::from_utf8(auth).ok());
+            }
+            _ => {}
+        }
+    }
+
+    for _ in 0..MAX_HEADERS {
+        line.clear();
+        if !api::read_line(reader, &mut line).await? {
+            return Ok(None);
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            return Ok(Some(request));
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            return Ok(None);
+        };
+        let value = value.trim().to_string();
+        if name.eq_ignore_ascii_case("authorization") {
+            request.authorization = Some(value);
+        } else if name.eq_ignore_ascii_case("sec-websocket-key") {
+            request.websocket_key = Some(value);
+        }
+    }
+    Ok(None)
+}
+
+async fn respond(stream: &mut TcpStream, status: &str) -> io::Result<()> {
+    let response = format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+fn event(event: &str, topics: &[String]) -> serde_json::Value {
+    serde_json::json!({ "event": event, "topic": topics.join(","), "time": now() })
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
@@ -0,0 +1,21 @@
+//! Randomized timer delays, so a fleet of instances sharing one config
+//! doesn't hit the server in synchronized bursts.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+/// How far [`around`] may move a period, as a fraction of it.
+const JITTER: f64 = 0.1;
+
+/// A random duration in `[0, max)`.
+pub(crate) fn random(max: Duration) -> Duration {
+    // Every `RandomState` has fresh keys, seeded from the OS RNG.
+    let bits = RandomState::new().build_hasher().finish() >> 11;
+    max.mul_f64(bits as f64 / (1u64 << 53) as f64)
+}
+
+/// `period`, moved by up to ±10% of itself.
+pub(crate) fn around(period: Duration) -> Duration {
+    period.mul_f64(1.0 - JITTER) + random(period.mul_f64(2.0 * JITTER))
+}
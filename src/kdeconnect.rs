@@ -0,0 +1,165 @@
+//! KDE Connect bridge over D-Bus, enabled with the `kdeconnect` feature.
+//!
+//! Received messages are pushed to the clipboards of paired devices, and text
+//! shared from a device to this machine is republished to the ntfy topic.
+//! KDE Connect does not expose incoming clipboard syncs on D-Bus, so on the
+//! phone use "Share" → KDE Connect rather than "Send clipboard".
+
+use crate::config::{Config, Vars};
+use crate::error::{ClipboardError, ConfigError};
+use crate::publish;
+use futures_util::StreamExt;
+use log::{debug, error, info};
+use tokio::fs;
+use tokio::time::{self, Duration};
+use url::Url;
+use zbus::message::Type as MessageType;
+use zbus::{Connection, MatchRule, MessageStream, Proxy};
+
+const SERVICE: &str = "org.kde.kdeconnect";
+const DEVICES_PATH: &str = "/modules/kdeconnect/devices";
+/// Shared text larger than this is most likely a file, not clipboard content.
+const MAX_SHARE: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct KdeConnectConfig {
+    /// Device ids to bridge; `None` means every reachable paired device.
+    pub devices: Option<Vec<String>>,
+    /// Republish text shared from devices to the ntfy topic.
+    pub republish: bool,
+}
+
+impl KdeConnectConfig {
+    /// Reads `KDECONNECT`, `KDECONNECT_DEVICES` and `KDECONNECT_REPUBLISH`.
+    pub(crate) fn from_vars(vars: &Vars) -> Result<Option<Self>, ConfigError> {
+        if !vars.var("KDECONNECT").is_ok_and(|v| v == "1") {
+            return Ok(None);
+        }
+        let devices = vars
+            .var("KDECONNECT_DEVICES")
+            .ok()
+            .filter(|d| !d.is_empty())
+            .map(|d| d.split(',').map(|id| id.trim().to_string()).collect());
+        let republish = match vars.var("KDECONNECT_REPUBLISH") {
+            Ok(value) => match value.as_str() {
+                "1" => true,
+                "0" => false,
+                _ => {
+                    return Err(ConfigError::InvalidValue {
+                        name: "KDECONNECT_REPUBLISH",
+                        value,
+                    })
+                }
+            },
+            Err(_) => true,
+        };
+        Ok(Some(KdeConnectConfig { devices, republish }))
+    }
+
+    fn bridges(&self, device: &str) -> bool {
+        self.devices
+            .as_ref()
+            .is_none_or(|devices| devices.iter().any(|d| d == device))
+    }
+}
+
+/// Starts republishing text shared from devices, if enabled.
+pub(crate) fn spawn(config: &Config, kde: &KdeConnectConfig) {
+    if !kde.republish {
+        return;
+    }
+    let config = config.clone();
+    let kde = kde.clone();
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = listen(&config, &kde).await {
+                error!("KDE Connect listener error: {}. Reconnecting...", e);
+            }
+            time::sleep(Duration::from_secs(5)).await;
+        }
+    });
+}
+
+/// Sends `content` to the clipboard of every bridged device.
+pub(crate) async fn send(
+    conn: &Connection,
+    kde: &KdeConnectConfig,
+    content: &str,
+) -> Result<(), ClipboardError> {
+    let daemon = Proxy::new(
+        conn,
+        SERVICE,
+        "/modules/kdeconnect",
+        "org.kde.kdeconnect.daemon",
+    )
+    .await?;
+    // devices(onlyReachable, onlyPaired)
+    let devices: Vec<String> = daemon.call("devices", &(true, true)).await?;
+    for device in devices.iter().filter(|d| kde.bridges(d)) {
+        let path = format!("{}/{}/clipboard", DEVICES_PATH, device);
+        let clipboard = Proxy::new(
+            conn,
+            SERVICE,
+            path.as_str(),
+            "org.kde.kdeconnect.device.clipboard",
+        )
+        .await?;
+        clipboard.call_method("sendClipboard", &(content,)).await?;
+        debug!(
+            "sent {} bytes to KDE Connect device {}",
+            content.len(),
+            device
+        );
+    }
+    Ok(())
+}
+
+async fn listen(config: &Config, kde: &KdeConnectConfig) -> zbus::Result<()> {
+    let conn = Connection::session().await?;
+    let rule = MatchRule::builder()
+        .msg_type(MessageType::Signal)
+        .sender(SERVICE)?
+        .interface("org.kde.kdeconnect.device.share")?
+        .member("shareReceived")?
+        .path_namespace(DEVICES_PATH)?
+        .build();
+    let mut stream = MessageStream::for_match_rule(rule, &conn, None).await?;
+    info!("listening for KDE Connect shares");
+    while let Some(msg) = stream.next().await {
+        let msg = msg?;
+        let header = msg.header();
+        // /modules/kdeconnect/devices/<id>/share
+        let Some(device) = header
+            .path()
+            .and_then(|p| p.as_str().strip_prefix(DEVICES_PATH))
+            .and_then(|p| p.trim_start_matches('/').split('/').next())
+        else {
+            continue;
+        };
+        if !kde.bridges(device) {
+            continue;
+        }
+        let url: String = msg.body().deserialize()?;
+        debug!("KDE Connect device {} shared {}", device, url);
+        let Some(content) = shared_text(&url).await else {
+            continue;
+        };
+        if let Err(e) = publish::publish(config, &content).await {
+            error!("Failed to republish KDE Connect share: {}", e);
+        }
+    }
+    Ok(())
+}
+
+/// KDE Connect stores shared text in a temporary `.txt` file; anything else
+/// is a real file share and is left alone.
+async fn shared_text(url: &str) -> Option<String> {
+    let path = Url::parse(url).ok()?.to_file_path().ok()?;
+    if path.extension().is_none_or(|ext| ext != "txt") {
+        return None;
+    }
+    if fs::metadata(&path).await.ok()?.len() > MAX_SHARE {
+        return None;
+    }
+    fs::read_to_string(&path).await.ok()
+}
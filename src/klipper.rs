@@ -0,0 +1,33 @@
+//! Clipboard backend through KDE's Klipper, enabled with the `klipper`
+//! feature.
+//!
+//! On KDE a selection set by `xclip` is gone once it exits unless Klipper
+//! happens to grab it, and then often misses it in its history. Handing the
+//! content to Klipper over D-Bus makes it the owner, so both work reliably.
+
+use crate::error::ClipboardError;
+use crate::session;
+use tokio::sync::OnceCell;
+use zbus::Connection;
+
+static CONNECTION: OnceCell<Connection> = OnceCell::const_new();
+
+/// Whether this is a KDE Plasma session.
+pub(crate) fn detected() -> bool {
+    session::var("XDG_CURRENT_DESKTOP")
+        .is_some_and(|desktops| desktops.split(':').any(|d| d.eq_ignore_ascii_case("KDE")))
+}
+
+pub(crate) async fn set_clip(content: &str) -> Result<(), ClipboardError> {
+    let connection = CONNECTION.get_or_try_init(Connection::session).await?;
+    connection
+        .call_method(
+            Some("org.kde.klipper"),
+            "/klipper",
+            Some("org.kde.klipper.klipper"),
+            "setClipboardContents",
+            &(content,),
+        )
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,354 @@
+//! mDNS discovery and direct LAN transfer, enabled with the `mdns` feature.
+//!
+//! Instances sharing `LAN_SECRET` and topic find each other over mDNS. When a
+//! message is larger than `LAN_THRESHOLD` and a peer is known, only a short
+//! signal goes through ntfy and the receivers fetch the content straight from
+//! the sender over TCP, authenticated with HMAC-SHA256 of the shared secret.
+
+use crate::config::{self, Config, Vars};
+use crate::error::ConfigError;
+use crate::message::WSMessage;
+use hmac::{Hmac, KeyInit, Mac};
+use log::{debug, error, info};
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io;
+use std::net::SocketAddr;
+use std::process;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::OnceCell;
+use tokio::time::{self, Duration, Instant};
+
+const SERVICE_TYPE: &str = "_ntfy2clip._tcp.local.";
+const SIGNAL_PREFIX: &str = "ntfy2clip-direct:";
+const OFFER_TTL: Duration = Duration::from_secs(600);
+const TRANSFER_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_TRANSFER: usize = 64 * 1024 * 1024;
+/// Request and response lines hold an id, a length and a MAC, well under this.
+const MAX_LINE: u64 = 256;
+
+#[derive(Debug, Clone)]
+pub struct LanConfig {
+    pub secret: String,
+    pub port: u16,
+    /// Messages larger than this many bytes are sent directly when possible.
+    pub threshold: usize,
+}
+
+impl LanConfig {
+    /// Reads `LAN_SECRET`, `LAN_PORT` and `LAN_THRESHOLD`.
+    pub(crate) fn from_vars(vars: &Vars) -> Result<Option<Self>, ConfigError> {
+        let Some(secret) = vars.var("LAN_SECRET").ok().filter(|s| !s.is_empty()) else {
+            return Ok(None);
+        };
+        let parse = |name, default: usize| match vars.var(name) {
+            Ok(value) => value
+                .parse()
+                .map_err(|_| ConfigError::InvalidValue { name, value }),
+            Err(_) => Ok(default),
+        };
+        let port = parse("LAN_PORT", 0)?;
+        Ok(Some(LanConfig {
+            secret,
+            port: u16::try_from(port).map_err(|_| ConfigError::InvalidValue {
+                name: "LAN_PORT",
+                value: port.to_string(),
+            })?,
+            threshold: parse("LAN_THRESHOLD", 4096)?,
+        }))
+    }
+}
+
+struct Peer {
+    instance: String,
+    addr: SocketAddr,
+}
+
+pub(crate) struct Lan {
+    secret: String,
+    instance: String,
+    offers: Mutex<HashMap<String, (Arc<str>, Instant)>>,
+    peers: Mutex<HashMap<String, Peer>>,
+    _daemon: ServiceDaemon,
+}
+
+static LAN: OnceCell<Option<Arc<Lan>>> = OnceCell::const_new();
+
+/// Starts the listener, advertisement and discovery on first use.
+async fn get(config: &Config) -> Option<Arc<Lan>> {
+    let lan = config.lan.as_ref()?;
+    LAN.get_or_init(|| async {
+        match start(lan, &config.topic).await {
+            Ok(lan) => Some(lan),
+            Err(e) => {
+                error!("Failed to start LAN discovery: {}", e);
+                None
+            }
+        }
+    })
+    .await
+    .clone()
+}
+
+async fn start(config: &LanConfig, topic: &str) -> io::Result<Arc<Lan>> {
+    let listener = TcpListener::bind(("0.0.0.0", config.port)).await?;
+    let port = listener.local_addr()?.port();
+    let host = config::hostname();
+    let instance = format!("{}-{}", host, process::id());
+    // Peers only need to agree on secret and topic, without advertising either.
+    let group = mac(&config.secret, format!("group:{topic}").as_bytes());
+    let group = group[..16].to_string();
+
+    let daemon = ServiceDaemon::new().map_err(io::Error::other)?;
+    let properties = [("instance", instance.as_str()), ("group", group.as_str())];
+    let service = ServiceInfo::new(
+        SERVICE_TYPE,
+        &instance,
+        &format!("{host}.local."),
+        (),
+        port,
+        &properties[..],
+    )
+    .map_err(io::Error::other)?
+    .enable_addr_auto();
+    daemon.register(service).map_err(io::Error::other)?;
+    let browser = daemon.browse(SERVICE_TYPE).map_err(io::Error::other)?;
+    info!("LAN discovery started as {} on port {}", instance, port);
+
+    let lan = Arc::new(Lan {
+        secret: config.secret.clone(),
+        instance,
+        offers: Mutex::new(HashMap::new()),
+        peers: Mutex::new(HashMap::new()),
+        _daemon: daemon,
+    });
+
+    let discovery = lan.clone();
+    tokio::spawn(async move {
+        while let Ok(event) = browser.recv_async().await {
+            match event {
+                ServiceEvent::ServiceResolved(service) => {
+                    let instance = service.get_property_val_str("instance");
+                    let addr = service.get_addresses().iter().next();
+                    let (Some(instance), Some(addr)) = (instance, addr) else {
+                        continue;
+                    };
+                    if service.get_property_val_str("group") != Some(group.as_str())
+                        || instance == discovery.instance
+                    {
+                        continue;
+                    }
+                    let addr = SocketAddr::new(addr.to_ip_addr(), service.get_port());
+                    info!("discovered LAN peer {} at {}", instance, addr);
+                    let peer = Peer {
+                        instance: instance.to_string(),
+                        addr,
+                    };
+                    discovery
+                        .peers
+                        .lock()
+                        .unwrap()
+                        .insert(service.get_fullname().to_string(), peer);
+                }
+                ServiceEvent::ServiceRemoved(_, fullname) => {
+                    if let Some(peer) = discovery.peers.lock().unwrap().remove(&fullname) {
+                        info!("LAN peer {} went away", peer.instance);
+                    }
+                }
+                _ => {}
+            }
+        }
+    });
+
+    let server = lan.clone();
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, peer)) => {
+                    let server = server.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = time::timeout(TRANSFER_TIMEOUT, server.serve(stream))
+                            .await
+                            .unwrap_or(Err(io::ErrorKind::TimedOut.into()))
+                        {
+                            debug!("LAN transfer to {} failed: {}", peer, e);
+                        }
+                    });
+                }
+                Err(e) => error!("LAN accept failed: {}", e),
+            }
+        }
+    });
+
+    Ok(lan)
+}
+
+impl Lan {
+    async fn serve(&self, stream: TcpStream) -> io::Result<()> {
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        (&mut reader).take(MAX_LINE).read_line(&mut line).await?;
+        let mut parts = line.split_whitespace();
+        let (true, Some("GET"), Some(id), Some(request_mac)) = (
+            line.ends_with('\n'),
+            parts.next(),
+            parts.next(),
+            parts.next(),
+        ) else {
+            return reader.get_mut().write_all(b"ERR bad request\n").await;
+        };
+        if !verify(&self.secret, format!("get:{id}").as_bytes(), request_mac) {
+            return reader.get_mut().write_all(b"ERR unauthorized\n").await;
+        }
+        let content = self.offers.lock().unwrap().get(id).map(|(c, _)| c.clone());
+        let Some(content) = content else {
+            return reader.get_mut().write_all(b"ERR unknown offer\n").await;
+        };
+        let header = format!(
+            "OK {} {}\n",
+            content.len(),
+            mac(&self.secret, content.as_bytes())
+        );
+        let stream = reader.get_mut();
+        stream.write_all(header.as_bytes()).await?;
+        stream.write_all(content.as_bytes()).await?;
+        stream.shutdown().await
+    }
+
+    async fn fetch(&self, addr: SocketAddr, id: &str) -> io::Result<String> {
+        let stream = TcpStream::connect(addr).await?;
+        let mut reader = BufReader::new(stream);
+        let request = format!(
+            "GET {} {}\n",
+            id,
+            mac(&self.secret, format!("get:{id}").as_bytes())
+        );
+        reader.get_mut().write_all(request.as_bytes()).await?;
+
+        let mut line = String::new();
+        (&mut reader).take(MAX_LINE).read_line(&mut line).await?;
+        if !line.ends_with('\n') {
+            return Err(io::Error::other("peer sent a malformed response"));
+        }
+        let mut parts = line.split_whitespace();
+        let (Some("OK"), Some(len), Some(content_mac)) = (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(io::Error::other(format!("peer refused: {}", line.trim())));
+        };
+        let len: usize = len.parse().map_err(io::Error::other)?;
+        if len > MAX_TRANSFER {
+            return Err(io::Error::other("transfer too large"));
+        }
+        let mut content = vec![0; len];
+        reader.read_exact(&mut content).await?;
+        if !verify(&self.secret, &content, content_mac) {
+            return Err(io::Error::other("content failed authentication"));
+        }
+        String::from_utf8(content).map_err(io::Error::other)
+    }
+}
+
+/// Offers `content` for direct transfer and returns the signal to publish
+/// instead, or `None` if direct transfer isn't configured, the content is
+/// small, or no peer is currently known.
+pub(crate) async fn offer(config: &Config, content: &str) -> Option<String> {
+    if content.len() <= config.lan.as_ref()?.threshold {
+        return None;
+    }
+    let lan = get(config).await?;
+    if lan.peers.lock().unwrap().is_empty() {
+        return None;
+    }
+    let mut id = [0u8; 16];
+    getrandom::fill(&mut id).ok()?;
+    let id = hex(&id);
+
+    let mut offers = lan.offers.lock().unwrap();
+    offers.retain(|_, (_, created)| created.elapsed() < OFFER_TTL);
+    offers.insert(id.clone(), (content.into(), Instant::now()));
+    info!("offering {} bytes for direct LAN transfer", content.len());
+    Some(format!("{}{}/{}", SIGNAL_PREFIX, lan.instance, id))
+}
+
+/// Replaces a direct-transfer signal with the content fetched from the
+/// sending peer. Returns `None` if the message should be dropped.
+pub(crate) async fn resolve(config: &Config, mut msg: WSMessage) -> Option<WSMessage> {
+    let Some(signal) = msg
+        .message
+        .as_deref()
+        .and_then(|m| m.strip_prefix(SIGNAL_PREFIX))
+    else {
+        return Some(msg);
+    };
+    let lan = get(config).await?;
+    let (instance, id) = signal.split_once('/')?;
+    if instance == lan.instance {
+        debug!("ignoring our own direct transfer signal");
+        return None;
+    }
+    let addr = lan
+        .peers
+        .lock()
+        .unwrap()
+        .values()
+        .find(|peer| peer.instance == instance)
+        .map(|peer| peer.addr);
+    let Some(addr) = addr else {
+        error!("Direct transfer from unknown LAN peer {}", instance);
+        return None;
+    };
+    match time::timeout(TRANSFER_TIMEOUT, lan.fetch(addr, id)).await {
+        Ok(Ok(content)) => {
+            info!(
+                "received {} bytes directly from {}",
+                content.len(),
+                instance
+            );
+            msg.message = Some(content);
+            Some(msg)
+        }
+        Ok(Err(e)) => {
+            error!("Direct transfer from {} failed: {}", instance, e);
+            None
+        }
+        Err(_) => {
+            error!("Direct transfer from {} timed out", instance);
+            None
+        }
+    }
+}
+
+/// Starts discovery early so peers are known before the first large publish.
+pub(crate) fn spawn(config: &Config) {
+    let config = config.clone();
+    tokio::spawn(async move {
+        get(&config).await;
+    });
+}
+
+fn mac(secret: &str, data: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("any key length");
+    mac.update(data);
+    hex(&mac.finalize().into_bytes())
+}
+
+fn verify(secret: &str, data: &[u8], expected: &str) -> bool {
+    let actual = mac(secret, data);
+    actual.len() == expected.len()
+        && actual
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut s, b| {
+        let _ = write!(s, "{b:02x}");
+        s
+    })
+}
@@ -0,0 +1,144 @@
+#[cfg(feature = "async")]
+mod api;
+#[cfg(feature = "async")]
+mod background;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+#[cfg(feature = "async")]
+mod channel;
+#[cfg(feature = "async")]
+mod chunks;
+#[cfg(not(target_arch = "wasm32"))]
+mod clip;
+mod config;
+#[cfg(feature = "keyring")]
+mod credentials;
+#[cfg(feature = "async")]
+mod dedup;
+#[cfg(feature = "hickory")]
+mod dns;
+#[cfg(feature = "e2e")]
+mod e2e;
+mod error;
+#[cfg(feature = "async")]
+mod events;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod filter;
+#[cfg(not(target_arch = "wasm32"))]
+mod handshake;
+#[cfg(feature = "async")]
+mod hub;
+#[cfg(feature = "imap")]
+mod imap;
+#[cfg(feature = "async")]
+mod jitter;
+#[cfg(feature = "kdeconnect")]
+mod kdeconnect;
+#[cfg(feature = "klipper")]
+mod klipper;
+#[cfg(feature = "mdns")]
+mod lan;
+mod message;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+#[cfg(feature = "neovim")]
+mod neovim;
+#[cfg(feature = "async")]
+mod net;
+#[cfg(feature = "async")]
+mod pipeline;
+#[cfg(feature = "portal")]
+mod portal;
+#[cfg(feature = "async")]
+mod proxy;
+#[cfg(feature = "async")]
+mod publish;
+#[cfg(feature = "python")]
+mod python;
+#[cfg(feature = "async")]
+mod repeats;
+#[cfg(not(target_arch = "wasm32"))]
+mod sandbox;
+#[cfg(not(target_arch = "wasm32"))]
+mod session;
+#[cfg(feature = "hmac")]
+mod signature;
+#[cfg(feature = "async")]
+mod sink;
+#[cfg(feature = "async")]
+mod sse;
+#[cfg(feature = "async")]
+mod standby;
+#[cfg(feature = "async")]
+mod stream;
+#[cfg(feature = "async")]
+mod supervisor;
+#[cfg(feature = "async")]
+mod suspend;
+#[cfg(feature = "async")]
+mod systemd;
+#[cfg(feature = "async")]
+mod tls;
+mod transform;
+#[cfg(feature = "async")]
+mod unifiedpush;
+#[cfg(feature = "async")]
+mod watch;
+#[cfg(target_arch = "wasm32")]
+mod web;
+#[cfg(feature = "async")]
+mod ws;
+#[cfg(feature = "x11")]
+mod x11;
+
+pub use config::{
+    ApiConfig, AttachmentConfig, CatchUp, ClientCert, Config, CopyqConfig, EmacsConfig, Extract,
+    FileConfig, HubConfig, IpFamily, JsonPath, OversizePolicy, Proxy, ProxyKind, Resolve, Route,
+    ServerKind, SocketConfig, SseConfig, SshConfig, Template, TitleMatch, TlsConfig, TokenSource,
+    Transport, UnifiedPushConfig, Vars, WatchConfig, SETTINGS,
+};
+pub use error::{
+    AuthError, ClipboardError, ConfigError, ConnectError, Error, ProtocolError, Result,
+    StorageError,
+};
+
+#[cfg(feature = "e2e")]
+pub use config::E2eConfig;
+#[cfg(feature = "hmac")]
+pub use config::HmacConfig;
+
+#[cfg(feature = "async")]
+pub use background::BackgroundClient;
+#[cfg(feature = "async")]
+pub use channel::OverflowPolicy;
+#[cfg(feature = "async")]
+pub use clip::set_clip;
+#[cfg(not(target_arch = "wasm32"))]
+pub use clip::{clipboard_backend, set_clip_blocking};
+#[cfg(feature = "keyring")]
+pub use credentials::store_token;
+#[cfg(feature = "hickory")]
+pub use dns::{DnsConfig, DnsProtocol};
+#[cfg(feature = "async")]
+pub use events::{ConnectionState, Event, EventBus};
+#[cfg(feature = "imap")]
+pub use imap::ImapConfig;
+#[cfg(feature = "kdeconnect")]
+pub use kdeconnect::KdeConnectConfig;
+#[cfg(feature = "mdns")]
+pub use lan::LanConfig;
+#[cfg(feature = "mqtt")]
+pub use mqtt::MqttConfig;
+#[cfg(feature = "neovim")]
+pub use neovim::NeovimConfig;
+#[cfg(feature = "async")]
+pub use publish::publish;
+#[cfg(not(target_arch = "wasm32"))]
+pub use sandbox::Sandbox;
+#[cfg(feature = "async")]
+pub use supervisor::{run, Supervisor, SupervisorHandle};
+#[cfg(target_arch = "wasm32")]
+pub use web::WebClient;
+#[cfg(feature = "async")]
+pub use ws::connect_and_run;
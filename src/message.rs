@@ -0,0 +1,275 @@
+use crate::config::{Config, ServerKind};
+use crate::error::ProtocolError;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use flate2::read::GzDecoder;
+use log::debug;
+use ruzstd::decoding::StreamingDecoder;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Read;
+
+/// Largest frame accepted. ntfy caps message bodies at 4 KiB and attachments
+/// are only linked, so anything near this is broken or hostile.
+pub(crate) const MAX_SIZE: usize = 256 * 1024;
+/// Deepest nesting of JSON arrays and objects accepted; real messages use 3.
+const MAX_DEPTH: usize = 16;
+/// Tags of messages whose body is gzip or zstd compressed and base64
+/// encoded. tungstenite doesn't do permessage-deflate, so large pastes are
+/// compressed by the sender instead.
+const GZIP_TAG: &str = "gzip";
+const ZSTD_TAG: &str = "zstd";
+/// Tag of messages whose body is base64 encoded, for text with control
+/// characters that wouldn't survive the trip otherwise.
+const BASE64_TAG: &str = "b64";
+/// Largest body a compressed message may expand to, against zip bombs.
+const MAX_DECOMPRESSED: u64 = 16 * 1024 * 1024;
+
+/// A message of ntfy's JSON format, which everything else is converted to.
+/// See <https://docs.ntfy.sh/subscribe/api/#json-message-format>.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub(crate) struct WSMessage {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub event: String,
+    pub topic: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// Unix seconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time: Option<u64>,
+    /// Unix seconds after which the server forgets the message.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attachment: Option<Attachment>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// 1 (min) to 5 (max); ntfy leaves out the default, 3.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<u8>,
+    /// URL opened when the notification is clicked.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub click: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub actions: Option<Vec<Action>>,
+    /// `text/markdown` for Markdown messages.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    /// `base64` for binary message bodies.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
+}
+
+/// A file attached to an ntfy message, downloadable from `url`.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub(crate) struct Attachment {
+    pub name: String,
+    /// MIME type.
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// Unix seconds after which the server deletes the file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires: Option<u64>,
+    pub url: String,
+}
+
+/// A button of a notification, see <https://docs.ntfy.sh/publish/#action-buttons>.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub(crate) struct Action {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// `view`, `broadcast`, `http` or `copy`.
+    pub action: String,
+    pub label: String,
+    /// Dismisses the notification once tapped.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clear: Option<bool>,
+    /// Opened by `view`, requested by `http`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub method: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub headers: Option<HashMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    /// Android intent sent by `broadcast`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub intent: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extras: Option<HashMap<String, String>>,
+    /// Text copied by `copy`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+}
+
+/// A message from Gotify's `/stream` endpoint; only the id and body are used.
+#[derive(Deserialize, Debug)]
+struct GotifyMessage {
+    id: Option<u64>,
+    message: String,
+}
+
+/// Parses a text frame into the ntfy shape the pipeline works with. A Gotify
+/// stream only carries messages for our client token, so they are tagged with
+/// the configured topic.
+///
+/// Frames over [`MAX_SIZE`] or nested deeper than [`MAX_DEPTH`] are rejected
+/// before being parsed. Bodies are left as they are for [`unpack`], which
+/// runs once the pipeline put chunks together and checked and decrypted
+/// them.
+pub(crate) fn decode(config: &Config, text: &str) -> Result<WSMessage, ProtocolError> {
+    if text.len() > MAX_SIZE {
+        return Err(ProtocolError::TooLarge(text.len()));
+    }
+    if depth(text) > MAX_DEPTH {
+        return Err(ProtocolError::TooDeep(MAX_DEPTH));
+    }
+    match config.kind {
+        ServerKind::Ntfy => Ok(serde_json::from_str(text)?),
+        ServerKind::Gotify => {
+            let msg = serde_json::from_str::<GotifyMessage>(text)?;
+            Ok(WSMessage {
+                id: msg.id.map(|id| id.to_string()),
+                event: "message".to_string(),
+                topic: config.topic.clone(),
+                message: Some(msg.message),
+                ..Default::default()
+            })
+        }
+    }
+}
+
+/// Replaces the body of a message tagged [`GZIP_TAG`], [`ZSTD_TAG`] or
+/// [`BASE64_TAG`], or of any with [`Config::base64`], with the text it
+/// encodes, dropping the tag. Base64 of compressed data is recognized by its
+/// magic bytes, also in untagged messages.
+pub(crate) fn unpack(config: &Config, mut msg: WSMessage) -> Result<WSMessage, ProtocolError> {
+    let gzip = take_tag(&mut msg, GZIP_TAG);
+    let zstd = take_tag(&mut msg, ZSTD_TAG);
+    let base64 = take_tag(&mut msg, BASE64_TAG) || config.base64;
+    let Some(body) = &msg.message else {
+        return Ok(msg);
+    };
+    let text = if gzip || zstd {
+        let compressed = STANDARD
+            .decode(body.trim())
+            .map_err(|e| ProtocolError::InvalidCompression(e.to_string()))?;
+        let compression = if gzip {
+            Compression::Gzip
+        } else {
+            Compression::Zstd
+        };
+        decompress(compression, &compressed)?
+    } else if base64 {
+        let bytes = STANDARD
+            .decode(body.trim())
+            .map_err(|e| ProtocolError::InvalidBase64(e.to_string()))?;
+        match Compression::detect(&bytes) {
+            Some(compression) => decompress(compression, &bytes)?,
+            None => String::from_utf8(bytes)
+                .map_err(|_| ProtocolError::InvalidBase64("not UTF-8 text".into()))?,
+        }
+    } else {
+        match sniff(body) {
+            Some(text) => text,
+            None => return Ok(msg),
+        }
+    };
+    msg.message = Some(text);
+    Ok(msg)
+}
+
+fn take_tag(msg: &mut WSMessage, tag: &str) -> bool {
+    let Some(tags) = &mut msg.tags else {
+        return false;
+    };
+    let len = tags.len();
+    tags.retain(|t| t != tag);
+    tags.len() != len
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    fn detect(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(&[0x1f, 0x8b]) {
+            Some(Compression::Gzip)
+        } else if bytes.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+}
+
+/// The text an untagged `body` compresses, if it's base64 of compressed
+/// data; anything else, including what only looks like it, is left alone.
+fn sniff(body: &str) -> Option<String> {
+    let body = body.trim();
+    // The magic bytes in base64, so not every message is decoded.
+    if !body.starts_with("H4sI") && !body.starts_with("KLUv/") {
+        return None;
+    }
+    let bytes = STANDARD.decode(body).ok()?;
+    let compression = Compression::detect(&bytes)?;
+    let text = decompress(compression, &bytes).ok()?;
+    debug!("unpacked untagged {:?} message", compression);
+    Some(text)
+}
+
+/// The text `compressed` expands to.
+fn decompress(compression: Compression, compressed: &[u8]) -> Result<String, ProtocolError> {
+    let invalid = |e: &dyn std::fmt::Display| ProtocolError::InvalidCompression(e.to_string());
+    let reader: Box<dyn Read + '_> = match compression {
+        Compression::Gzip => Box::new(GzDecoder::new(compressed)),
+        Compression::Zstd => Box::new(StreamingDecoder::new(compressed).map_err(|e| invalid(&e))?),
+    };
+    let mut text = String::new();
+    reader
+        .take(MAX_DECOMPRESSED + 1)
+        .read_to_string(&mut text)
+        .map_err(|e| invalid(&e))?;
+    if text.len() as u64 > MAX_DECOMPRESSED {
+        return Err(invalid(&format_args!(
+            "expands to over {} bytes",
+            MAX_DECOMPRESSED
+        )));
+    }
+    Ok(text)
+}
+
+/// Nesting depth of JSON arrays and objects, up to where it first exceeds
+/// [`MAX_DEPTH`].
+fn depth(text: &str) -> usize {
+    let (mut depth, mut max) = (0usize, 0);
+    let (mut in_string, mut escaped) = (false, false);
+    for byte in text.bytes() {
+        match byte {
+            _ if escaped => escaped = false,
+            b'\\' if in_string => escaped = true,
+            b'"' => in_string = !in_string,
+            _ if in_string => {}
+            b'{' | b'[' => {
+                depth += 1;
+                max = max.max(depth);
+                if max > MAX_DEPTH {
+                    break;
+                }
+            }
+            b'}' | b']' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    max
+}
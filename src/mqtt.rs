@@ -0,0 +1,252 @@
+//! MQTT bridge, enabled with the `mqtt` feature.
+//!
+//! Payloads published to `MQTT_SUBSCRIBE` enter the pipeline as if they had
+//! been sent to the ntfy topic, and every message the pipeline accepts is
+//! republished to `MQTT_PUBLISH`. With `MQTT_HOMEASSISTANT` the bridge also
+//! announces itself through Home Assistant's MQTT discovery.
+
+use crate::channel::Sender;
+use crate::config::{self, Vars};
+use crate::error::ConfigError;
+use crate::events::{ConnectionState, Event, EventBus};
+use crate::message::WSMessage;
+use log::{debug, error, info};
+use rumqttc::{AsyncClient, Event as MqttEvent, LastWill, MqttOptions, Packet, QoS};
+use serde_json::json;
+use std::process;
+use tokio::sync::broadcast;
+use tokio::time::{self, Duration};
+
+#[derive(Debug, Clone)]
+pub struct MqttConfig {
+    pub options: MqttOptions,
+    pub subscribe: Option<String>,
+    pub publish: Option<String>,
+    /// Home Assistant discovery prefix, usually `homeassistant`.
+    pub homeassistant: Option<String>,
+}
+
+impl MqttConfig {
+    /// Reads `MQTT_URL`, `MQTT_SUBSCRIBE`, `MQTT_PUBLISH` and
+    /// `MQTT_HOMEASSISTANT`. A missing
+    /// `client_id` query parameter defaults to one derived from the PID.
+    pub(crate) fn from_vars(vars: &Vars) -> Result<Option<Self>, ConfigError> {
+        let Ok(url) = vars.var("MQTT_URL") else {
+            return Ok(None);
+        };
+        let invalid = |name, value: &str| ConfigError::InvalidValue {
+            name,
+            value: value.to_string(),
+        };
+        let mut parsed = url::Url::parse(&url).map_err(|_| invalid("MQTT_URL", &url))?;
+        if !parsed.query_pairs().any(|(k, _)| k == "client_id") {
+            parsed
+                .query_pairs_mut()
+                .append_pair("client_id", &format!("ntfy2clip-{}", process::id()));
+        }
+        let options =
+            MqttOptions::parse_url(parsed.as_str()).map_err(|_| invalid("MQTT_URL", &url))?;
+
+        let subscribe = vars.var("MQTT_SUBSCRIBE").ok().filter(|t| !t.is_empty());
+        let publish = vars.var("MQTT_PUBLISH").ok().filter(|t| !t.is_empty());
+        // Republishing onto the topic we read from would loop forever.
+        if let (Some(subscribe), Some(publish)) = (&subscribe, &publish) {
+            if subscribe == publish {
+                return Err(invalid("MQTT_PUBLISH", publish));
+            }
+        }
+
+        let homeassistant =
+            vars.var("MQTT_HOMEASSISTANT")
+                .ok()
+                .map(|prefix| match prefix.as_str() {
+                    "" | "1" => "homeassistant".to_string(),
+                    _ => prefix,
+                });
+
+        Ok(Some(MqttConfig {
+            options,
+            subscribe,
+            publish,
+            homeassistant,
+        }))
+    }
+}
+
+/// Starts the MQTT connection and returns a client for publishing. Incoming
+/// payloads are tagged with `topic` so they pass the pipeline's topic filter.
+pub(crate) fn spawn(
+    config: &MqttConfig,
+    topic: &str,
+    pipeline: Sender<WSMessage>,
+    events: &EventBus,
+) -> AsyncClient {
+    let mut options = config.options.clone();
+    let discovery = config
+        .homeassistant
+        .as_ref()
+        .map(|prefix| Discovery::new(prefix, topic));
+    if let Some(discovery) = &discovery {
+        options.set_last_will(LastWill::new(
+            &discovery.availability,
+            "offline",
+            QoS::AtLeastOnce,
+            true,
+        ));
+    }
+    let (client, mut eventloop) = AsyncClient::new(options, 16);
+    if let Some(discovery) = &discovery {
+        discovery.report(client.clone(), events);
+    }
+    let subscribe = config.subscribe.clone();
+    let topic = topic.to_string();
+    let subscriber = client.clone();
+    tokio::spawn(async move {
+        loop {
+            match eventloop.poll().await {
+                Ok(MqttEvent::Incoming(Packet::ConnAck(_))) => {
+                    info!("connected to MQTT broker");
+                    if let Some(subscribe) = &subscribe {
+                        if let Err(e) = subscriber.try_subscribe(subscribe, QoS::AtLeastOnce) {
+                            error!("Failed to subscribe to MQTT topic {}: {}", subscribe, e);
+                        }
+                    }
+                    if let Some(discovery) = &discovery {
+                        discovery.announce(&subscriber);
+                    }
+                }
+                Ok(MqttEvent::Incoming(Packet::Publish(publish))) => {
+                    debug!("MQTT received message on {}", publish.topic);
+                    match String::from_utf8(publish.payload.to_vec()) {
+                        Ok(message) => {
+                            let msg = WSMessage {
+                                id: None,
+                                event: "message".to_string(),
+                                topic: topic.clone(),
+                                message: Some(message),
+                                ..Default::default()
+                            };
+                            if !pipeline.send(msg).await {
+                                return;
+                            }
+                        }
+                        Err(e) => error!("Ignoring non-UTF-8 MQTT payload: {}", e),
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    error!("MQTT connection error: {}. Reconnecting...", e);
+                    time::sleep(Duration::from_secs(5)).await;
+                }
+            }
+        }
+    });
+    client
+}
+
+/// Home Assistant MQTT discovery: a device with a connectivity binary sensor
+/// and a sensor holding the last clipboard content.
+struct Discovery {
+    prefix: String,
+    node: String,
+    availability: String,
+    connected: String,
+    last_message: String,
+}
+
+impl Discovery {
+    fn new(prefix: &str, topic: &str) -> Self {
+        let node: String = format!("ntfy2clip_{}_{}", config::hostname(), topic)
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        Discovery {
+            prefix: prefix.to_string(),
+            availability: format!("ntfy2clip/{node}/availability"),
+            connected: format!("ntfy2clip/{node}/connected"),
+            last_message: format!("ntfy2clip/{node}/last_message"),
+            node,
+        }
+    }
+
+    /// Publishes the retained discovery configs and marks the device online.
+    fn announce(&self, client: &AsyncClient) {
+        let device = json!({
+            "identifiers": [self.node],
+            "name": format!("ntfy2clip {}", config::hostname()),
+            "manufacturer": "ntfy2clip",
+            "sw_version": env!("CARGO_PKG_VERSION"),
+        });
+        let configs = [
+            (
+                "binary_sensor",
+                "connected",
+                json!({
+                    "name": "Connected",
+                    "device_class": "connectivity",
+                    "state_topic": self.connected,
+                    "payload_on": "ON",
+                    "payload_off": "OFF",
+                }),
+            ),
+            (
+                "sensor",
+                "last_message",
+                json!({
+                    "name": "Last clipboard message",
+                    "icon": "mdi:clipboard-text",
+                    "state_topic": self.last_message,
+                }),
+            ),
+        ];
+        for (component, object, mut payload) in configs {
+            payload["unique_id"] = json!(format!("{}_{}", self.node, object));
+            payload["availability_topic"] = json!(self.availability);
+            payload["device"] = device.clone();
+            let topic = format!(
+                "{}/{}/{}/{}/config",
+                self.prefix, component, self.node, object
+            );
+            self.publish(client, &topic, payload.to_string());
+        }
+        self.publish(client, &self.availability, "online".to_string());
+    }
+
+    /// Mirrors connection state changes and clipboard writes to the sensors.
+    fn report(&self, client: AsyncClient, events: &EventBus) {
+        let mut rx = events.subscribe();
+        let (connected, last_message) = (self.connected.clone(), self.last_message.clone());
+        tokio::spawn(async move {
+            loop {
+                let event = match rx.recv().await {
+                    Ok(event) => event,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return,
+                };
+                let (topic, payload) = match event {
+                    Event::StateChanged(ConnectionState::Connected) => {
+                        (&connected, "ON".to_string())
+                    }
+                    Event::StateChanged(_) => (&connected, "OFF".to_string()),
+                    // Home Assistant rejects states longer than 255 characters.
+                    Event::ClipboardWritten { content } => {
+                        (&last_message, content.chars().take(255).collect())
+                    }
+                    _ => continue,
+                };
+                if let Err(e) = client.publish(topic, QoS::AtLeastOnce, true, payload).await {
+                    error!("Failed to publish Home Assistant state: {}", e);
+                }
+            }
+        });
+    }
+
+    fn publish(&self, client: &AsyncClient, topic: &str, payload: String) {
+        if let Err(e) = client.try_publish(topic, QoS::AtLeastOnce, true, payload) {
+            error!(
+                "Failed to publish Home Assistant discovery to {}: {}",
+                topic, e
+            );
+        }
+    }
+}
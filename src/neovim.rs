@@ -0,0 +1,141 @@
+//! Neovim register sink, enabled with the `neovim` feature.
+//!
+//! Talks msgpack-RPC to a running editor's server socket and sets a register,
+//! which works in terminal-only sessions without any system clipboard.
+
+use crate::config::Vars;
+use crate::error::{ClipboardError, ConfigError};
+use log::debug;
+use rmpv::Value;
+use std::env;
+use std::fs;
+use std::io;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const RPC_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+pub struct NeovimConfig {
+    /// Server socket; `None` finds every running instance.
+    pub socket: Option<PathBuf>,
+    /// Register to set, such as `"`, `+` or `a`.
+    pub register: char,
+}
+
+impl NeovimConfig {
+    /// Reads `NEOVIM_SOCKET` and `NEOVIM_REGISTER`.
+    pub(crate) fn from_vars(vars: &Vars) -> Result<Option<Self>, ConfigError> {
+        let Ok(socket) = vars.var("NEOVIM_SOCKET") else {
+            return Ok(None);
+        };
+        let socket = (socket != "auto").then(|| PathBuf::from(socket));
+        let register = match vars.var("NEOVIM_REGISTER") {
+            Ok(value) => {
+                let mut chars = value.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) if c.is_ascii_alphanumeric() || "\"+*-".contains(c) => c,
+                    _ => {
+                        return Err(ConfigError::InvalidValue {
+                            name: "NEOVIM_REGISTER",
+                            value,
+                        })
+                    }
+                }
+            }
+            Err(_) => '"',
+        };
+        Ok(Some(NeovimConfig { socket, register }))
+    }
+}
+
+/// Sets the register in the configured instance, or in every one found.
+pub(crate) fn send(config: &NeovimConfig, content: &str) -> Result<(), ClipboardError> {
+    let Some(socket) = &config.socket else {
+        for socket in discover() {
+            // Sockets of crashed instances stay behind, so only log failures.
+            match set_register(&socket, config.register, content) {
+                Ok(()) => debug!("set Neovim register in {}", socket.display()),
+                Err(e) => debug!("skipping Neovim socket {}: {}", socket.display(), e),
+            }
+        }
+        return Ok(());
+    };
+    set_register(socket, config.register, content).map_err(|source| ClipboardError::Write {
+        command: "nvim",
+        source,
+    })
+}
+
+/// `$NVIM` inside a `:terminal`, otherwise the default `nvim.<pid>.0` server
+/// sockets in `$XDG_RUNTIME_DIR` or, without one, `$TMPDIR/nvim.$USER/*/`.
+fn discover() -> Vec<PathBuf> {
+    if let Some(socket) = env::var_os("NVIM") {
+        return vec![PathBuf::from(socket)];
+    }
+    let sockets = |dir: &Path| -> Vec<PathBuf> {
+        fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.starts_with("nvim.") && name.ends_with(".0"))
+            })
+            .collect()
+    };
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => sockets(Path::new(&dir)),
+        None => {
+            let user = env::var("USER").unwrap_or_default();
+            fs::read_dir(env::temp_dir().join(format!("nvim.{}", user)))
+                .into_iter()
+                .flatten()
+                .flatten()
+                .flat_map(|entry| sockets(&entry.path()))
+                .collect()
+        }
+    }
+}
+
+/// Calls `setreg(register, content)` and waits for the response.
+fn set_register(socket: &Path, register: char, content: &str) -> io::Result<()> {
+    let mut stream = UnixStream::connect(socket)?;
+    stream.set_read_timeout(Some(RPC_TIMEOUT))?;
+    stream.set_write_timeout(Some(RPC_TIMEOUT))?;
+    const MSGID: u32 = 1;
+    // [type = request, msgid, method, params]
+    let request = Value::Array(vec![
+        0.into(),
+        MSGID.into(),
+        "nvim_call_function".into(),
+        Value::Array(vec![
+            "setreg".into(),
+            Value::Array(vec![register.to_string().into(), content.into()]),
+        ]),
+    ]);
+    rmpv::encode::write_value(&mut stream, &request).map_err(io::Error::other)?;
+    loop {
+        let response = rmpv::decode::read_value(&mut stream).map_err(io::Error::other)?;
+        // [type = response, msgid, error, result]; anything else is a notification.
+        let Some([kind, id, error, _]) = response.as_array().map(Vec::as_slice) else {
+            continue;
+        };
+        if kind.as_u64() != Some(1) || id.as_u64() != Some(MSGID.into()) {
+            continue;
+        }
+        if error.is_nil() {
+            return Ok(());
+        }
+        // Errors are [type, message].
+        let message = error
+            .as_array()
+            .and_then(|e| e.get(1))
+            .and_then(Value::as_str)
+            .map_or_else(|| error.to_string(), str::to_string);
+        return Err(io::Error::other(message));
+    }
+}
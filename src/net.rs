@@ -0,0 +1,239 @@
+//! Opening the TCP connection to the server.
+//!
+//! Addresses are raced Happy Eyeballs style (RFC 8305): attempts alternate
+//! between IPv6 and IPv4 and start 250 ms apart, or as soon as the previous
+//! one fails, and the first to connect wins. A broken route for one family
+//! then costs a fraction of a second instead of a full connect timeout.
+//!
+//! Sockets are bound to [`SocketConfig`]'s address and interface before
+//! connecting, for multi-homed machines whose default route can't reach the
+//! server, and get its TCP options, so half-open connections can be noticed
+//! by the OS well before the application-level idle timeout.
+//!
+//! With a [`SocketConfig::proxy`], the same goes for the connection to the
+//! proxy, which is then asked to connect on to the server.
+//!
+//! [`SocketConfig::resolve`] entries replace the lookup for their host and
+//! port, and [`SocketConfig::ip_family`] narrows or reorders what is raced.
+
+use crate::config::{Config, IpFamily, SocketConfig};
+use crate::proxy;
+use futures_util::stream::{FuturesUnordered, StreamExt};
+use log::debug;
+use socket2::{SockRef, TcpKeepalive};
+use std::io;
+use std::net::SocketAddr;
+use tokio::net::{self, TcpSocket, TcpStream};
+use tokio::time::{self, Duration};
+use tungstenite::handshake::client::Request;
+
+/// RFC 8305's recommended "Connection Attempt Delay".
+const ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Connects to the host of a WebSocket `request`.
+pub(crate) async fn connect(config: &Config, request: &Request) -> io::Result<TcpStream> {
+    let uri = request.uri();
+    let host = uri.host().unwrap_or_default();
+    let port = uri
+        .port_u16()
+        .unwrap_or(if uri.scheme_str() == Some("wss") {
+            443
+        } else {
+            80
+        });
+    if let Some(proxy) = config.socket.proxy.as_ref().filter(|p| p.applies_to(host)) {
+        debug!(
+            "tunneling to {}:{} through {}:{}",
+            host, port, proxy.host, proxy.port
+        );
+        let stream = reach(config, &proxy.host, proxy.port).await?;
+        // A pinned address is passed on, the proxy would resolve the name.
+        return match pinned(&config.socket, host, port).first() {
+            Some(SocketAddr::V6(addr)) => {
+                proxy::tunnel(stream, proxy, &format!("[{}]", addr.ip()), port).await
+            }
+            Some(addr) => proxy::tunnel(stream, proxy, &addr.ip().to_string(), port).await,
+            None => proxy::tunnel(stream, proxy, host, port).await,
+        };
+    }
+    reach(config, host, port).await
+}
+
+async fn reach(config: &Config, host: &str, port: u16) -> io::Result<TcpStream> {
+    let mut addrs = match pinned(&config.socket, host, port) {
+        pinned if pinned.is_empty() => resolve(config, host, port).await?,
+        pinned => pinned,
+    };
+    match config.socket.ip_family {
+        IpFamily::Any => {}
+        IpFamily::PreferV4 => addrs.sort_by_key(SocketAddr::is_ipv6),
+        IpFamily::PreferV6 => addrs.sort_by_key(SocketAddr::is_ipv4),
+        IpFamily::OnlyV4 | IpFamily::OnlyV6 => {
+            let v6 = config.socket.ip_family == IpFamily::OnlyV6;
+            addrs.retain(|addr| addr.is_ipv6() == v6);
+            if addrs.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrNotAvailable,
+                    format!("{} has no IPv{} address", host, if v6 { 6 } else { 4 }),
+                ));
+            }
+        }
+    }
+    if let Some(bind) = config.socket.bind_addr {
+        addrs.retain(|addr| addr.is_ipv6() == bind.is_ipv6());
+        if addrs.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrNotAvailable,
+                format!("{} has no address of the same family as {}", host, bind),
+            ));
+        }
+    }
+    debug!("connecting to {} via {:?}", host, addrs);
+    race(&config.socket, interleave(addrs)).await
+}
+
+/// The [`SocketConfig::resolve`] addresses of `host:port`.
+fn pinned(config: &SocketConfig, host: &str, port: u16) -> Vec<SocketAddr> {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    config
+        .resolve
+        .iter()
+        .filter(|entry| entry.port == port && entry.host.eq_ignore_ascii_case(host))
+        .map(|entry| SocketAddr::new(entry.ip, port))
+        .collect()
+}
+
+async fn resolve(config: &Config, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+    #[cfg(feature = "hickory")]
+    if let Some(dns) = &config.dns {
+        let ips = crate::dns::resolve(dns, host).await?;
+        return Ok(ips
+            .into_iter()
+            .map(|ip| SocketAddr::new(ip, port))
+            .collect());
+    }
+    #[cfg(not(feature = "hickory"))]
+    let _ = config;
+    // `lookup_host` wants IPv6 literals without the URL's brackets.
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    Ok(net::lookup_host((host, port)).await?.collect())
+}
+
+/// Alternates address families, starting with the resolver's first choice.
+fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let prefer_v6 = addrs.first().is_some_and(SocketAddr::is_ipv6);
+    let (mut first, mut second): (Vec<_>, Vec<_>) = addrs
+        .into_iter()
+        .partition(|addr| addr.is_ipv6() == prefer_v6);
+    let mut ordered = Vec::with_capacity(first.len() + second.len());
+    let (mut first, mut second) = (first.drain(..), second.drain(..));
+    loop {
+        match (first.next(), second.next()) {
+            (None, None) => return ordered,
+            (a, b) => ordered.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+async fn race(socket: &SocketConfig, addrs: Vec<SocketAddr>) -> io::Result<TcpStream> {
+    let mut pending = addrs.into_iter();
+    let mut attempts = FuturesUnordered::new();
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, "no addresses to connect to");
+    loop {
+        if attempts.is_empty() {
+            match pending.next() {
+                Some(addr) => attempts.push(attempt(socket, addr)),
+                None => return Err(last_error),
+            }
+        }
+        tokio::select! {
+            Some((addr, result)) = attempts.next() => match result {
+                Ok(stream) => return Ok(stream),
+                Err(e) => {
+                    debug!("connecting to {} failed: {}", addr, e);
+                    last_error = e;
+                    attempts.extend(pending.next().map(|addr| attempt(socket, addr)));
+                }
+            },
+            _ = time::sleep(ATTEMPT_DELAY), if pending.len() > 0 => {
+                attempts.extend(pending.next().map(|addr| attempt(socket, addr)));
+            }
+        }
+    }
+}
+
+async fn attempt(config: &SocketConfig, addr: SocketAddr) -> (SocketAddr, io::Result<TcpStream>) {
+    (addr, open(config, addr).await)
+}
+
+async fn open(config: &SocketConfig, addr: SocketAddr) -> io::Result<TcpStream> {
+    let socket = if addr.is_ipv6() {
+        TcpSocket::new_v6()?
+    } else {
+        TcpSocket::new_v4()?
+    };
+    if let Some(ip) = config.bind_addr {
+        socket.bind(SocketAddr::new(ip, 0))?;
+    }
+    if let Some(interface) = &config.interface {
+        bind_device(&socket, interface)?;
+    }
+    socket.set_nodelay(config.nodelay)?;
+    if let Some(time) = config.keepalive {
+        SockRef::from(&socket).set_tcp_keepalive(&keepalive(config, time))?;
+    }
+    match config.connect_timeout {
+        Some(timeout) => time::timeout(timeout, socket.connect(addr))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "connect timed out"))?,
+        None => socket.connect(addr).await,
+    }
+}
+
+#[cfg_attr(
+    not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "windows"
+    )),
+    allow(unused_variables)
+)]
+fn keepalive(config: &SocketConfig, time: Duration) -> TcpKeepalive {
+    let keepalive = TcpKeepalive::new().with_time(time);
+    // Probe intervals and counts aren't configurable everywhere.
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "windows"
+    ))]
+    let keepalive = {
+        let keepalive = match config.keepalive_interval {
+            Some(interval) => keepalive.with_interval(interval),
+            None => keepalive,
+        };
+        match config.keepalive_retries {
+            Some(retries) => keepalive.with_retries(retries),
+            None => keepalive,
+        }
+    };
+    keepalive
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn bind_device(socket: &TcpSocket, interface: &str) -> io::Result<()> {
+    socket.bind_device(Some(interface.as_bytes()))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn bind_device(_: &TcpSocket, _: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "BIND_INTERFACE is only supported on Linux",
+    ))
+}
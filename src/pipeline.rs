@@ -0,0 +1,305 @@
+use crate::channel::{self, OverflowPolicy, Sender};
+use crate::chunks::Reassembler;
+use crate::config::{Config, Route};
+use crate::dedup::SeenIds;
+use crate::events::{Event, EventBus};
+use crate::filter;
+use crate::message::{self, WSMessage};
+use crate::sink::{self, ClipboardSink};
+use crate::tls;
+use crate::transform;
+use log::{debug, error};
+use std::sync::Arc;
+use tokio::sync::watch;
+
+const PIPELINE_CAPACITY: usize = 64;
+const CLIPBOARD_CAPACITY: usize = 4;
+const ROUTE_CAPACITY: usize = 16;
+const HUB_CAPACITY: usize = 64;
+const ATTACHMENT_CAPACITY: usize = 16;
+const FILE_CAPACITY: usize = 16;
+const SSH_CAPACITY: usize = 4;
+const EMACS_CAPACITY: usize = 4;
+#[cfg(feature = "kdeconnect")]
+const KDECONNECT_CAPACITY: usize = 4;
+#[cfg(feature = "neovim")]
+const NEOVIM_CAPACITY: usize = 4;
+#[cfg(feature = "mqtt")]
+const MQTT_CAPACITY: usize = 64;
+
+/// Spawns the pipeline and sink tasks and returns the sender transports feed
+/// messages into. The tasks stop once every sender is dropped.
+///
+/// Sinks are set up from `config`; messages are filtered by the latest one
+/// from `reloads`.
+pub(crate) fn spawn(
+    config: &Config,
+    events: &EventBus,
+    mut reloads: watch::Receiver<Config>,
+) -> Sender<WSMessage> {
+    let (tx, mut rx) =
+        channel::bounded::<WSMessage>("pipeline", PIPELINE_CAPACITY, OverflowPolicy::Block);
+    // Each message goes to the sink of its topic's route, and to all the rest.
+    let mut routes = Vec::new();
+    if config.file.as_ref().is_none_or(|file| file.clipboard) {
+        let (clip_tx, clip_rx) =
+            channel::bounded("clipboard", CLIPBOARD_CAPACITY, config.clipboard_overflow);
+        let ack = config.ack_topic.as_ref().map(|topic| Config {
+            topic: topic.clone(),
+            ..config.clone()
+        });
+        match &config.copyq {
+            Some(copyq) => {
+                let copyq_sink = sink::CopyqSink {
+                    config: copyq.clone(),
+                    ack,
+                };
+                sink::spawn(copyq_sink, clip_rx, events.clone());
+            }
+            None => sink::spawn(ClipboardSink { ack }, clip_rx, events.clone()),
+        }
+        routes.push((Route::Clipboard, clip_tx));
+    }
+    for (_, route) in &config.routes {
+        // Without the clipboard, messages routed there only reach the other
+        // sinks; the rest are shared by topics.
+        if *route == Route::Clipboard || routes.iter().any(|(r, _)| r == route) {
+            continue;
+        }
+        // Unlike the clipboard, every note and link matters.
+        let (route_tx, route_rx) = channel::bounded("route", ROUTE_CAPACITY, OverflowPolicy::Block);
+        match route {
+            Route::Clipboard => unreachable!(),
+            Route::File(path) => {
+                let file_sink = sink::FileSink {
+                    path: path.clone(),
+                    format: None,
+                };
+                sink::spawn(file_sink, route_rx, events.clone());
+            }
+            Route::Open => sink::spawn(sink::OpenSink, route_rx, events.clone()),
+        }
+        routes.push((route.clone(), route_tx));
+    }
+    let mut sinks = Vec::new();
+    // Sinks passing messages on to other devices rather than using them here.
+    #[cfg_attr(not(feature = "mqtt"), allow(unused_mut))]
+    let mut relays: Vec<Sender<Arc<WSMessage>>> = Vec::new();
+
+    // Fed before everything else, so it relays what the server sent.
+    let hub = config.hub.as_ref().map(|hub| {
+        let (hub_tx, hub_rx) = channel::bounded("hub", HUB_CAPACITY, OverflowPolicy::DropOldest);
+        let topics = std::iter::once(&config.topic)
+            .chain(&config.topics)
+            .cloned()
+            .collect();
+        let hub_sink = sink::HubSink {
+            tx: crate::hub::spawn(hub, topics),
+        };
+        sink::spawn(hub_sink, hub_rx, events.clone());
+        hub_tx
+    });
+
+    if let Some(attachments) = &config.attachments {
+        // Unlike clipboard content, every file matters, so wait for room.
+        let (file_tx, file_rx) =
+            channel::bounded("attachment", ATTACHMENT_CAPACITY, OverflowPolicy::Block);
+        match (config.http_url(), tls::http_client(config)) {
+            (Ok(server), Ok(client)) => {
+                let file_sink = sink::AttachmentSink {
+                    config: attachments.clone(),
+                    server,
+                    account: config.clone(),
+                    client,
+                };
+                sink::spawn(file_sink, file_rx, events.clone());
+                sinks.push(file_tx);
+            }
+            (Err(e), _) => error!("Attachments disabled, invalid server URL: {}", e),
+            (_, Err(e)) => error!("Attachments disabled: {}", e),
+        }
+    }
+
+    if let Some(file) = &config.file {
+        // A drop-box, so every message matters.
+        let (file_tx, file_rx) = channel::bounded("file", FILE_CAPACITY, OverflowPolicy::Block);
+        let file_sink = sink::FileSink {
+            path: file.path.clone(),
+            format: Some(file.format.clone()),
+        };
+        sink::spawn(file_sink, file_rx, events.clone());
+        sinks.push(file_tx);
+    }
+
+    if let Some(ssh) = &config.ssh {
+        let (ssh_tx, ssh_rx) = channel::bounded("ssh", SSH_CAPACITY, OverflowPolicy::DropOldest);
+        let ssh_sink = sink::SshSink {
+            config: ssh.clone(),
+        };
+        sink::spawn(ssh_sink, ssh_rx, events.clone());
+        sinks.push(ssh_tx);
+    }
+
+    if let Some(emacs) = &config.emacs {
+        let (emacs_tx, emacs_rx) =
+            channel::bounded("emacs", EMACS_CAPACITY, OverflowPolicy::DropOldest);
+        let emacs_sink = sink::EmacsSink {
+            config: emacs.clone(),
+        };
+        sink::spawn(emacs_sink, emacs_rx, events.clone());
+        sinks.push(emacs_tx);
+    }
+
+    #[cfg(feature = "kdeconnect")]
+    if let Some(kde) = &config.kdeconnect {
+        crate::kdeconnect::spawn(config, kde);
+        let (kde_tx, kde_rx) = channel::bounded(
+            "kdeconnect",
+            KDECONNECT_CAPACITY,
+            OverflowPolicy::DropOldest,
+        );
+        let kde_sink = sink::KdeConnectSink {
+            config: kde.clone(),
+            conn: None,
+        };
+        sink::spawn(kde_sink, kde_rx, events.clone());
+        sinks.push(kde_tx);
+    }
+
+    #[cfg(feature = "neovim")]
+    if let Some(neovim) = &config.neovim {
+        let (nvim_tx, nvim_rx) =
+            channel::bounded("neovim", NEOVIM_CAPACITY, OverflowPolicy::DropOldest);
+        let nvim_sink = sink::NeovimSink {
+            config: neovim.clone(),
+        };
+        sink::spawn(nvim_sink, nvim_rx, events.clone());
+        sinks.push(nvim_tx);
+    }
+
+    #[cfg(feature = "mdns")]
+    crate::lan::spawn(config);
+
+    if let Some(up) = &config.unifiedpush {
+        crate::unifiedpush::init(config, up);
+    }
+
+    if let Some(watch) = &config.watch {
+        crate::watch::spawn(config, watch);
+    }
+
+    if let Some(api) = &config.api {
+        crate::api::spawn(api, &config.topic, tx.clone());
+    }
+
+    if let Some(sse) = &config.sse {
+        match tls::other_client(config) {
+            Ok(client) => crate::sse::spawn(sse, &config.topic, client, tx.clone()),
+            Err(e) => error!("SSE source disabled: {}", e),
+        }
+    }
+
+    #[cfg(feature = "imap")]
+    if let Some(imap) = &config.imap {
+        crate::imap::spawn(imap, &config.topic, tx.clone());
+    }
+
+    #[cfg(feature = "mqtt")]
+    if let Some(mqtt) = &config.mqtt {
+        let client = crate::mqtt::spawn(mqtt, &config.topic, tx.clone(), events);
+        if let Some(topic) = &mqtt.publish {
+            let (mqtt_tx, mqtt_rx) =
+                channel::bounded("mqtt", MQTT_CAPACITY, OverflowPolicy::DropOldest);
+            let mqtt_sink = sink::MqttSink {
+                client,
+                topic: topic.clone(),
+            };
+            sink::spawn(mqtt_sink, mqtt_rx, events.clone());
+            relays.push(mqtt_tx);
+        }
+    }
+
+    let sinks_config = config.clone();
+    let mut config = config.clone();
+    let events = events.clone();
+    tokio::spawn(async move {
+        let mut seen = SeenIds::load(&config);
+        let mut chunks = Reassembler::default();
+        while let Some(msg) = rx.recv().await {
+            if let Some(id) = &msg.id {
+                if !seen.first_time(id) {
+                    debug!("pipeline dropped duplicate message {}", id);
+                    continue;
+                }
+            }
+            if let Some(hub) = &hub {
+                hub.send(Arc::new(msg.clone())).await;
+            }
+            if reloads.has_changed().unwrap_or(false) {
+                config = reloads.borrow_and_update().clone();
+            }
+            let Some(msg) = chunks.add(msg) else {
+                continue;
+            };
+            if crate::unifiedpush::deliver(&msg) {
+                continue;
+            }
+            #[cfg(feature = "hmac")]
+            let Some(msg) = crate::signature::verify(&config, msg) else {
+                continue;
+            };
+            #[cfg(feature = "e2e")]
+            let Some(msg) = crate::e2e::open(&config, msg) else {
+                continue;
+            };
+            let size = msg.message.as_ref().map_or(0, String::len);
+            let msg = match message::unpack(&config, msg) {
+                Ok(msg) => msg,
+                Err(e) => {
+                    error!("Protocol anomaly: {} ({} bytes)", e, size);
+                    events.emit(Event::ProtocolAnomaly {
+                        reason: e.to_string(),
+                        size,
+                    });
+                    continue;
+                }
+            };
+            if !filter::accepts(&config, &msg) {
+                continue;
+            }
+            #[cfg(feature = "mdns")]
+            let Some(msg) = crate::lan::resolve(&config, msg).await
+            else {
+                continue;
+            };
+            let Some(msg) = transform::apply(&config, msg) else {
+                continue;
+            };
+            if msg.message.is_none() {
+                continue;
+            }
+            // Shared by every sink; large pastes are never copied per sink.
+            let msg = Arc::new(msg);
+            for relay in &relays {
+                relay.send(msg.clone()).await;
+            }
+            if !filter::for_device(&config, &msg) {
+                continue;
+            }
+            debug!("pipeline accepted message: {:?}", &msg);
+            events.emit_with(|| Event::MessageReceived {
+                topic: msg.topic.clone(),
+                message: msg.message.clone().unwrap_or_default(),
+            });
+            let route = sinks_config.route(&msg.topic);
+            if let Some((_, sink)) = routes.iter().find(|(r, _)| r == route) {
+                sink.send(msg.clone()).await;
+            }
+            for sink in &sinks {
+                sink.send(msg.clone()).await;
+            }
+        }
+    });
+
+    tx
+}
@@ -0,0 +1,183 @@
+//! Clipboard backend using the XDG desktop portal, enabled with the `portal`
+//! feature, so sandboxed builds can write the clipboard without access to
+//! the X11 or Wayland sockets.
+//!
+//! The Clipboard portal only works inside a RemoteDesktop session. The user
+//! approves the session once; the restore token is kept so later runs don't
+//! ask again. Paste requests arrive as `SelectionTransfer` signals and are
+//! answered with the latest content. A session that fails, e.g. because the
+//! user revoked it or the portal restarted, is dropped and started anew on
+//! the next write.
+
+use crate::config;
+use crate::error::ClipboardError;
+use crate::sandbox::Sandbox;
+use ashpd::desktop::clipboard::{Clipboard, RequestClipboardOptions, SetSelectionOptions};
+use ashpd::desktop::remote_desktop::{
+    DeviceType, RemoteDesktop, SelectDevicesOptions, StartOptions,
+};
+use ashpd::desktop::{CreateSessionOptions, PersistMode, Session};
+use ashpd::enumflags2::BitFlags;
+use futures_util::StreamExt;
+use log::{debug, error, info};
+use std::fs;
+use std::os::fd::OwnedFd;
+use std::sync::{Arc, Mutex};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{oneshot, Mutex as AsyncMutex};
+use tokio::task::JoinHandle;
+
+const MIME_TYPES: &[&str] = &[
+    "text/plain;charset=utf-8",
+    "text/plain",
+    "UTF8_STRING",
+    "STRING",
+    "TEXT",
+];
+
+/// Dropped on failure so the next write starts a new session.
+static PORTAL: AsyncMutex<Option<Portal>> = AsyncMutex::const_new(None);
+
+struct Portal {
+    clipboard: Clipboard,
+    session: Session<RemoteDesktop>,
+    content: Arc<Mutex<Arc<str>>>,
+    transfers: JoinHandle<()>,
+}
+
+impl Drop for Portal {
+    /// Stops answering paste requests, which a new session would get twice.
+    fn drop(&mut self) {
+        self.transfers.abort();
+    }
+}
+
+/// Whether to use the portal: inside a sandbox, or when `CLIPBOARD_PORTAL=1`.
+/// `CLIPBOARD_PORTAL=0` forces the regular backends even when sandboxed.
+pub(crate) fn enabled() -> bool {
+    match config::var("CLIPBOARD_PORTAL").as_deref() {
+        Ok("1") => true,
+        Ok("0") => false,
+        _ => Sandbox::detect().is_sandboxed(),
+    }
+}
+
+pub(crate) async fn set_clip(content: Arc<str>) -> Result<(), ClipboardError> {
+    let mut portal = PORTAL.lock().await;
+    let current = match portal.take() {
+        Some(current) => current,
+        None => start().await?,
+    };
+    *current.content.lock().unwrap() = content;
+    current
+        .clipboard
+        .set_selection(
+            &current.session,
+            SetSelectionOptions::default().set_mime_types(MIME_TYPES),
+        )
+        .await?;
+    *portal = Some(current);
+    Ok(())
+}
+
+async fn start() -> Result<Portal, ClipboardError> {
+    let remote = RemoteDesktop::new().await?;
+    let clipboard = Clipboard::new().await?;
+    let session = remote
+        .create_session(CreateSessionOptions::default())
+        .await?;
+    clipboard
+        .request(&session, RequestClipboardOptions::default())
+        .await?;
+
+    let token_path = config::state_dir().join("portal-token");
+    let token = fs::read_to_string(&token_path).ok();
+    remote
+        .select_devices(
+            &session,
+            SelectDevicesOptions::default()
+                .set_devices(BitFlags::from(DeviceType::Keyboard))
+                .set_persist_mode(PersistMode::ExplicitlyRevoked)
+                .set_restore_token(token.as_deref().map(str::trim)),
+        )
+        .await?;
+    let selected = remote
+        .start(&session, None, StartOptions::default())
+        .await?
+        .response()?;
+    if !selected.is_clipboard_enabled() {
+        return Err(ClipboardError::Unsupported(
+            "desktop portal session without clipboard access",
+        ));
+    }
+    if let Some(token) = selected.restore_token() {
+        let saved = token_path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|()| fs::write(&token_path, token));
+        if let Err(e) = saved {
+            error!("Failed to save portal restore token: {}", e);
+        }
+    }
+    info!("desktop portal clipboard session started");
+
+    let content = Arc::new(Mutex::new(Arc::from("")));
+    let (ready_tx, ready_rx) = oneshot::channel();
+    let transfers = tokio::spawn(transfers(content.clone(), ready_tx));
+    ready_rx.await.unwrap_or(Err(ashpd::Error::NoResponse))?;
+
+    Ok(Portal {
+        clipboard,
+        session,
+        content,
+        transfers,
+    })
+}
+
+/// Answers paste requests with the latest content, using proxies separate
+/// from the one `Portal` keeps. Reports on `ready` once subscribed, since that
+/// has to happen before the first `SetSelection`.
+async fn transfers(content: Arc<Mutex<Arc<str>>>, ready: oneshot::Sender<ashpd::Result<()>>) {
+    let (listener, writer) = match tokio::try_join!(Clipboard::new(), Clipboard::new()) {
+        Ok(proxies) => proxies,
+        Err(e) => {
+            let _ = ready.send(Err(e));
+            return;
+        }
+    };
+    let transfers = match listener.receive_selection_transfer::<RemoteDesktop>().await {
+        Ok(transfers) => transfers,
+        Err(e) => {
+            let _ = ready.send(Err(e));
+            return;
+        }
+    };
+    let _ = ready.send(Ok(()));
+
+    let mut transfers = std::pin::pin!(transfers);
+    while let Some((session, mime_type, serial)) = transfers.next().await {
+        debug!("portal requested clipboard as {}", mime_type);
+        let content = content.lock().unwrap().clone();
+        let success = match writer.selection_write(&session, serial).await {
+            Ok(fd) => write(OwnedFd::from(fd), &content).await,
+            Err(e) => {
+                error!("Portal clipboard transfer failed: {}", e);
+                false
+            }
+        };
+        if let Err(e) = writer.selection_write_done(&session, serial, success).await {
+            error!("Portal clipboard transfer failed: {}", e);
+        }
+    }
+}
+
+async fn write(fd: OwnedFd, content: &str) -> bool {
+    let mut file = tokio::fs::File::from_std(fs::File::from(fd));
+    match file.write_all(content.as_bytes()).await {
+        Ok(()) => file.flush().await.is_ok(),
+        Err(e) => {
+            error!("Portal clipboard transfer failed: {}", e);
+            false
+        }
+    }
+}
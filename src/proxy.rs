@@ -0,0 +1,209 @@
+//! Tunneling the connection to the server through a proxy.
+//!
+//! The TCP connection to the proxy is opened like any other, then the proxy
+//! is asked to connect on to the server: with SOCKS5, or an HTTP `CONNECT`
+//! request. The server's name is passed along unresolved, so it is resolved
+//! by the proxy, just like with `ssh -D` and curl's `socks5h://`. TLS and the
+//! WebSocket handshake then run over the tunnel as if it were a direct
+//! connection.
+
+use crate::config::{Proxy, ProxyKind};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use std::io;
+use std::net::IpAddr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+const SOCKS_VERSION: u8 = 5;
+const NO_AUTH: u8 = 0;
+/// RFC 1929 username and password.
+const PASSWORD_AUTH: u8 = 2;
+const NO_ACCEPTABLE_METHOD: u8 = 0xff;
+const CONNECT: u8 = 1;
+const IPV4: u8 = 1;
+const DOMAIN: u8 = 3;
+const IPV6: u8 = 4;
+/// Limit on the proxy's answer to `CONNECT`.
+const MAX_RESPONSE: usize = 8192;
+
+/// Has the proxy at the other end of `stream` connect to `host:port`.
+pub(crate) async fn tunnel(
+    stream: TcpStream,
+    proxy: &Proxy,
+    host: &str,
+    port: u16,
+) -> io::Result<TcpStream> {
+    match proxy.kind {
+        ProxyKind::Socks5 => socks5(stream, proxy, host, port).await,
+        ProxyKind::Http => http_connect(stream, proxy, host, port).await,
+    }
+}
+
+/// Asks the HTTP proxy at the other end of `stream` for a tunnel to
+/// `host:port` (RFC 9110, section 9.3.6).
+async fn http_connect(
+    mut stream: TcpStream,
+    proxy: &Proxy,
+    host: &str,
+    port: u16,
+) -> io::Result<TcpStream> {
+    let target = format!("{}:{}", host, port);
+    let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", target);
+    if let Some((user, password)) = &proxy.auth {
+        let credentials = STANDARD.encode(format!("{}:{}", user, password));
+        request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", credentials));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await?;
+
+    // Byte by byte, so nothing after the header is taken from the tunnel.
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() >= MAX_RESPONSE {
+            return Err(invalid("proxy response header is too long".into()));
+        }
+        response.push(stream.read_u8().await?);
+    }
+    let response = String::from_utf8_lossy(&response);
+    let status_line = response.lines().next().unwrap_or_default();
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse::<u16>().ok())
+        .ok_or_else(|| invalid(format!("invalid proxy response: {}", status_line)))?;
+    match status {
+        200..=299 => Ok(stream),
+        407 => Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("proxy authentication failed: {}", status_line),
+        )),
+        _ => Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!("proxy couldn't connect to {}: {}", target, status_line),
+        )),
+    }
+}
+
+/// Asks the SOCKS5 proxy at the other end of `stream` to connect to
+/// `host:port`, returning the tunnel (RFC 1928).
+async fn socks5(
+    mut stream: TcpStream,
+    proxy: &Proxy,
+    host: &str,
+    port: u16,
+) -> io::Result<TcpStream> {
+    let methods: &[u8] = match proxy.auth {
+        Some(_) => &[NO_AUTH, PASSWORD_AUTH],
+        None => &[NO_AUTH],
+    };
+    let mut greeting = vec![SOCKS_VERSION, methods.len() as u8];
+    greeting.extend_from_slice(methods);
+    stream.write_all(&greeting).await?;
+    let mut choice = [0; 2];
+    stream.read_exact(&mut choice).await?;
+    check_version(choice[0])?;
+    match (choice[1], &proxy.auth) {
+        (NO_AUTH, _) => {}
+        (PASSWORD_AUTH, Some((user, password))) => {
+            authenticate(&mut stream, user, password).await?
+        }
+        (NO_ACCEPTABLE_METHOD, _) => {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "the SOCKS proxy accepts none of the offered authentication methods",
+            ))
+        }
+        (method, _) => return Err(invalid(format!("SOCKS proxy chose method {}", method))),
+    }
+
+    let mut request = vec![SOCKS_VERSION, CONNECT, 0];
+    match host.trim_start_matches('[').trim_end_matches(']').parse() {
+        Ok(IpAddr::V4(ip)) => {
+            request.push(IPV4);
+            request.extend_from_slice(&ip.octets());
+        }
+        Ok(IpAddr::V6(ip)) => {
+            request.push(IPV6);
+            request.extend_from_slice(&ip.octets());
+        }
+        Err(_) => {
+            let name = u8::try_from(host.len())
+                .map_err(|_| invalid(format!("host name {} is too long for SOCKS", host)))?;
+            request.extend_from_slice(&[DOMAIN, name]);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply = [0; 4];
+    stream.read_exact(&mut reply).await?;
+    check_version(reply[0])?;
+    if reply[1] != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!(
+                "SOCKS proxy couldn't connect to {}:{}: {}",
+                host,
+                port,
+                reply_error(reply[1])
+            ),
+        ));
+    }
+    // The address the proxy connected from, which isn't needed.
+    let bound = match reply[3] {
+        IPV4 => 4,
+        IPV6 => 16,
+        DOMAIN => stream.read_u8().await? as usize,
+        kind => return Err(invalid(format!("SOCKS proxy sent address type {}", kind))),
+    };
+    let mut rest = vec![0; bound + 2];
+    stream.read_exact(&mut rest).await?;
+    Ok(stream)
+}
+
+async fn authenticate(stream: &mut TcpStream, user: &str, password: &str) -> io::Result<()> {
+    let too_long = || invalid("SOCKS username or password is longer than 255 bytes".into());
+    let mut request = vec![1, u8::try_from(user.len()).map_err(|_| too_long())?];
+    request.extend_from_slice(user.as_bytes());
+    request.push(u8::try_from(password.len()).map_err(|_| too_long())?);
+    request.extend_from_slice(password.as_bytes());
+    stream.write_all(&request).await?;
+    let mut status = [0; 2];
+    stream.read_exact(&mut status).await?;
+    if status[1] != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "the SOCKS proxy rejected the username or password",
+        ));
+    }
+    Ok(())
+}
+
+fn check_version(version: u8) -> io::Result<()> {
+    if version != SOCKS_VERSION {
+        return Err(invalid(format!(
+            "proxy answered with SOCKS version {}, not 5",
+            version
+        )));
+    }
+    Ok(())
+}
+
+fn reply_error(code: u8) -> &'static str {
+    match code {
+        1 => "general failure",
+        2 => "not allowed by the ruleset",
+        3 => "network unreachable",
+        4 => "host unreachable",
+        5 => "connection refused",
+        6 => "TTL expired",
+        7 => "command not supported",
+        8 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
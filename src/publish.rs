@@ -0,0 +1,65 @@
+use crate::chunks;
+use crate::config::Config;
+use crate::error::{AuthError, ConnectError, Result};
+use crate::tls;
+use log::{debug, info};
+use url::Url;
+
+/// Publishes `message` to the configured topic over HTTP, in pieces of
+/// [`Config::chunk_size`] if it's longer.
+pub async fn publish(config: &Config, message: &str) -> Result<()> {
+    let url = config.http_url().map_err(ConnectError::from)?;
+    #[cfg(feature = "mdns")]
+    let signal = crate::lan::offer(config, message).await;
+    #[cfg(feature = "mdns")]
+    let body = signal.as_deref().unwrap_or(message);
+    #[cfg(not(feature = "mdns"))]
+    let body = message;
+    #[allow(unused_mut)]
+    let mut tags = Vec::new();
+    #[cfg(feature = "e2e")]
+    let sealed = match &config.e2e {
+        Some(e2e) => {
+            tags.push(crate::e2e::TAG.to_string());
+            Some(crate::e2e::seal(e2e, body).ok_or(ConnectError::Encryption)?)
+        }
+        None => None,
+    };
+    #[cfg(feature = "e2e")]
+    let body = sealed.as_deref().unwrap_or(body);
+    #[cfg(feature = "hmac")]
+    if let Some(hmac) = &config.hmac {
+        let tag = crate::signature::tag(hmac, &config.topic, &tags, body);
+        tags.push(tag);
+    }
+    match config.chunk_size.filter(|&size| body.len() > size) {
+        Some(size) => {
+            for (tag, piece) in chunks::split(body, size) {
+                let tags = [tags.as_slice(), &[tag]].concat();
+                post(config, url.clone(), piece, &tags).await?;
+            }
+        }
+        None => post(config, url, body, &tags).await?,
+    }
+    info!("published {} bytes to {}", message.len(), config.topic);
+    Ok(())
+}
+
+async fn post(config: &Config, url: Url, body: &str, tags: &[String]) -> Result<()> {
+    let mut request = tls::http_client(config)?.post(url).body(body.to_string());
+    if let Some(authorization) = config.authorization()? {
+        request = request.header("Authorization", authorization);
+    }
+    if !tags.is_empty() {
+        request = request.header("Tags", tags.join(","));
+    }
+
+    debug!("publish request: {:?}", &request);
+    let response = request.send().await.map_err(ConnectError::from)?;
+    let status = response.status().as_u16();
+    match status {
+        200..=299 => Ok(()),
+        401 | 403 => Err(AuthError::Rejected(status).into()),
+        _ => Err(ConnectError::Status(status).into()),
+    }
+}
@@ -0,0 +1,131 @@
+//! Python module, enabled with the `python` feature and built with maturin.
+
+use crate::{publish, run, Config, Event, EventBus};
+use log::debug;
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use pyo3_async_runtimes::tokio::future_into_py;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task::JoinHandle;
+
+create_exception!(ntfy2clip, Error, PyException);
+
+fn to_py_err(e: crate::Error) -> PyErr {
+    Error::new_err(e.to_string())
+}
+
+fn make_config(topic: String, server: Option<String>, token: Option<String>) -> Config {
+    let mut config = Config::new(topic);
+    if let Some(server) = server {
+        config.server = server;
+    }
+    config.token = token.filter(|t| !t.is_empty());
+    config
+}
+
+/// The task calling `on_message`, stopped when dropped.
+struct Callbacks(JoinHandle<()>);
+
+impl Drop for Callbacks {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Subscribes to a topic and copies received messages to the clipboard.
+#[pyclass(module = "ntfy2clip")]
+struct Client {
+    config: Config,
+}
+
+#[pymethods]
+impl Client {
+    #[new]
+    #[pyo3(signature = (topic, server=None, token=None, timeout=120))]
+    fn new(topic: String, server: Option<String>, token: Option<String>, timeout: u64) -> Self {
+        let mut config = make_config(topic, server, token);
+        config.timeout = Duration::from_secs(timeout);
+        Client { config }
+    }
+
+    /// Runs until a non-retryable error occurs, calling `on_message(topic,
+    /// message)` for every received message.
+    #[pyo3(signature = (on_message=None))]
+    fn run<'py>(
+        &self,
+        py: Python<'py>,
+        on_message: Option<Py<PyAny>>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let config = self.config.clone();
+        future_into_py(py, async move {
+            let events = EventBus::default();
+            // Held until `run` returns or is cancelled.
+            let _callbacks = on_message.map(|callback| {
+                let mut rx = events.subscribe();
+                Callbacks(tokio::spawn(async move {
+                    loop {
+                        let event = match rx.recv().await {
+                            Ok(event) => event,
+                            Err(RecvError::Lagged(n)) => {
+                                debug!("on_message lagged behind, skipped {} events", n);
+                                continue;
+                            }
+                            Err(RecvError::Closed) => return,
+                        };
+                        if let Event::MessageReceived { topic, message } = event {
+                            Python::attach(|py| {
+                                if let Err(e) = callback.call1(py, (topic, message)) {
+                                    e.print(py);
+                                }
+                            });
+                        }
+                    }
+                }))
+            });
+            run(&config, &events).await.map_err(to_py_err)
+        })
+    }
+
+    /// Publishes `text` to this client's topic.
+    fn publish<'py>(&self, py: Python<'py>, text: String) -> PyResult<Bound<'py, PyAny>> {
+        let config = self.config.clone();
+        future_into_py(py, async move {
+            publish(&config, &text).await.map_err(to_py_err)
+        })
+    }
+}
+
+/// Publishes `text` to `topic` without keeping a subscription open.
+#[pyfunction(name = "publish")]
+#[pyo3(signature = (topic, text, server=None, token=None))]
+fn publish_once(
+    py: Python<'_>,
+    topic: String,
+    text: String,
+    server: Option<String>,
+    token: Option<String>,
+) -> PyResult<Bound<'_, PyAny>> {
+    let config = make_config(topic, server, token);
+    future_into_py(py, async move {
+        publish(&config, &text).await.map_err(to_py_err)
+    })
+}
+
+/// Writes `text` to the system clipboard using the detected backend.
+#[pyfunction]
+fn set_clip(py: Python<'_>, text: String) -> PyResult<Bound<'_, PyAny>> {
+    future_into_py(py, async move {
+        crate::set_clip(text).await.map_err(|e| to_py_err(e.into()))
+    })
+}
+
+#[pymodule]
+fn ntfy2clip(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("Error", m.py().get_type::<Error>())?;
+    m.add_class::<Client>()?;
+    m.add_function(wrap_pyfunction!(publish_once, m)?)?;
+    m.add_function(wrap_pyfunction!(set_clip, m)?)?;
+    Ok(())
+}
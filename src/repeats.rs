@@ -0,0 +1,64 @@
+//! Collapsing log lines that repeat while a condition persists, like the
+//! same connection error every few seconds while the network is down.
+
+use log::{debug, error, info};
+use std::time::{Duration, Instant};
+
+/// How often a persisting error is logged again, with a count.
+const SUMMARY_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Remembers the last error logged through it.
+pub(crate) struct Repeats {
+    target: &'static str,
+    last: Option<String>,
+    suppressed: u32,
+    reported: Instant,
+}
+
+impl Repeats {
+    /// Logs with `target`, usually the caller's `module_path!()`.
+    pub(crate) fn new(target: &'static str) -> Self {
+        Repeats {
+            target,
+            last: None,
+            suppressed: 0,
+            reported: Instant::now(),
+        }
+    }
+
+    /// Logs `line` as an error, or only at debug level if the condition
+    /// `key` already was reported less than [`SUMMARY_INTERVAL`] ago.
+    pub(crate) fn error(&mut self, key: &str, line: impl FnOnce() -> String) {
+        if self.last.as_deref() != Some(key) {
+            self.finish();
+            error!(target: self.target, "{}", line());
+            self.last = Some(key.to_string());
+        } else if self.reported.elapsed() < SUMMARY_INTERVAL {
+            self.suppressed += 1;
+            debug!(target: self.target, "{}", line());
+            return;
+        } else {
+            error!(
+                target: self.target,
+                "{} (repeated {} times in the last {:?})",
+                line(),
+                self.suppressed,
+                self.reported.elapsed()
+            );
+            self.suppressed = 0;
+        }
+        self.reported = Instant::now();
+    }
+
+    /// Ends the condition, saying how often it was left unlogged.
+    pub(crate) fn finish(&mut self) {
+        if self.last.take().is_some() && self.suppressed > 0 {
+            info!(
+                target: self.target,
+                "last message repeated {} times",
+                self.suppressed
+            );
+        }
+        self.suppressed = 0;
+    }
+}
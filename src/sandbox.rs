@@ -0,0 +1,77 @@
+//! Detection of Flatpak and Snap sandboxes, which hide host binaries and
+//! limit access to the display server sockets.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+const FLATPAK_INFO: &str = "/.flatpak-info";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Sandbox {
+    None,
+    Flatpak { app_id: String },
+    Snap { name: String },
+}
+
+impl Sandbox {
+    pub fn detect() -> Self {
+        if Path::new(FLATPAK_INFO).exists() {
+            Sandbox::Flatpak {
+                app_id: env::var("FLATPAK_ID").unwrap_or_default(),
+            }
+        } else if let Ok(name) = env::var("SNAP_NAME") {
+            Sandbox::Snap { name }
+        } else {
+            Sandbox::None
+        }
+    }
+
+    pub fn is_sandboxed(&self) -> bool {
+        *self != Sandbox::None
+    }
+
+    /// Human-readable restrictions that affect the client in this sandbox.
+    pub fn restrictions(&self) -> Vec<String> {
+        let mut restrictions = Vec::new();
+        match self {
+            Sandbox::None => {}
+            Sandbox::Flatpak { .. } => {
+                restrictions.push(
+                    "host binaries are hidden; clipboard tools must come from the runtime"
+                        .to_string(),
+                );
+                let info = fs::read_to_string(FLATPAK_INFO).unwrap_or_default();
+                let values = |key: &str| -> Vec<String> {
+                    info.lines()
+                        .find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+                        .map(|v| v.split(';').filter(|s| !s.is_empty()).map(str::to_string))
+                        .into_iter()
+                        .flatten()
+                        .collect()
+                };
+                let sockets = values("sockets");
+                if !sockets.iter().any(|s| s == "wayland") {
+                    restrictions.push("no access to the Wayland socket".to_string());
+                }
+                if !sockets.iter().any(|s| s == "x11" || s == "fallback-x11") {
+                    restrictions.push("no access to the X11 socket".to_string());
+                }
+                if !values("shared").iter().any(|s| s == "network") {
+                    restrictions.push("no network access".to_string());
+                }
+            }
+            Sandbox::Snap { .. } => {
+                restrictions.push(
+                    "host binaries are hidden; clipboard tools must be bundled in the snap"
+                        .to_string(),
+                );
+                if env::var("SNAP_CONFINEMENT").is_ok_and(|c| c == "strict") {
+                    restrictions
+                        .push("strict confinement; connect the wayland/x11 interfaces".to_string());
+                }
+            }
+        }
+        restrictions
+    }
+}
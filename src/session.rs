@@ -0,0 +1,136 @@
+//! The graphical session the clipboard backends talk to.
+//!
+//! A long-running daemon keeps the environment it was started with, which
+//! goes stale when the X server comes back on another display, the Wayland
+//! compositor restarts with a new socket, or a remote desktop session
+//! reconnects. When a write fails, [`refresh`] looks for the current session
+//! so the write can be retried against it.
+
+use log::info;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+
+/// Variables that locate the session.
+const VARS: [&str; 5] = [
+    "WAYLAND_DISPLAY",
+    "DISPLAY",
+    "XAUTHORITY",
+    "XDG_RUNTIME_DIR",
+    "XDG_CURRENT_DESKTOP",
+];
+
+const X11_SOCKETS: &str = "/tmp/.X11-unix";
+
+/// Values found by [`refresh`], taking precedence over the process's own.
+static OVERRIDES: Mutex<Vec<(&'static str, String)>> = Mutex::new(Vec::new());
+
+/// `name` in the current session.
+pub(crate) fn var(name: &str) -> Option<String> {
+    let overrides = OVERRIDES.lock().unwrap();
+    match overrides.iter().find(|(var, _)| *var == name) {
+        Some((_, value)) => Some(value.clone()).filter(|v| !v.is_empty()),
+        None => env::var(name).ok(),
+    }
+}
+
+/// Variables [`refresh`] changed, for the environment of backend commands.
+pub(crate) fn overrides() -> Vec<(&'static str, String)> {
+    OVERRIDES.lock().unwrap().clone()
+}
+
+/// Looks for the current session; `true` if it differs from the one used so
+/// far, so a failed write is worth retrying.
+pub(crate) fn refresh() -> bool {
+    let mut found = systemd_environment();
+    let lookup = |found: &[(&str, String)], name: &str| {
+        found
+            .iter()
+            .find(|(var, _)| *var == name)
+            .map(|(_, value)| value.clone())
+            .or_else(|| var(name))
+    };
+    // Sessions don't always update systemd, and its copy can be stale too.
+    if let Some(runtime) = lookup(&found, "XDG_RUNTIME_DIR").map(PathBuf::from) {
+        let wayland = lookup(&found, "WAYLAND_DISPLAY");
+        if wayland.is_some_and(|w| !runtime.join(w).exists()) {
+            if let Some(socket) = wayland_socket(&runtime) {
+                found.retain(|(var, _)| *var != "WAYLAND_DISPLAY");
+                found.push(("WAYLAND_DISPLAY", socket));
+            }
+        }
+    }
+    if lookup(&found, "DISPLAY").is_none_or(|d| !x11_socket_exists(&d)) {
+        if let Some(display) = x11_display() {
+            found.retain(|(var, _)| *var != "DISPLAY");
+            found.push(("DISPLAY", display));
+        }
+    }
+
+    let mut changed = false;
+    for (name, value) in found {
+        if var(name).as_deref() == Some(value.as_str()) {
+            continue;
+        }
+        info!("session changed: {}={}", name, value);
+        let mut overrides = OVERRIDES.lock().unwrap();
+        overrides.retain(|(var, _)| *var != name);
+        overrides.push((name, value));
+        changed = true;
+    }
+    changed
+}
+
+/// The session variables of the systemd user manager, which desktop
+/// sessions update on login.
+fn systemd_environment() -> Vec<(&'static str, String)> {
+    let Ok(output) = Command::new("systemctl")
+        .args(["--user", "show-environment"])
+        .output()
+    else {
+        return Vec::new();
+    };
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let (name, value) = line.split_once('=')?;
+            let name = VARS.into_iter().find(|var| *var == name)?;
+            // Values needing quotes are shell-escaped; none of ours should.
+            (!value.starts_with("$'")).then(|| (name, value.to_string()))
+        })
+        .collect()
+}
+
+fn wayland_socket(runtime: &Path) -> Option<String> {
+    let mut sockets: Vec<String> = fs::read_dir(runtime)
+        .ok()?
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter(|name| name.starts_with("wayland-") && !name.ends_with(".lock"))
+        .collect();
+    sockets.sort();
+    sockets.into_iter().next()
+}
+
+/// Whether the X server of `display`, e.g. `:0.0`, has a local socket.
+/// Remote displays are assumed to be fine.
+fn x11_socket_exists(display: &str) -> bool {
+    let Some(number) = display.strip_prefix(':') else {
+        return true;
+    };
+    let number = number.split('.').next().unwrap_or_default();
+    Path::new(X11_SOCKETS).join(format!("X{}", number)).exists()
+}
+
+/// The lowest-numbered local X server.
+fn x11_display() -> Option<String> {
+    fs::read_dir(X11_SOCKETS)
+        .ok()?
+        .filter_map(|entry| {
+            let name = entry.ok()?.file_name().into_string().ok()?;
+            name.strip_prefix('X')?.parse::<u32>().ok()
+        })
+        .min()
+        .map(|number| format!(":{}", number))
+}
@@ -0,0 +1,119 @@
+//! Authenticating messages with a shared secret, so nobody who merely
+//! guessed the topic can write to the clipboard.
+//!
+//! Published messages are tagged `sig:<time>:<mac>`, where `<mac>` is the
+//! base64 of HMAC-SHA256 over the time in Unix seconds, the topic, the title,
+//! the other tags joined by commas and the body, each on a line of their own.
+//! The body is signed as it's sent, so after encryption and before chunking,
+//! and verified after reassembly and before decryption. With
+//! [`Config::replay_window`] the signed time has to be recent, not just the
+//! server's. The priority, click action, icon and attachment aren't signed.
+
+use crate::config::{Config, HmacConfig};
+use crate::filter;
+use crate::message::WSMessage;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, KeyInit, Mac};
+use log::warn;
+use sha2::Sha256;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const TAG_PREFIX: &str = "sig:";
+
+/// The tag signing `body` with `tags` and no title for `topic`, as of now.
+pub(crate) fn tag(config: &HmacConfig, topic: &str, tags: &[String], body: &str) -> String {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let signed = Signed {
+        time,
+        topic,
+        title: "",
+        tags,
+        body,
+    };
+    let mac = signed.mac(config).finalize().into_bytes();
+    format!("{}{}:{}", TAG_PREFIX, time, URL_SAFE_NO_PAD.encode(mac))
+}
+
+/// Checks the signature of a message, dropping the tag. Without
+/// [`Config::hmac`] messages are passed through.
+pub(crate) fn verify(config: &Config, mut msg: WSMessage) -> Option<WSMessage> {
+    let Some(hmac) = &config.hmac else {
+        return Some(msg);
+    };
+    let tags = msg.tags.as_deref().unwrap_or_default();
+    let Some(index) = tags.iter().position(|tag| tag.starts_with(TAG_PREFIX)) else {
+        warn!(
+            "Dropping unsigned message {}",
+            msg.id.as_deref().unwrap_or_default()
+        );
+        return None;
+    };
+    let tag = msg.tags.as_mut()?.remove(index);
+    let Some(time) = signed_at(hmac, &tag[TAG_PREFIX.len()..], &msg) else {
+        warn!(
+            "Dropping message {} with a signature that doesn't match HMAC_SECRET",
+            msg.id.as_deref().unwrap_or_default()
+        );
+        return None;
+    };
+    // The server's time is new whenever a signed message is published
+    // again, the signed one isn't.
+    if let Some(window) = config.replay_window {
+        if !filter::within(window, time) {
+            warn!(
+                "Dropping message {} signed at {}, outside REPLAY_WINDOW",
+                msg.id.as_deref().unwrap_or_default(),
+                time
+            );
+            return None;
+        }
+    }
+    Some(msg)
+}
+
+/// The signed time, if the signature is valid.
+fn signed_at(config: &HmacConfig, signature: &str, msg: &WSMessage) -> Option<u64> {
+    let (time, expected) = signature.split_once(':')?;
+    let time = time.parse().ok()?;
+    let expected = URL_SAFE_NO_PAD.decode(expected).ok()?;
+    let signed = Signed {
+        time,
+        topic: &msg.topic,
+        title: msg.title.as_deref().unwrap_or_default(),
+        // Without the signature's own tag, which was taken out already.
+        tags: msg.tags.as_deref().unwrap_or_default(),
+        body: msg.message.as_deref().unwrap_or_default(),
+    };
+    signed.mac(config).verify_slice(&expected).ok()?;
+    Some(time)
+}
+
+/// What a signature covers.
+struct Signed<'a> {
+    time: u64,
+    topic: &'a str,
+    title: &'a str,
+    tags: &'a [String],
+    body: &'a str,
+}
+
+impl Signed<'_> {
+    fn mac(&self, config: &HmacConfig) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(config.secret.as_bytes()).expect("any key length");
+        mac.update(
+            format!(
+                "{}\n{}\n{}\n{}\n{}",
+                self.time,
+                self.topic,
+                self.title,
+                self.tags.join(","),
+                self.body
+            )
+            .as_bytes(),
+        );
+        mac
+    }
+}
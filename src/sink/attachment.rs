@@ -0,0 +1,180 @@
+use super::{Sink, TempFile};
+use crate::config::{AttachmentConfig, Config};
+use crate::error::{AuthError, ConnectError, Error, StorageError};
+use crate::events::EventBus;
+use crate::message::{Attachment, WSMessage};
+use log::info;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+use url::Url;
+
+/// Saves attachments as `<dir>/<topic>/<YYYY-MM-DD>/<name>`, never
+/// overwriting an existing file, so the tree can live in a synced folder.
+pub(crate) struct AttachmentSink {
+    pub config: AttachmentConfig,
+    /// The server's topic URL; the token is only sent to the same origin.
+    pub server: Url,
+    /// Whose credentials are sent.
+    pub account: Config,
+    pub client: reqwest::Client,
+}
+
+impl Sink for AttachmentSink {
+    const NAME: &'static str = "attachment";
+    // Large files on a slow link take as long as they take.
+    const DEADLINE: Option<Duration> = None;
+
+    async fn write(&mut self, msg: &WSMessage, _events: &EventBus) -> Result<(), Error> {
+        let Some(attachment) = &msg.attachment else {
+            return Ok(());
+        };
+        let time = msg.time.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs())
+        });
+        let dir = self.config.dir.join(sanitize(&msg.topic)).join(date(time));
+        let budget = match self.config.quota {
+            Some(quota) => {
+                let root = self.config.dir.clone();
+                let used = tokio::task::spawn_blocking(move || usage(&root))
+                    .await
+                    .unwrap_or(0);
+                let budget = quota.saturating_sub(used);
+                let size = attachment.size.unwrap_or(0);
+                if size > budget {
+                    return Err(StorageError::Quota { size, quota }.into());
+                }
+                Some((budget, quota))
+            }
+            None => None,
+        };
+
+        // Once renamed there's nothing left to remove.
+        let part = TempFile(dir.join(format!(".{}.part", sanitize(&attachment.name))));
+        let path = self.download(attachment, &dir, &part.0, budget).await?;
+        info!("saved attachment to {}", path.display());
+        Ok(())
+    }
+}
+
+impl AttachmentSink {
+    async fn download(
+        &self,
+        attachment: &Attachment,
+        dir: &Path,
+        part: &Path,
+        budget: Option<(u64, u64)>,
+    ) -> Result<PathBuf, Error> {
+        let url = Url::parse(&attachment.url).map_err(ConnectError::from)?;
+        let mut request = self.client.get(url.clone());
+        if url.origin() == self.server.origin() {
+            if let Some(authorization) = self.account.authorization()? {
+                request = request.header("Authorization", authorization);
+            }
+        }
+        let mut response = request.send().await.map_err(ConnectError::from)?;
+        let status = response.status().as_u16();
+        match status {
+            200..=299 => {}
+            401 | 403 => return Err(AuthError::Rejected(status).into()),
+            _ => return Err(ConnectError::Status(status).into()),
+        }
+
+        let write_error = |path: &Path| {
+            let path = path.to_path_buf();
+            move |source| StorageError::Write { path, source }
+        };
+        tokio::fs::create_dir_all(dir)
+            .await
+            .map_err(write_error(dir))?;
+        let mut file = File::create(part).await.map_err(write_error(part))?;
+        let mut written = 0;
+        while let Some(chunk) = response.chunk().await.map_err(ConnectError::from)? {
+            written += chunk.len() as u64;
+            if let Some((budget, quota)) = budget {
+                if written > budget {
+                    return Err(StorageError::Quota {
+                        size: written,
+                        quota,
+                    }
+                    .into());
+                }
+            }
+            file.write_all(&chunk).await.map_err(write_error(part))?;
+        }
+        file.flush().await.map_err(write_error(part))?;
+
+        let path = unique_path(dir, &sanitize(&attachment.name));
+        tokio::fs::rename(part, &path)
+            .await
+            .map_err(write_error(&path))?;
+        Ok(path)
+    }
+}
+
+/// Keeps names to a single, non-hidden path component.
+fn sanitize(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | '\0' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    let name = name.trim_start_matches('.').trim();
+    if name.is_empty() {
+        "attachment".to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+/// `name`, or `stem (n).ext` for the first `n` that isn't taken.
+fn unique_path(dir: &Path, name: &str) -> PathBuf {
+    let path = dir.join(name);
+    if !path.exists() {
+        return path;
+    }
+    let (stem, ext) = match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => (stem, format!(".{ext}")),
+        _ => (name, String::new()),
+    };
+    (1..)
+        .map(|n| dir.join(format!("{stem} ({n}){ext}")))
+        .find(|path| !path.exists())
+        .expect("some name is free")
+}
+
+fn usage(dir: &Path) -> u64 {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .map(|entry| match entry.file_type() {
+            Ok(t) if t.is_dir() => usage(&entry.path()),
+            Ok(_) => entry.metadata().map_or(0, |m| m.len()),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+/// The UTC date of a Unix timestamp as `YYYY-MM-DD`.
+fn date(secs: u64) -> String {
+    // Howard Hinnant's days-to-civil algorithm.
+    let z = (secs / 86_400) as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
@@ -0,0 +1,54 @@
+use super::Sink;
+use crate::clip;
+use crate::config::Config;
+use crate::error::Error;
+use crate::events::{Event, EventBus};
+use crate::message::WSMessage;
+use crate::publish;
+use log::error;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub(crate) struct ClipboardSink {
+    /// Where writes are acknowledged, see [`ack`].
+    pub ack: Option<Config>,
+}
+
+impl Sink for ClipboardSink {
+    const NAME: &'static str = "clipboard";
+
+    async fn write(&mut self, msg: &WSMessage, events: &EventBus) -> Result<(), Error> {
+        let Some(content) = &msg.message else {
+            return Ok(());
+        };
+        // The one copy, shared from here on with the writers and the event.
+        let content: Arc<str> = content.as_str().into();
+        clip::write(content.clone()).await?;
+        events.emit_with(|| Event::ClipboardWritten { content });
+        ack(self.ack.as_ref(), msg);
+        Ok(())
+    }
+
+    fn stalled(&mut self) {
+        clip::abandon();
+    }
+}
+
+/// Publishes the id of the message just written, this device's name and the
+/// time to the topic of `config`, in the background so a slow server doesn't
+/// hold up the next write.
+pub(super) fn ack(config: Option<&Config>, msg: &WSMessage) {
+    let Some(config) = config.cloned() else {
+        return;
+    };
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let body = serde_json::json!({ "id": msg.id, "device": config.device, "time": time });
+    tokio::spawn(async move {
+        if let Err(e) = publish::publish(&config, &body.to_string()).await {
+            error!("Failed to acknowledge clipboard write: {}", e);
+        }
+    });
+}
@@ -0,0 +1,61 @@
+use super::Sink;
+use crate::clip;
+use crate::config::{Config, CopyqConfig};
+use crate::error::{ClipboardError, Error};
+use crate::events::{Event, EventBus};
+use crate::message::WSMessage;
+use log::debug;
+use std::process::{Command, Stdio};
+
+/// Sets the clipboard through CopyQ, so content lands in its history tagged
+/// with the topic instead of as an anonymous clipboard change.
+pub(crate) struct CopyqSink {
+    pub config: CopyqConfig,
+    /// Where writes are acknowledged, see [`super::clipboard::ack`].
+    pub ack: Option<Config>,
+}
+
+impl Sink for CopyqSink {
+    const NAME: &'static str = "copyq";
+
+    async fn write(&mut self, msg: &WSMessage, events: &EventBus) -> Result<(), Error> {
+        let Some(content) = &msg.message else {
+            return Ok(());
+        };
+        // `add` can't set tags, so the item is written with them, and then
+        // selected, which copies it without CopyQ adding it a second time.
+        // The content is read from stdin rather than put where `ps` shows it.
+        self.copyq(
+            &[
+                "write",
+                "0",
+                "text/plain",
+                "-",
+                "application/x-copyq-tags",
+                &msg.topic,
+            ],
+            content,
+        )
+        .await?;
+        clip::mark_written(content);
+        self.copyq(&["select", "0"], "").await?;
+        debug!("added {} bytes to CopyQ", content.len());
+        events.emit_with(|| Event::ClipboardWritten {
+            content: content.as_str().into(),
+        });
+        super::clipboard::ack(self.ack.as_ref(), msg);
+        Ok(())
+    }
+}
+
+impl CopyqSink {
+    /// Runs `copyq` with `args`, writing `input` to its stdin.
+    async fn copyq(&self, args: &[&str], input: &str) -> Result<(), ClipboardError> {
+        let mut cmd = Command::new("copyq");
+        if let Some(tab) = &self.config.tab {
+            cmd.args(["tab", tab]);
+        }
+        cmd.args(args).stdout(Stdio::null());
+        clip::run_async("copyq", cmd, input).await
+    }
+}
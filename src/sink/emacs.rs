@@ -0,0 +1,100 @@
+use super::{Sink, TempFile};
+use crate::config::EmacsConfig;
+use crate::error::{ClipboardError, Error, StorageError};
+use crate::events::EventBus;
+use crate::message::WSMessage;
+use log::debug;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::{self, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+pub(crate) struct EmacsSink {
+    pub config: EmacsConfig,
+}
+
+impl Sink for EmacsSink {
+    const NAME: &'static str = "emacs";
+
+    async fn write(&mut self, msg: &WSMessage, _events: &EventBus) -> Result<(), Error> {
+        let Some(content) = &msg.message else {
+            return Ok(());
+        };
+        if let Some(topics) = &self.config.topics {
+            if !topics.contains(&msg.topic) {
+                return Ok(());
+            }
+        }
+        // Handed over in a file only the user can read, since anyone can see
+        // the command line.
+        let file = TempFile(temp_path());
+        write_private(&file.0, content)
+            .await
+            .map_err(|source| StorageError::Write {
+                path: file.0.clone(),
+                source,
+            })?;
+        let eval = format!(
+            "(kill-new (with-temp-buffer \
+             (let ((coding-system-for-read 'utf-8)) (insert-file-contents {})) \
+             (buffer-string)))",
+            elisp_string(&file.0.to_string_lossy())
+        );
+        let mut cmd = Command::new("emacsclient");
+        if let Some(socket) = &self.config.socket {
+            cmd.args(["-s", socket]);
+        }
+        let status = cmd
+            .args(["--eval", &eval])
+            .stdout(Stdio::null())
+            .status()
+            .await
+            .map_err(|source| ClipboardError::Spawn {
+                command: "emacsclient",
+                source,
+            })?;
+        if !status.success() {
+            return Err(ClipboardError::Exit {
+                command: "emacsclient",
+                status,
+            }
+            .into());
+        }
+        debug!("pushed {} bytes onto the Emacs kill ring", content.len());
+        Ok(())
+    }
+}
+
+/// A fresh name in the temporary directory.
+fn temp_path() -> PathBuf {
+    static COUNT: AtomicU64 = AtomicU64::new(0);
+    let n = COUNT.fetch_add(1, Ordering::Relaxed);
+    env::temp_dir().join(format!("n2c-emacs-{}-{}", process::id(), n))
+}
+
+async fn write_private(path: &Path, content: &str) -> std::io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(path).await?;
+    file.write_all(content.as_bytes()).await?;
+    file.flush().await
+}
+
+/// Quotes `s` as an Emacs Lisp string literal.
+fn elisp_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
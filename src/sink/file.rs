@@ -0,0 +1,58 @@
+use super::Sink;
+use crate::config::Template;
+use crate::error::{Error, StorageError};
+use crate::events::EventBus;
+use crate::message::WSMessage;
+use crate::transform;
+use log::debug;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+
+/// Appends messages to a file, one per line or in `format`.
+pub(crate) struct FileSink {
+    pub path: PathBuf,
+    /// Rendered for each message, with the time of arrival for messages
+    /// without one.
+    pub format: Option<Template>,
+}
+
+impl Sink for FileSink {
+    const NAME: &'static str = "file";
+
+    async fn write(&mut self, msg: &WSMessage, _events: &EventBus) -> Result<(), Error> {
+        let Some(content) = &msg.message else {
+            return Ok(());
+        };
+        let write_error = |source| StorageError::Write {
+            path: self.path.clone(),
+            source,
+        };
+        let mut line = match &self.format {
+            Some(format) => {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.as_secs());
+                let msg = WSMessage {
+                    time: msg.time.or(Some(now)),
+                    ..msg.clone()
+                };
+                transform::render(format, &msg)
+            }
+            None => content.clone(),
+        };
+        if !line.ends_with('\n') {
+            line.push('\n');
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await
+            .map_err(write_error)?;
+        file.write_all(line.as_bytes()).await.map_err(write_error)?;
+        debug!("appended {} bytes to {}", line.len(), self.path.display());
+        Ok(())
+    }
+}
@@ -0,0 +1,27 @@
+use super::Sink;
+use crate::error::{Error, ProtocolError};
+use crate::events::EventBus;
+use crate::hub::Frame;
+use crate::message::WSMessage;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+pub(crate) struct HubSink {
+    pub tx: broadcast::Sender<Arc<Frame>>,
+}
+
+impl Sink for HubSink {
+    const NAME: &'static str = "hub";
+
+    async fn write(&mut self, msg: &WSMessage, _events: &EventBus) -> Result<(), Error> {
+        let text = serde_json::to_string(msg).map_err(ProtocolError::from)?;
+        // No connected clients is not an error.
+        let _ = self.tx.send(Arc::new(Frame {
+            topic: msg.topic.clone(),
+            id: msg.id.clone(),
+            time: msg.time,
+            text,
+        }));
+        Ok(())
+    }
+}
@@ -0,0 +1,29 @@
+use super::Sink;
+use crate::error::{ClipboardError, Error};
+use crate::events::EventBus;
+use crate::kdeconnect::{self, KdeConnectConfig};
+use crate::message::WSMessage;
+use zbus::Connection;
+
+pub(crate) struct KdeConnectSink {
+    pub config: KdeConnectConfig,
+    /// Opened on first use and dropped on failure so the next write reconnects.
+    pub conn: Option<Connection>,
+}
+
+impl Sink for KdeConnectSink {
+    const NAME: &'static str = "kdeconnect";
+
+    async fn write(&mut self, msg: &WSMessage, _events: &EventBus) -> Result<(), Error> {
+        let Some(content) = &msg.message else {
+            return Ok(());
+        };
+        let conn = match &self.conn {
+            Some(conn) => conn.clone(),
+            None => Connection::session().await.map_err(ClipboardError::from)?,
+        };
+        let result = kdeconnect::send(&conn, &self.config, content).await;
+        self.conn = result.is_ok().then_some(conn);
+        Ok(result?)
+    }
+}
@@ -0,0 +1,123 @@
+mod attachment;
+mod clipboard;
+mod copyq;
+mod emacs;
+mod file;
+mod hub;
+#[cfg(feature = "kdeconnect")]
+mod kdeconnect;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+#[cfg(feature = "neovim")]
+mod neovim;
+mod open;
+mod ssh;
+
+pub(crate) use attachment::AttachmentSink;
+pub(crate) use clipboard::ClipboardSink;
+pub(crate) use copyq::CopyqSink;
+pub(crate) use emacs::EmacsSink;
+pub(crate) use file::FileSink;
+pub(crate) use hub::HubSink;
+#[cfg(feature = "kdeconnect")]
+pub(crate) use kdeconnect::KdeConnectSink;
+#[cfg(feature = "mqtt")]
+pub(crate) use mqtt::MqttSink;
+#[cfg(feature = "neovim")]
+pub(crate) use neovim::NeovimSink;
+pub(crate) use open::OpenSink;
+pub(crate) use ssh::SshSink;
+
+use crate::channel::{OverflowPolicy, Receiver};
+use crate::error::Error;
+use crate::events::{Event, EventBus};
+use crate::message::WSMessage;
+use log::{debug, error};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::time::{self, Duration};
+
+/// The final stage of the pipeline; each sink runs in its own task.
+pub(crate) trait Sink: Send + 'static {
+    const NAME: &'static str;
+    /// Longest a single write may take before the watchdog abandons it.
+    /// Dropping the write kills any command it is still waiting on, like a
+    /// wedged `xclip`. `None` for sinks whose writes may rightly take long.
+    const DEADLINE: Option<Duration> = Some(Duration::from_secs(60));
+
+    fn write(
+        &mut self,
+        msg: &WSMessage,
+        events: &EventBus,
+    ) -> impl std::future::Future<Output = Result<(), Error>> + Send;
+
+    /// Called once the watchdog abandoned a write, to let go of whatever
+    /// it may have left wedged.
+    fn stalled(&mut self) {}
+}
+
+/// Runs `sink` on every message from `rx`. With [`OverflowPolicy::Latest`]
+/// only the newest message matters, so a write still in flight when a newer
+/// message arrives is cancelled.
+pub(crate) fn spawn<S: Sink>(mut sink: S, mut rx: Receiver<Arc<WSMessage>>, events: EventBus) {
+    tokio::spawn(async move {
+        let mut next = rx.recv().await;
+        while let Some(msg) = next.take() {
+            let result = if rx.policy() == OverflowPolicy::Latest {
+                tokio::select! {
+                    result = guarded(&mut sink, &msg, &events) => result,
+                    newer = rx.recv() => match newer {
+                        Some(newer) => {
+                            debug!("{} sink cancelled a superseded write", S::NAME);
+                            next = Some(newer);
+                            continue;
+                        }
+                        // Senders are gone, so nothing can supersede it anymore.
+                        None => guarded(&mut sink, &msg, &events).await,
+                    },
+                }
+            } else {
+                guarded(&mut sink, &msg, &events).await
+            };
+            if let Err(e) = result {
+                error!("{} sink failed: {}", S::NAME, e);
+                events.emit(Event::Error {
+                    message: e.to_string(),
+                    retryable: e.is_retryable(),
+                });
+            }
+            if next.is_none() {
+                next = rx.recv().await;
+            }
+        }
+    });
+}
+
+/// [`Sink::write`] under the watchdog: a write that hangs past
+/// [`Sink::DEADLINE`] is given up on, so it can't stall every later one.
+async fn guarded<S: Sink>(sink: &mut S, msg: &WSMessage, events: &EventBus) -> Result<(), Error> {
+    let Some(deadline) = S::DEADLINE else {
+        return sink.write(msg, events).await;
+    };
+    match time::timeout(deadline, sink.write(msg, events)).await {
+        Ok(result) => result,
+        Err(_) => {
+            sink.stalled();
+            events.emit(Event::SinkStalled {
+                sink: S::NAME,
+                deadline,
+            });
+            Err(Error::Stalled(deadline))
+        }
+    }
+}
+
+/// A file a write leaves behind only while it's in progress, removed when
+/// dropped, so neither a failed nor a cancelled write leaves it around.
+struct TempFile(PathBuf);
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
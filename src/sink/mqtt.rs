@@ -0,0 +1,30 @@
+use super::Sink;
+use crate::error::{ConnectError, Error};
+use crate::events::EventBus;
+use crate::message::WSMessage;
+use rumqttc::{AsyncClient, QoS};
+
+pub(crate) struct MqttSink {
+    pub client: AsyncClient,
+    pub topic: String,
+}
+
+impl Sink for MqttSink {
+    const NAME: &'static str = "mqtt";
+
+    async fn write(&mut self, msg: &WSMessage, _events: &EventBus) -> Result<(), Error> {
+        let Some(content) = &msg.message else {
+            return Ok(());
+        };
+        self.client
+            .publish(
+                &self.topic,
+                QoS::AtLeastOnce,
+                false,
+                content.as_bytes().to_vec(),
+            )
+            .await
+            .map_err(ConnectError::from)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,28 @@
+use super::Sink;
+use crate::error::{ClipboardError, Error};
+use crate::events::EventBus;
+use crate::message::WSMessage;
+use crate::neovim::{self, NeovimConfig};
+
+pub(crate) struct NeovimSink {
+    pub config: NeovimConfig,
+}
+
+impl Sink for NeovimSink {
+    const NAME: &'static str = "neovim";
+
+    async fn write(&mut self, msg: &WSMessage, _events: &EventBus) -> Result<(), Error> {
+        let Some(content) = &msg.message else {
+            return Ok(());
+        };
+        let config = self.config.clone();
+        let content = content.clone();
+        tokio::task::spawn_blocking(move || neovim::send(&config, &content))
+            .await
+            .map_err(|e| ClipboardError::Write {
+                command: "nvim",
+                source: std::io::Error::other(e),
+            })??;
+        Ok(())
+    }
+}
@@ -0,0 +1,56 @@
+use super::Sink;
+use crate::error::{ClipboardError, Error};
+use crate::events::EventBus;
+use crate::message::WSMessage;
+use log::{debug, warn};
+use std::process::Stdio;
+use tokio::process::Command;
+use url::Url;
+
+#[cfg(target_os = "macos")]
+const OPENER: (&str, &[&str]) = ("open", &[]);
+#[cfg(windows)]
+const OPENER: (&str, &[&str]) = ("rundll32", &["url.dll,FileProtocolHandler"]);
+#[cfg(not(any(target_os = "macos", windows)))]
+const OPENER: (&str, &[&str]) = ("xdg-open", &[]);
+
+/// Opens URLs in the default browser.
+pub(crate) struct OpenSink;
+
+impl Sink for OpenSink {
+    const NAME: &'static str = "open";
+
+    async fn write(&mut self, msg: &WSMessage, _events: &EventBus) -> Result<(), Error> {
+        let Some(content) = &msg.message else {
+            return Ok(());
+        };
+        // Anything else could be a local file or a custom scheme's handler.
+        let url = match Url::parse(content.trim()) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => url,
+            _ => {
+                warn!("not opening {:?}, it isn't an http(s) URL", content);
+                return Ok(());
+            }
+        };
+        let (program, args) = OPENER;
+        let status = Command::new(program)
+            .args(args)
+            .arg(url.as_str())
+            .stdout(Stdio::null())
+            .status()
+            .await
+            .map_err(|source| ClipboardError::Spawn {
+                command: program,
+                source,
+            })?;
+        if !status.success() {
+            return Err(ClipboardError::Exit {
+                command: program,
+                status,
+            }
+            .into());
+        }
+        debug!("opened {}", url);
+        Ok(())
+    }
+}
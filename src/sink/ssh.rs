@@ -0,0 +1,90 @@
+use super::Sink;
+use crate::clip;
+use crate::config::SshConfig;
+use crate::error::{ClipboardError, Error};
+use crate::events::{Event, EventBus};
+use crate::message::WSMessage;
+use futures_util::future::join_all;
+use log::{debug, error};
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::time::{self, Duration};
+
+/// Covers a slow login as well as the remote command itself.
+const FORWARD_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Writes the sequence from stdin to every terminal the user owns, so any
+/// OSC 52 capable terminal attached to the host picks it up.
+const OSC52_SCRIPT: &str = r#"s=$(cat); for t in /dev/pts/[0-9]*; do [ -O "$t" ] && [ -w "$t" ] && printf '%s' "$s" > "$t"; done; true"#;
+
+pub(crate) struct SshSink {
+    pub config: SshConfig,
+}
+
+impl Sink for SshSink {
+    const NAME: &'static str = "ssh";
+
+    async fn write(&mut self, msg: &WSMessage, events: &EventBus) -> Result<(), Error> {
+        let Some(content) = &msg.message else {
+            return Ok(());
+        };
+        let (command, input) = match &self.config.command {
+            Some(command) => (command.as_str(), content.clone()),
+            None => (OSC52_SCRIPT, clip::osc52(content)),
+        };
+        let hosts = &self.config.hosts;
+        let results = join_all(hosts.iter().map(|host| forward(host, command, &input))).await;
+        // One unreachable host shouldn't hide the others, so report each.
+        for (host, result) in hosts.iter().zip(results) {
+            match result {
+                Ok(()) => debug!("forwarded {} bytes to {}", content.len(), host),
+                Err(e) => {
+                    error!("Failed to forward clipboard to {}: {}", host, e);
+                    events.emit(Event::Error {
+                        message: format!("{}: {}", host, e),
+                        retryable: e.is_retryable(),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn forward(host: &str, command: &str, input: &str) -> Result<(), ClipboardError> {
+    let mut child = Command::new("ssh")
+        .args(["-o", "BatchMode=yes", "-o", "ConnectTimeout=10", "-T"])
+        .args([host, "--", command])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|source| ClipboardError::Spawn {
+            command: "ssh",
+            source,
+        })?;
+    let write_err = |source| ClipboardError::Write {
+        command: "ssh",
+        source,
+    };
+    let run = async {
+        let mut stdin = child.stdin.take().ok_or(ClipboardError::Write {
+            command: "ssh",
+            source: std::io::Error::other("failed to open stdin"),
+        })?;
+        stdin.write_all(input.as_bytes()).await.map_err(write_err)?;
+        drop(stdin);
+        child.wait().await.map_err(write_err)
+    };
+    let status = time::timeout(FORWARD_TIMEOUT, run)
+        .await
+        .map_err(|_| write_err(std::io::ErrorKind::TimedOut.into()))??;
+    if !status.success() {
+        return Err(ClipboardError::Exit {
+            command: "ssh",
+            status,
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,151 @@
+//! Generic Server-Sent Events source: each event's `data` (or a JSON field
+//! inside it) enters the pipeline as a message for the configured topic.
+
+use crate::channel::Sender;
+use crate::config::SseConfig;
+use crate::error::{AuthError, ConnectError, Result};
+use crate::message::{self, WSMessage};
+use log::{debug, error, info, warn};
+use serde_json::Value;
+use tokio::time::{self, Duration};
+
+/// Incrementally splits an SSE byte stream into the `data` of each event.
+/// Lines and events over [`message::MAX_SIZE`] are dropped rather than
+/// buffered until they end.
+#[derive(Default)]
+pub(crate) struct EventParser {
+    buf: Vec<u8>,
+    data: Option<String>,
+    /// The rest of a line too long to buffer is still to come.
+    overlong: bool,
+    /// The current event grew too large and is skipped up to its end.
+    dropping: bool,
+}
+
+impl EventParser {
+    /// Feeds a chunk and returns the events it completed.
+    pub fn push(&mut self, mut chunk: &[u8]) -> Vec<String> {
+        if self.overlong {
+            let Some(end) = chunk.iter().position(|&b| b == b'\n') else {
+                return Vec::new();
+            };
+            chunk = &chunk[end + 1..];
+            self.overlong = false;
+        }
+        self.buf.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        while let Some(end) = self.buf.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\n', '\r']);
+            if line.is_empty() {
+                if !std::mem::take(&mut self.dropping) {
+                    events.extend(self.data.take());
+                }
+                continue;
+            }
+            if self.dropping {
+                continue;
+            }
+            let (field, value) = line.split_once(':').unwrap_or((line, ""));
+            if field == "data" {
+                let value = value.strip_prefix(' ').unwrap_or(value);
+                match &mut self.data {
+                    Some(data) => {
+                        data.push('\n');
+                        data.push_str(value);
+                    }
+                    None => self.data = Some(value.to_string()),
+                }
+                if self
+                    .data
+                    .as_ref()
+                    .is_some_and(|data| data.len() > message::MAX_SIZE)
+                {
+                    self.drop_event();
+                }
+            }
+        }
+        if self.buf.len() > message::MAX_SIZE {
+            self.buf.clear();
+            self.overlong = true;
+            self.drop_event();
+        }
+        events
+    }
+
+    fn drop_event(&mut self) {
+        warn!("Dropping an event over {} bytes", message::MAX_SIZE);
+        self.data = None;
+        self.dropping = true;
+    }
+}
+
+pub(crate) fn spawn(
+    config: &SseConfig,
+    topic: &str,
+    client: reqwest::Client,
+    pipeline: Sender<WSMessage>,
+) {
+    let config = config.clone();
+    let topic = topic.to_string();
+    tokio::spawn(async move {
+        loop {
+            match read(&config, &topic, &client, &pipeline).await {
+                Ok(true) => info!("SSE stream {} closed", config.url),
+                Ok(false) => return,
+                Err(e) => error!("SSE source error: {}. Reconnecting...", e),
+            }
+            time::sleep(Duration::from_secs(5)).await;
+        }
+    });
+}
+
+/// Reads the stream until it ends. Returns `false` once the pipeline is gone.
+async fn read(
+    config: &SseConfig,
+    topic: &str,
+    client: &reqwest::Client,
+    pipeline: &Sender<WSMessage>,
+) -> Result<bool> {
+    let mut request = client
+        .get(config.url.clone())
+        .header("Accept", "text/event-stream");
+    if let Some(token) = &config.token {
+        request = request.bearer_auth(token);
+    }
+    let mut response = request.send().await.map_err(ConnectError::from)?;
+    let status = response.status().as_u16();
+    match status {
+        200..=299 => info!("connected to SSE stream {}", config.url),
+        401 | 403 => return Err(AuthError::Rejected(status).into()),
+        _ => return Err(ConnectError::Status(status).into()),
+    }
+
+    let mut parser = EventParser::default();
+    while let Some(chunk) = response.chunk().await.map_err(ConnectError::from)? {
+        for data in parser.push(&chunk) {
+            let message = match &config.field {
+                Some(field) => serde_json::from_str::<Value>(&data)
+                    .ok()
+                    .and_then(|json| field.text(&json)),
+                None => Some(data),
+            };
+            let Some(message) = message else {
+                debug!("SSE event without {:?}", config.field);
+                continue;
+            };
+            let msg = WSMessage {
+                id: None,
+                event: "message".to_string(),
+                topic: topic.to_string(),
+                message: Some(message),
+                ..Default::default()
+            };
+            if !pipeline.send(msg).await {
+                return Ok(false);
+            }
+        }
+    }
+    Ok(true)
+}
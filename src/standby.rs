@@ -0,0 +1,143 @@
+//! Warm standby connection for instant failover.
+//!
+//! A second subscription is kept open next to the primary one, so when the
+//! primary dies the supervisor can switch over without waiting for backoff
+//! and a fresh handshake. Its messages are only kept for a short while, to
+//! replay whatever the primary may have missed before it was noticed dead.
+
+use crate::config::Config;
+use crate::jitter;
+use crate::message::{self, WSMessage};
+use crate::repeats::Repeats;
+use crate::suspend;
+use crate::unifiedpush;
+use crate::ws::{self, WsStream};
+use futures_util::{SinkExt, StreamExt};
+use log::{debug, error, info};
+use std::collections::VecDeque;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{self, Duration, Instant};
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+/// Upper bound for the replay buffer, whatever the timeout.
+const MAX_RECENT: usize = 64;
+
+type Handover = (WsStream, unifiedpush::Changes, Vec<WSMessage>);
+
+/// Handle to the task holding the standby connection.
+pub(crate) struct Standby {
+    promote: mpsc::Sender<oneshot::Sender<Handover>>,
+}
+
+impl Standby {
+    /// Keeps a connection to `config.server` open in the background.
+    pub(crate) fn spawn(config: Config) -> Self {
+        let (promote, rx) = mpsc::channel(1);
+        tokio::spawn(hold(config, rx));
+        Standby { promote }
+    }
+
+    /// Takes over the connection with the messages it received recently, or
+    /// `None` if it isn't connected right now.
+    pub(crate) async fn take(self) -> Option<Handover> {
+        let (tx, rx) = oneshot::channel();
+        self.promote.send(tx).await.ok()?;
+        rx.await.ok()
+    }
+}
+
+async fn hold(config: Config, mut promote: mpsc::Receiver<oneshot::Sender<Handover>>) {
+    let mut repeats = Repeats::new(module_path!());
+    loop {
+        let changes = unifiedpush::changes();
+        match ws::connect(&config, None).await {
+            Ok(stream) => {
+                repeats.finish();
+                info!("warm standby connected to {}", config.server);
+                match watch(stream, changes, &config, &mut promote).await {
+                    Some(()) => return,
+                    None => error!("Warm standby connection lost. Reconnecting..."),
+                }
+            }
+            Err(e) => repeats.error(&e.to_string(), || {
+                format!("Warm standby error: {}. Reconnecting...", e)
+            }),
+        }
+        // Dropping a promotion request while disconnected answers `None`.
+        tokio::select! {
+            _ = time::sleep(jitter::around(RETRY_DELAY)) => {}
+            request = promote.recv() => if request.is_none() {
+                return;
+            },
+        }
+    }
+}
+
+/// Reads the standby connection until it is handed over or no longer wanted
+/// (`Some`), or until it dies.
+async fn watch(
+    mut stream: WsStream,
+    changes: unifiedpush::Changes,
+    config: &Config,
+    promote: &mut mpsc::Receiver<oneshot::Sender<Handover>>,
+) -> Option<()> {
+    let timeout = config.timeout;
+    let mut ping_interval = time::interval_at(Instant::now() + jitter::random(timeout), timeout);
+    let mut last_traffic = Instant::now();
+    let mut clock = suspend::Clock::new();
+    let mut clock_interval = time::interval(suspend::CHECK_INTERVAL);
+    let mut recent: VecDeque<(Instant, WSMessage)> = VecDeque::new();
+    loop {
+        tokio::select! {
+            msg = stream.next() => {
+                last_traffic = Instant::now();
+                match msg? {
+                    Ok(Message::Text(text)) => {
+                        let Ok(msg) = message::decode(config, &text) else {
+                            continue;
+                        };
+                        if msg.event != "message" {
+                            continue;
+                        }
+                        // The primary can be silently dead for up to `timeout`.
+                        while recent.front().is_some_and(|(at, _)| at.elapsed() > timeout)
+                            || recent.len() >= MAX_RECENT
+                        {
+                            recent.pop_front();
+                        }
+                        recent.push_back((Instant::now(), msg));
+                    }
+                    Ok(Message::Ping(ping)) => {
+                        stream.send(Message::Pong(ping)).await.ok()?;
+                    }
+                    Ok(Message::Close(_)) | Err(_) => return None,
+                    Ok(_) => {}
+                }
+            }
+            request = promote.recv() => {
+                // The supervisor dropped its handle, so nobody wants it anymore.
+                let Some(reply) = request else {
+                    return Some(());
+                };
+                let recent = recent.into_iter().map(|(_, msg)| msg).collect();
+                debug!("handing over the warm standby connection");
+                let _ = reply.send((stream, changes, recent));
+                return Some(());
+            }
+            _ = ping_interval.tick() => {
+                if last_traffic.elapsed() > timeout {
+                    return None;
+                }
+                ping_interval.reset_after(jitter::around(timeout));
+            }
+            _ = clock_interval.tick() => {
+                if clock.suspended().is_some() {
+                    stream.send(Message::Ping(Default::default())).await.ok()?;
+                    last_traffic = Instant::now().checked_sub(timeout).unwrap_or(last_traffic);
+                    ping_interval.reset_after(ws::WAKE_PROBE);
+                }
+            }
+        }
+    }
+}
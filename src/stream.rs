@@ -0,0 +1,269 @@
+//! Subscribing over ntfy's HTTP streaming endpoints, for networks where
+//! WebSockets don't get through. They send the same JSON as the WebSocket
+//! endpoint, including the keepalives the idle timeout relies on: `/sse` as
+//! Server-Sent Events, `/json` one message per line. The latter also answers
+//! polls, for devices where a connection kept open costs too much power.
+
+use crate::channel::Sender;
+use crate::config::{CatchUp, Config};
+use crate::error::{AuthError, ConnectError, Result};
+use crate::events::EventBus;
+use crate::message::{self, WSMessage};
+use crate::sse::EventParser;
+use crate::suspend;
+use crate::tls;
+use crate::unifiedpush;
+use crate::ws::{self, IdleTimeout, Received};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use log::{debug, error, info, warn};
+use reqwest::Response;
+use tokio::time::{self, Instant};
+
+/// Most of a poll response read at once. The messages after it come after the
+/// last one delivered, so they're fetched the next time.
+const MAX_RESPONSE: usize = 64 * message::MAX_SIZE;
+
+/// The streaming endpoints, which only differ in framing.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Endpoint {
+    Sse,
+    Json,
+}
+
+impl Endpoint {
+    fn name(self) -> &'static str {
+        match self {
+            Endpoint::Sse => "SSE",
+            Endpoint::Json => "JSON stream",
+        }
+    }
+}
+
+/// Subscribes at `endpoint`; with `since`, ntfy first sends the messages
+/// after that id or time.
+pub(crate) async fn connect(
+    config: &Config,
+    endpoint: Endpoint,
+    since: Option<&str>,
+) -> Result<Response> {
+    let query: &[(&str, &str)] = match &since {
+        Some(since) => &[("since", since)],
+        None => &[],
+    };
+    let response = match endpoint {
+        Endpoint::Sse => get(config, "sse", query).await?,
+        Endpoint::Json => get(config, "json", query).await?,
+    };
+    info!(
+        "connected to {} over {} with topic={} and timeout={}",
+        config.server,
+        endpoint.name(),
+        config.topic,
+        config.timeout.as_secs()
+    );
+    Ok(response)
+}
+
+/// Polls `/json` for new messages every `config.poll_interval`, starting
+/// with those after `since` and moving it along.
+pub(crate) async fn poll(
+    config: &Config,
+    since: &mut String,
+    pipeline: &Sender<WSMessage>,
+    events: &EventBus,
+) -> Result<()> {
+    loop {
+        let body = fetch(config, since).await?;
+        for line in body.lines().filter(|line| !line.trim().is_empty()) {
+            if let Received::Message(Some(id)) = ws::receive(line, config, pipeline, events).await {
+                *since = id;
+            }
+        }
+        debug!(
+            "polled {}, next poll in {:?}",
+            config.topic, config.poll_interval
+        );
+        time::sleep(config.poll_interval).await;
+    }
+}
+
+/// Delivers the messages sent after `since` while disconnected: all of
+/// them, or with [`CatchUp::Latest`] only the newest.
+pub(crate) async fn catch_up(
+    config: &Config,
+    since: &mut String,
+    pipeline: &Sender<WSMessage>,
+) -> Result<()> {
+    let body = fetch(config, since).await?;
+    let mut missed = Vec::new();
+    for line in body.lines().filter(|line| !line.trim().is_empty()) {
+        match message::decode(config, line) {
+            Ok(msg) if msg.event == "message" => missed.push(msg),
+            Ok(_) => {}
+            Err(e) => error!("Protocol anomaly in missed messages: {}", e),
+        }
+    }
+    if missed.is_empty() {
+        return Ok(());
+    }
+    info!("{} messages arrived while disconnected", missed.len());
+    if config.catch_up == CatchUp::Latest {
+        missed.drain(..missed.len() - 1);
+    }
+    for msg in missed {
+        if let Some(id) = &msg.id {
+            since.clone_from(id);
+        }
+        pipeline.send(msg).await;
+    }
+    Ok(())
+}
+
+/// The messages after `since`, one per line, up to [`MAX_RESPONSE`].
+async fn fetch(config: &Config, since: &str) -> Result<String> {
+    let mut response = get(config, "json", &[("poll", "1"), ("since", since)]).await?;
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(ConnectError::from)? {
+        body.extend_from_slice(&chunk);
+        if body.len() > MAX_RESPONSE {
+            warn!(
+                "Poll response over {} bytes, fetching the rest later",
+                MAX_RESPONSE
+            );
+            let end = body[..MAX_RESPONSE]
+                .iter()
+                .rposition(|&b| b == b'\n')
+                .map_or(0, |end| end + 1);
+            body.truncate(end);
+            break;
+        }
+    }
+    Ok(String::from_utf8_lossy(&body).into_owned())
+}
+
+async fn get(config: &Config, path: &str, query: &[(&str, &str)]) -> Result<Response> {
+    let mut url = config
+        .stream_url(&unifiedpush::topics(), path)
+        .map_err(ConnectError::from)?;
+    if !query.is_empty() {
+        url.query_pairs_mut().extend_pairs(query);
+    }
+    let mut authorization = config.authorization()?;
+    // Like the WebSocket subscription, for proxies that strip the header.
+    if config.auth_query {
+        if let Some(value) = authorization.take() {
+            url.query_pairs_mut()
+                .append_pair("auth", &URL_SAFE_NO_PAD.encode(value));
+        }
+    }
+    let mut request = tls::http_client(config)?.get(url);
+    if path == "sse" {
+        request = request.header("Accept", "text/event-stream");
+    }
+    if let Some(authorization) = authorization {
+        request = request.header("Authorization", authorization);
+    }
+    let response = request.send().await.map_err(ConnectError::from)?;
+    let status = response.status().as_u16();
+    match status {
+        200..=299 => Ok(response),
+        401 | 403 => Err(AuthError::Rejected(status).into()),
+        _ => Err(ConnectError::Status(status).into()),
+    }
+}
+
+/// Serves the subscription, keeping `since` at the last message's id.
+pub(crate) async fn serve(
+    mut response: Response,
+    endpoint: Endpoint,
+    mut changes: unifiedpush::Changes,
+    config: &Config,
+    since: &mut String,
+    pipeline: &Sender<WSMessage>,
+    events: &EventBus,
+) -> Result<()> {
+    let mut timeout = IdleTimeout::new(config);
+    let idle = time::sleep(timeout.get());
+    tokio::pin!(idle);
+    let mut sse = EventParser::default();
+    let mut lines = Lines::default();
+    let mut clock = suspend::Clock::new();
+    let mut clock_interval = time::interval(suspend::CHECK_INTERVAL);
+
+    loop {
+        tokio::select! {
+            chunk = response.chunk() => {
+                let Some(chunk) = chunk.map_err(ConnectError::from)? else {
+                    debug!("{} ended", endpoint.name());
+                    return Ok(());
+                };
+                let texts = match endpoint {
+                    Endpoint::Sse => sse.push(&chunk),
+                    Endpoint::Json => lines.push(&chunk),
+                };
+                for text in texts {
+                    match ws::receive(&text, config, pipeline, events).await {
+                        Received::Message(Some(id)) => *since = id,
+                        Received::KeepAlive => timeout.keep_alive(),
+                        _ => {}
+                    }
+                }
+                idle.as_mut().reset(Instant::now() + timeout.get());
+            }
+            _ = &mut idle => return Err(ConnectError::Idle(timeout.get()).into()),
+            _ = changes.changed() => {
+                debug!("UnifiedPush registrations changed, resubscribing");
+                return Ok(());
+            }
+            _ = clock_interval.tick() => {
+                // There is nothing to ping over HTTP, so just start over.
+                if let Some(slept) = clock.suspended() {
+                    info!("resumed after {:?} of suspend, reconnecting", slept);
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+/// Splits a stream into its non-empty lines. A line growing past
+/// [`message::MAX_SIZE`] is dropped rather than buffered until it ends.
+#[derive(Default)]
+struct Lines {
+    buf: Vec<u8>,
+    /// The rest of a dropped line is still to come.
+    overlong: bool,
+}
+
+impl Lines {
+    /// Appends `chunk` and takes the complete lines out.
+    fn push(&mut self, mut chunk: &[u8]) -> Vec<String> {
+        if self.overlong {
+            let Some(end) = chunk.iter().position(|&b| b == b'\n') else {
+                return Vec::new();
+            };
+            chunk = &chunk[end + 1..];
+            self.overlong = false;
+        }
+        self.buf.extend_from_slice(chunk);
+        let lines = match self.buf.iter().rposition(|&b| b == b'\n') {
+            Some(end) => {
+                let complete: Vec<u8> = self.buf.drain(..=end).collect();
+                String::from_utf8_lossy(&complete)
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            }
+            None => Vec::new(),
+        };
+        if self.buf.len() > message::MAX_SIZE {
+            warn!("Dropping a line over {} bytes", message::MAX_SIZE);
+            self.buf.clear();
+            self.overlong = true;
+        }
+        lines
+    }
+}
@@ -0,0 +1,413 @@
+use crate::channel::Sender;
+use crate::config::{CatchUp, Config, ServerKind, Transport};
+use crate::error::{Error, Result};
+use crate::events::{ConnectionState, Event, EventBus};
+use crate::jitter;
+use crate::message::WSMessage;
+use crate::pipeline;
+use crate::repeats::Repeats;
+use crate::standby::Standby;
+use crate::stream;
+use crate::systemd;
+use crate::unifiedpush;
+use crate::ws::{self, WsStream};
+use log::{debug, error, info, warn};
+use std::mem;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::watch;
+use tokio::time::{self, Duration, Instant};
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(5);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// Longest wait before reconnecting after the server closed the connection.
+const CLOSE_SPREAD: Duration = Duration::from_secs(1);
+/// WebSocket connections failing in a row before [`Transport::Auto`]
+/// switches to SSE.
+const AUTO_FALLBACK: u32 = 3;
+
+/// Drives the connection through its [`ConnectionState`]s, emitting a
+/// [`Event::StateChanged`] for every transition.
+pub struct Supervisor {
+    config: Config,
+    events: EventBus,
+    paused: watch::Receiver<bool>,
+    reloads: watch::Receiver<Config>,
+    state: ConnectionState,
+    repeats: Repeats,
+}
+
+/// Cheap handle for pausing, resuming and reconfiguring a running
+/// [`Supervisor`].
+#[derive(Debug, Clone)]
+pub struct SupervisorHandle {
+    paused: Arc<watch::Sender<bool>>,
+    config: Arc<watch::Sender<Config>>,
+}
+
+impl SupervisorHandle {
+    /// Closes the connection and stays disconnected until [`resume`](Self::resume).
+    pub fn pause(&self) {
+        self.paused.send_replace(true);
+    }
+
+    pub fn resume(&self) {
+        self.paused.send_replace(false);
+    }
+
+    /// Reconnects with `config`. Filtering follows it right away, but sinks
+    /// and integrations keep the settings they were started with.
+    pub fn reload(&self, config: Config) {
+        self.config.send_replace(config);
+    }
+}
+
+impl Supervisor {
+    pub fn new(config: Config, events: EventBus) -> (Self, SupervisorHandle) {
+        let (tx, rx) = watch::channel(false);
+        let (config_tx, config_rx) = watch::channel(config.clone());
+        let supervisor = Supervisor {
+            config,
+            events,
+            paused: rx,
+            reloads: config_rx,
+            state: ConnectionState::Connecting,
+            repeats: Repeats::new(module_path!()),
+        };
+        let handle = SupervisorHandle {
+            paused: Arc::new(tx),
+            config: Arc::new(config_tx),
+        };
+        (supervisor, handle)
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        self.state
+    }
+
+    fn transition(&mut self, next: ConnectionState) {
+        debug!("connection state: {:?} -> {:?}", self.state, next);
+        self.state = next;
+        systemd::notify(next);
+        self.events.emit(Event::StateChanged(next));
+    }
+
+    fn report(&self, e: &Error) {
+        self.events.emit(Event::Error {
+            message: e.to_string(),
+            retryable: e.is_retryable(),
+        });
+    }
+
+    fn server_changed(&self, active: &Config) {
+        self.events.emit(Event::ServerChanged {
+            server: active.server.clone(),
+        });
+    }
+
+    /// Runs until an error occurs that reconnecting cannot fix.
+    pub async fn run(mut self) -> Result<()> {
+        let pipeline = pipeline::spawn(&self.config, &self.events, self.reloads.clone());
+        let mut backoff = INITIAL_BACKOFF;
+        // The active and standby servers swap roles on every failover.
+        let mut active = self.config.clone();
+        let mut standby_config = self.config.standby.as_ref().map(|(kind, server)| Config {
+            kind: *kind,
+            server: server.clone(),
+            ..self.config.clone()
+        });
+        let mut standby = None;
+        let mut promoted = None;
+        let mut transport = self.config.transport;
+        let mut ws_failures = 0;
+        // Where polling and reconnections pick up, the last message seen;
+        // nothing from before the start is wanted.
+        let mut since = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs())
+            .to_string();
+        let mut reconnecting = false;
+        // Failures in a row while going through the fallback servers.
+        let mut server_failures = 0;
+        loop {
+            if self.reloads.has_changed().unwrap_or(false) {
+                self.config = self.reloads.borrow_and_update().clone();
+                info!("Reloaded the configuration");
+                active = self.config.clone();
+                standby_config = self.config.standby.as_ref().map(|(kind, server)| Config {
+                    kind: *kind,
+                    server: server.clone(),
+                    ..self.config.clone()
+                });
+                standby = None;
+                promoted = None;
+                backoff = INITIAL_BACKOFF;
+                transport = self.config.transport;
+                ws_failures = 0;
+                server_failures = 0;
+            }
+            if *self.paused.borrow() {
+                standby = None;
+                self.transition(ConnectionState::Paused);
+                wait_for(&mut self.paused, false).await;
+            }
+            // The standby is a WebSocket too.
+            if standby.is_none() && matches!(transport, Transport::Auto | Transport::WebSocket) {
+                standby = standby_config.clone().map(Standby::spawn);
+            }
+
+            self.transition(ConnectionState::Connecting);
+            let connected = match promoted.take() {
+                Some((ws_stream, changes)) => Ok(Connection::WebSocket(ws_stream, changes)),
+                None => subscribe(&active, transport, reconnecting, &mut since, &pipeline).await,
+            };
+            reconnecting = true;
+            let mut connected_at = None;
+            let result = match connected {
+                Ok(connection) => {
+                    self.transition(ConnectionState::Connected);
+                    self.repeats.finish();
+                    backoff = INITIAL_BACKOFF;
+                    server_failures = 0;
+                    connected_at = Some(Instant::now());
+                    let mut paused = self.paused.clone();
+                    let mut reloads = self.reloads.clone();
+                    tokio::select! {
+                        result = connection.serve(&active, &mut since, &pipeline, &self.events) => result,
+                        _ = wait_for(&mut paused, true) => continue,
+                        _ = reloaded(&mut reloads) => continue,
+                    }
+                }
+                Err(e) => Err(e),
+            };
+
+            if transport == Transport::Auto && active.kind == ServerKind::Ntfy {
+                // Connections a proxy kills right away count as failures too.
+                let short = connected_at.is_none_or(|at| at.elapsed() < active.timeout);
+                match &result {
+                    Err(e) if e.is_retryable() && short => ws_failures += 1,
+                    _ => ws_failures = 0,
+                }
+                // Every server gets its tries.
+                if ws_failures >= AUTO_FALLBACK * (1 + self.config.fallback_servers.len() as u32) {
+                    info!(
+                        "WebSocket failed {} times in a row, switching to SSE",
+                        ws_failures
+                    );
+                    transport = Transport::Sse;
+                    standby = None;
+                }
+            }
+
+            match result {
+                Ok(()) => {
+                    info!("Connection closed cleanly");
+                    promoted =
+                        fail_over(&mut standby, &mut active, &mut standby_config, &pipeline).await;
+                    if promoted.is_some() {
+                        info!("Switched to {}", active.server);
+                        self.server_changed(&active);
+                    } else {
+                        // A restarting server closes everyone at once.
+                        time::sleep(jitter::random(CLOSE_SPREAD)).await;
+                    }
+                }
+                Err(e) if e.is_retryable() => {
+                    self.report(&e);
+                    promoted =
+                        fail_over(&mut standby, &mut active, &mut standby_config, &pipeline).await;
+                    if promoted.is_some() {
+                        error!("Connection error: {}. Switched to {}", e, active.server);
+                        self.server_changed(&active);
+                        continue;
+                    }
+                    // A connection that worked is retried on the same server.
+                    let next = match connected_at {
+                        Some(_) => None,
+                        None => next_server(&self.config, &active),
+                    };
+                    if let Some((kind, server)) = next {
+                        active.kind = kind;
+                        active.server = server;
+                        self.server_changed(&active);
+                        // Back off only once every server has failed.
+                        server_failures += 1;
+                        if server_failures <= self.config.fallback_servers.len() {
+                            error!("Connection error: {}. Switching to {}", e, active.server);
+                            continue;
+                        }
+                        server_failures = 0;
+                    }
+                    // Half fixed, half random, to spread out a fleet's retries.
+                    let delay = backoff / 2 + jitter::random(backoff / 2);
+                    self.repeats.error(&e.to_string(), || {
+                        format!("Connection error: {}. Reconnecting in {:?}...", e, delay)
+                    });
+                    self.transition(ConnectionState::Backoff(delay));
+                    let mut paused = self.paused.clone();
+                    let mut reloads = self.reloads.clone();
+                    tokio::select! {
+                        _ = time::sleep(delay) => {}
+                        _ = wait_for(&mut paused, true) => {}
+                        _ = reloaded(&mut reloads) => {}
+                    }
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+                Err(e) => {
+                    self.report(&e);
+                    self.transition(ConnectionState::GivingUp);
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
+
+/// The server after `current` in the list of [`Config::fallback_servers`],
+/// if there is one.
+fn next_server(config: &Config, current: &Config) -> Option<(ServerKind, String)> {
+    if config.fallback_servers.is_empty() {
+        return None;
+    }
+    let servers: Vec<(ServerKind, &String)> = std::iter::once((config.kind, &config.server))
+        .chain(
+            config
+                .fallback_servers
+                .iter()
+                .map(|(kind, server)| (*kind, server)),
+        )
+        .collect();
+    // The standby's server may not be on the list; start over then.
+    let next = servers
+        .iter()
+        .position(|&entry| entry == (current.kind, &current.server))
+        .map_or(0, |at| (at + 1) % servers.len());
+    let (kind, server) = servers[next];
+    Some((kind, server.clone()))
+}
+
+/// Opens the subscription. Reconnecting to ntfy, it first delivers what
+/// was missed in between as [`Config::catch_up`] says, then subscribes
+/// from there so nothing falls in the gap.
+async fn subscribe(
+    config: &Config,
+    transport: Transport,
+    reconnecting: bool,
+    since: &mut String,
+    pipeline: &Sender<WSMessage>,
+) -> Result<Connection> {
+    // Gotify only streams over WebSockets, whatever ntfy servers use.
+    let transport = match config.kind {
+        ServerKind::Ntfy => transport,
+        ServerKind::Gotify => Transport::WebSocket,
+    };
+    let catch_up = reconnecting
+        && config.kind == ServerKind::Ntfy
+        && config.catch_up != CatchUp::Off
+        && transport != Transport::Poll;
+    if !catch_up {
+        return Connection::open(config, transport, None).await;
+    }
+    // Without it ntfy still sends everything missed, just not trimmed to
+    // the latest, so a failed fetch mustn't keep the subscription down.
+    if let Err(e) = stream::catch_up(config, since, pipeline).await {
+        warn!(
+            "Failed to fetch the missed messages, subscribing from the last one: {}",
+            e
+        );
+    }
+    Connection::open(config, transport, Some(since)).await
+}
+
+/// An open subscription, over any [`Transport`]. There's only ever one, so
+/// the size difference doesn't matter.
+#[allow(clippy::large_enum_variant)]
+enum Connection {
+    WebSocket(WsStream, unifiedpush::Changes),
+    Stream(reqwest::Response, stream::Endpoint, unifiedpush::Changes),
+    /// Nothing is held open between polls.
+    Poll,
+}
+
+impl Connection {
+    /// Subscribes, starting after the message or time `since` if given.
+    async fn open(config: &Config, transport: Transport, since: Option<&str>) -> Result<Self> {
+        match transport {
+            Transport::Auto | Transport::WebSocket => {
+                let changes = unifiedpush::changes();
+                let ws_stream = ws::connect(config, since).await?;
+                Ok(Connection::WebSocket(ws_stream, changes))
+            }
+            Transport::Sse => Self::stream(config, stream::Endpoint::Sse, since).await,
+            Transport::Json => Self::stream(config, stream::Endpoint::Json, since).await,
+            Transport::Poll => Ok(Connection::Poll),
+        }
+    }
+
+    async fn stream(
+        config: &Config,
+        endpoint: stream::Endpoint,
+        since: Option<&str>,
+    ) -> Result<Self> {
+        let changes = unifiedpush::changes();
+        let response = stream::connect(config, endpoint, since).await?;
+        Ok(Connection::Stream(response, endpoint, changes))
+    }
+
+    async fn serve(
+        self,
+        config: &Config,
+        since: &mut String,
+        pipeline: &Sender<WSMessage>,
+        events: &EventBus,
+    ) -> Result<()> {
+        match self {
+            Connection::WebSocket(ws_stream, changes) => {
+                ws::serve(ws_stream, changes, config, since, pipeline, events).await
+            }
+            Connection::Stream(response, endpoint, changes) => {
+                stream::serve(response, endpoint, changes, config, since, pipeline, events).await
+            }
+            Connection::Poll => stream::poll(config, since, pipeline, events).await,
+        }
+    }
+}
+
+/// Takes over the warm standby connection if it is up, replaying what it
+/// received recently; the pipeline drops messages already delivered.
+async fn fail_over(
+    standby: &mut Option<Standby>,
+    active: &mut Config,
+    standby_config: &mut Option<Config>,
+    pipeline: &Sender<WSMessage>,
+) -> Option<(WsStream, unifiedpush::Changes)> {
+    let (ws_stream, changes, recent) = standby.take()?.take().await?;
+    mem::swap(active, standby_config.as_mut()?);
+    for msg in recent {
+        pipeline.send(msg).await;
+    }
+    Some((ws_stream, changes))
+}
+
+async fn wait_for(paused: &mut watch::Receiver<bool>, value: bool) {
+    // The sender lives in the handle; if every handle is gone the state can
+    // no longer change, so just wait forever.
+    if paused.wait_for(|v| *v == value).await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
+
+async fn reloaded(reloads: &mut watch::Receiver<Config>) {
+    // Like `wait_for`: without a handle there is nothing to reload.
+    if reloads.changed().await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
+
+/// Keeps the subscription alive, reconnecting after retryable errors.
+///
+/// Shorthand for [`Supervisor::run`] without pause control.
+pub async fn run(config: &Config, events: &EventBus) -> Result<()> {
+    let (supervisor, _handle) = Supervisor::new(config.clone(), events.clone());
+    supervisor.run().await
+}
@@ -0,0 +1,36 @@
+//! Noticing that the machine was suspended.
+//!
+//! `Instant` stops while the system sleeps on Linux and macOS, so a
+//! connection that died during a long suspend still looks recently active on
+//! wake. Comparing it with the wall clock shows the gap.
+
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often [`Clock::suspended`] should be called.
+pub(crate) const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// Gaps shorter than this are scheduling noise or clock adjustments.
+const THRESHOLD: Duration = Duration::from_secs(10);
+
+pub(crate) struct Clock {
+    monotonic: Instant,
+    wall: SystemTime,
+}
+
+impl Clock {
+    pub(crate) fn new() -> Self {
+        Clock {
+            monotonic: Instant::now(),
+            wall: SystemTime::now(),
+        }
+    }
+
+    /// How long the system was suspended since the last call, if it was.
+    pub(crate) fn suspended(&mut self) -> Option<Duration> {
+        let previous = std::mem::replace(self, Clock::new());
+        let monotonic = self.monotonic - previous.monotonic;
+        // A wall clock set backwards is no suspend.
+        let wall = self.wall.duration_since(previous.wall).ok()?;
+        let gap = wall.checked_sub(monotonic)?;
+        (gap > THRESHOLD).then_some(gap)
+    }
+}
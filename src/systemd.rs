@@ -0,0 +1,51 @@
+//! systemd's service notifications: with `Type=notify` the unit only counts
+//! as started once connected, and `systemctl status` shows how the connection
+//! is doing. Outside of systemd, without `NOTIFY_SOCKET`, nothing is sent.
+
+use crate::events::ConnectionState;
+use log::debug;
+
+/// Tells systemd about entering `state`.
+pub(crate) fn notify(state: ConnectionState) {
+    let status = match state {
+        ConnectionState::Connecting => "STATUS=Connecting".to_string(),
+        ConnectionState::Connected => "READY=1\nSTATUS=Connected".to_string(),
+        ConnectionState::Backoff(delay) => {
+            format!("STATUS=Reconnecting in {} seconds", delay.as_secs())
+        }
+        ConnectionState::Paused => "STATUS=Paused".to_string(),
+        ConnectionState::GivingUp => "STATUS=Giving up".to_string(),
+    };
+    if let Err(e) = send(&status) {
+        debug!("sd_notify failed: {}", e);
+    }
+}
+
+#[cfg(unix)]
+fn send(state: &str) -> std::io::Result<()> {
+    use std::os::unix::net::UnixDatagram;
+
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return Ok(());
+    };
+    let socket = UnixDatagram::unbound()?;
+    // A leading `@` names a socket in the abstract namespace.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    if let Some(name) = path.as_encoded_bytes().strip_prefix(b"@") {
+        #[cfg(target_os = "android")]
+        use std::os::android::net::SocketAddrExt;
+        #[cfg(target_os = "linux")]
+        use std::os::linux::net::SocketAddrExt;
+
+        let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+        socket.send_to_addr(state.as_bytes(), &addr)?;
+        return Ok(());
+    }
+    socket.send_to(state.as_bytes(), path)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn send(_state: &str) -> std::io::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,474 @@
+//! TLS settings for connections to the server, see [`TlsConfig`].
+//!
+//! The settings are turned into one configuration that is used for the
+//! WebSocket as well as the HTTP requests, so both trust the same
+//! certificates. Like the rest of the crate it is native-tls, or rustls on
+//! macOS; without any settings each library's defaults apply.
+//!
+//! Pinned keys are checked right after the TLS handshake, before anything is
+//! sent: by a certificate verifier with rustls, and with native-tls, which
+//! has no room for one, on the stream of the WebSocket and by a connector
+//! layer for the HTTP requests.
+
+use crate::config::{Config, Proxy, ProxyKind, SocketConfig, TlsConfig};
+use crate::error::{ConnectError, Error};
+use crate::handshake;
+use base64::{engine::general_purpose::STANDARD, Engine};
+#[cfg(not(target_os = "macos"))]
+use hyper_util::client::legacy::connect::Connection;
+use log::{error, warn};
+use sha2::{Digest, Sha256};
+use std::fs;
+#[cfg(not(target_os = "macos"))]
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::Path;
+#[cfg(not(target_os = "macos"))]
+use std::pin::Pin;
+#[cfg(not(target_os = "macos"))]
+use std::task::{Context, Poll};
+use tokio::net::TcpStream;
+use tokio_tungstenite::MaybeTlsStream;
+use tungstenite::handshake::client::Request;
+
+#[cfg(not(target_os = "macos"))]
+type TlsConnector = native_tls::TlsConnector;
+#[cfg(target_os = "macos")]
+type TlsConnector = std::sync::Arc<rustls::ClientConfig>;
+
+/// Runs the TLS handshake for the WebSocket `request` over `stream`, if it
+/// is a `wss` one.
+pub(crate) async fn wrap(
+    config: &TlsConfig,
+    request: &Request,
+    stream: TcpStream,
+) -> Result<MaybeTlsStream<TcpStream>, Error> {
+    let uri = request.uri();
+    if uri.scheme_str() != Some("wss") {
+        return Ok(MaybeTlsStream::Plain(stream));
+    }
+    let host = uri
+        .host()
+        .unwrap_or_default()
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    if config.insecure {
+        warn!(
+            "INSECURE_SKIP_VERIFY is set, not verifying the certificate of {}: \
+             anyone in between can read and change what is sent",
+            host
+        );
+    }
+    handshake_tls(config, host, stream).await
+}
+
+#[cfg(not(target_os = "macos"))]
+async fn handshake_tls(
+    config: &TlsConfig,
+    host: &str,
+    stream: TcpStream,
+) -> Result<MaybeTlsStream<TcpStream>, Error> {
+    let connector = match build(config)? {
+        Some(connector) => connector,
+        None => native_tls::TlsConnector::new().map_err(|e| ConnectError::Tls(e.to_string()))?,
+    };
+    let stream = tokio_native_tls::TlsConnector::from(connector)
+        .connect(host, stream)
+        .await
+        .map_err(|e| handshake::error(tungstenite::Error::Tls(e.into())))?;
+    if !config.pins.is_empty() {
+        let cert = stream
+            .get_ref()
+            .peer_certificate()
+            .map_err(|e| ConnectError::Tls(e.to_string()))?
+            .and_then(|cert| cert.to_der().ok());
+        check_pin(&config.pins, cert.as_deref()).map_err(|()| ConnectError::PinMismatch)?;
+    }
+    Ok(MaybeTlsStream::NativeTls(stream))
+}
+
+#[cfg(target_os = "macos")]
+async fn handshake_tls(
+    config: &TlsConfig,
+    host: &str,
+    stream: TcpStream,
+) -> Result<MaybeTlsStream<TcpStream>, Error> {
+    let name = rustls::pki_types::ServerName::try_from(host.to_string())
+        .map_err(|e| ConnectError::Tls(e.to_string()))?;
+    let stream = tokio_rustls::TlsConnector::from(rustls_config(config)?)
+        .connect(name, stream)
+        .await
+        .map_err(|e| handshake::error(e.into()))?;
+    Ok(MaybeTlsStream::Rustls(stream))
+}
+
+/// This machine, which a [`Proxy`] never applies to, in `NO_PROXY` syntax.
+const LOOPBACK: &str = "localhost,127.0.0.0/8,::1";
+
+/// A client for HTTP requests to the server, connecting like the WebSocket
+/// and trusting the same certificates.
+pub(crate) fn http_client(config: &Config) -> Result<reqwest::Client, ConnectError> {
+    let builder = connecting(&config.socket)?;
+    let builder = match build(&config.tls)? {
+        Some(tls) => builder.use_preconfigured_tls(tls),
+        None => builder,
+    };
+    #[cfg(not(target_os = "macos"))]
+    let builder = match config.tls.pins.is_empty() {
+        true => builder,
+        false => builder
+            .tls_info(true)
+            .connector_layer(PinLayer(config.tls.pins.clone())),
+    };
+    Ok(builder.build()?)
+}
+
+/// A client for HTTP requests elsewhere, like `SSE_URL` and UnifiedPush
+/// callbacks: connecting like the one for the server, with the default
+/// certificate checks instead of the server's.
+pub(crate) fn other_client(config: &Config) -> Result<reqwest::Client, ConnectError> {
+    Ok(connecting(&config.socket)?.build()?)
+}
+
+/// Connections as the WebSocket's: through [`SocketConfig::proxy`], from
+/// the bound address and interface, and with the addresses of
+/// [`SocketConfig::resolve`].
+fn connecting(socket: &SocketConfig) -> Result<reqwest::ClientBuilder, ConnectError> {
+    let mut builder = reqwest::Client::builder().local_address(socket.bind_addr);
+    builder = match &socket.proxy {
+        Some(proxy) => builder.proxy(http_proxy(proxy)?),
+        None => builder.no_proxy(),
+    };
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    if let Some(interface) = &socket.interface {
+        builder = builder.interface(interface);
+    }
+    if let Some(timeout) = socket.connect_timeout {
+        builder = builder.connect_timeout(timeout);
+    }
+    for pinned in &socket.resolve {
+        builder = builder.resolve(&pinned.host, SocketAddr::new(pinned.ip, pinned.port));
+    }
+    Ok(builder)
+}
+
+/// `proxy` for reqwest; SOCKS as `socks5h`, so the proxy resolves the
+/// server's name like it does for the WebSocket.
+fn http_proxy(proxy: &Proxy) -> Result<reqwest::Proxy, ConnectError> {
+    let scheme = match proxy.kind {
+        ProxyKind::Socks5 => "socks5h",
+        ProxyKind::Http => "http",
+    };
+    let url = format!("{}://{}:{}", scheme, proxy.host, proxy.port);
+    let mut http_proxy = reqwest::Proxy::all(url)?;
+    if let Some((user, password)) = &proxy.auth {
+        http_proxy = http_proxy.basic_auth(user, password);
+    }
+    let bypass = [LOOPBACK, &proxy.bypass.join(",")].join(",");
+    Ok(http_proxy.no_proxy(reqwest::NoProxy::from_string(&bypass)))
+}
+
+#[cfg(not(target_os = "macos"))]
+fn build(config: &TlsConfig) -> Result<Option<TlsConnector>, ConnectError> {
+    if config.ca_file.is_none() && config.client_cert.is_none() && !config.insecure {
+        return Ok(None);
+    }
+    let mut builder = native_tls::TlsConnector::builder();
+    if config.insecure {
+        builder
+            .danger_accept_invalid_certs(true)
+            .danger_accept_invalid_hostnames(true);
+    }
+    if let Some(path) = &config.ca_file {
+        for pem in certificates(path)? {
+            let cert = native_tls::Certificate::from_pem(pem.as_bytes())
+                .map_err(|e| invalid_file(path, e))?;
+            builder.add_root_certificate(cert);
+        }
+    }
+    if let Some(client) = &config.client_cert {
+        // Only the sections needed, the two may be in the same file.
+        let chain = certificates(&client.cert)?.concat();
+        let key = private_key(&client.key)?;
+        let identity = native_tls::Identity::from_pkcs8(chain.as_bytes(), key.as_bytes())
+            .map_err(|e| invalid_file(&client.key, e))?;
+        builder.identity(identity);
+    }
+    let connector = builder
+        .build()
+        .map_err(|e| ConnectError::Tls(e.to_string()))?;
+    Ok(Some(connector))
+}
+
+#[cfg(target_os = "macos")]
+fn build(config: &TlsConfig) -> Result<Option<TlsConnector>, ConnectError> {
+    if config.ca_file.is_none()
+        && config.client_cert.is_none()
+        && config.pins.is_empty()
+        && !config.insecure
+    {
+        return Ok(None);
+    }
+    rustls_config(config).map(Some)
+}
+
+#[cfg(target_os = "macos")]
+fn rustls_config(config: &TlsConfig) -> Result<TlsConnector, ConnectError> {
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+
+    let parse = |path: &Path| -> Result<Vec<CertificateDer<'static>>, ConnectError> {
+        certificates(path)?
+            .iter()
+            .map(|pem| {
+                CertificateDer::from_pem_slice(pem.as_bytes()).map_err(|e| invalid_file(path, e))
+            })
+            .collect()
+    };
+    let mut roots = rustls::RootCertStore::empty();
+    // Like the default connector, which uses the system's roots too.
+    roots.add_parsable_certificates(rustls_native_certs::load_native_certs().certs);
+    if let Some(path) = &config.ca_file {
+        for cert in parse(path)? {
+            roots.add(cert).map_err(|e| invalid_file(path, e))?;
+        }
+    }
+    let builder = rustls::ClientConfig::builder();
+    let builder = match config.pins.is_empty() && !config.insecure {
+        true => builder.with_root_certificates(roots),
+        false => {
+            let webpki = rustls::client::WebPkiServerVerifier::builder(roots.into())
+                .build()
+                .map_err(|e| ConnectError::Tls(e.to_string()))?;
+            builder
+                .dangerous()
+                .with_custom_certificate_verifier(std::sync::Arc::new(Verifier {
+                    webpki,
+                    pins: config.pins.clone(),
+                    insecure: config.insecure,
+                }))
+        }
+    };
+    let tls = match &config.client_cert {
+        Some(client) => {
+            let key = PrivateKeyDer::from_pem_file(&client.key)
+                .map_err(|e| invalid_file(&client.key, e))?;
+            builder
+                .with_client_auth_cert(parse(&client.cert)?, key)
+                .map_err(|e| invalid_file(&client.key, e))?
+        }
+        None => builder.with_no_client_auth(),
+    };
+    Ok(std::sync::Arc::new(tls))
+}
+
+/// The usual verification unless it is `insecure`, then the pins.
+#[cfg(target_os = "macos")]
+#[derive(Debug)]
+struct Verifier {
+    webpki: std::sync::Arc<rustls::client::WebPkiServerVerifier>,
+    pins: Vec<[u8; 32]>,
+    insecure: bool,
+}
+
+#[cfg(target_os = "macos")]
+impl rustls::client::danger::ServerCertVerifier for Verifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &rustls::pki_types::CertificateDer<'_>,
+        intermediates: &[rustls::pki_types::CertificateDer<'_>],
+        server_name: &rustls::pki_types::ServerName<'_>,
+        ocsp_response: &[u8],
+        now: rustls::pki_types::UnixTime,
+    ) -> Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
+        if !self.insecure {
+            self.webpki.verify_server_cert(
+                end_entity,
+                intermediates,
+                server_name,
+                ocsp_response,
+                now,
+            )?;
+        }
+        if !self.pins.is_empty() {
+            check_pin(&self.pins, Some(end_entity.as_ref()))
+                .map_err(|()| rustls::Error::General(ConnectError::PinMismatch.to_string()))?;
+        }
+        Ok(rustls::client::danger::ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        self.webpki.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        self.webpki.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.webpki.supported_verify_schemes()
+    }
+}
+
+/// Checks the pins on every new HTTP connection, before a request is sent
+/// on it.
+#[cfg(not(target_os = "macos"))]
+#[derive(Clone)]
+struct PinLayer(Vec<[u8; 32]>);
+
+#[cfg(not(target_os = "macos"))]
+impl<S> tower_layer::Layer<S> for PinLayer {
+    type Service = PinService<S>;
+
+    fn layer(&self, inner: S) -> PinService<S> {
+        PinService {
+            inner,
+            pins: self.0.clone(),
+        }
+    }
+}
+
+#[cfg(not(target_os = "macos"))]
+#[derive(Clone)]
+struct PinService<S> {
+    inner: S,
+    pins: Vec<[u8; 32]>,
+}
+
+#[cfg(not(target_os = "macos"))]
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+#[cfg(not(target_os = "macos"))]
+impl<S, R> tower_service::Service<R> for PinService<S>
+where
+    S: tower_service::Service<R, Error = BoxError>,
+    S::Response: Connection,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<S::Response, BoxError>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), BoxError>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: R) -> Self::Future {
+        let connecting = self.inner.call(request);
+        let pins = self.pins.clone();
+        Box::pin(async move {
+            let conn = connecting.await?;
+            let mut extras = http::Extensions::new();
+            conn.connected().get_extras(&mut extras);
+            let cert = extras
+                .get::<reqwest::tls::TlsInfo>()
+                .and_then(|info| info.peer_certificate());
+            check_pin(&pins, cert).map_err(|()| ConnectError::PinMismatch)?;
+            Ok(conn)
+        })
+    }
+}
+
+/// Whether the key of the certificate `cert` is one of `pins`.
+fn check_pin(pins: &[[u8; 32]], cert: Option<&[u8]>) -> Result<(), ()> {
+    let hash = cert
+        .and_then(spki)
+        .map(|spki| <[u8; 32]>::from(Sha256::digest(spki)));
+    match hash {
+        Some(hash) if pins.contains(&hash) => Ok(()),
+        Some(hash) => {
+            error!("server key pin is sha256//{}", STANDARD.encode(hash));
+            Err(())
+        }
+        None => Err(()),
+    }
+}
+
+/// The DER SubjectPublicKeyInfo in the X.509 certificate `cert`.
+fn spki(cert: &[u8]) -> Option<&[u8]> {
+    let (_, certificate, _) = der_element(cert)?;
+    let (_, mut tbs, _) = der_element(certificate)?;
+    // The version is an optional, explicitly tagged [0].
+    if tbs.first() == Some(&0xa0) {
+        tbs = der_element(tbs)?.2;
+    }
+    // Serial number, signature algorithm, issuer, validity and subject.
+    for _ in 0..5 {
+        tbs = der_element(tbs)?.2;
+    }
+    let (_, _, rest) = der_element(tbs)?;
+    Some(&tbs[..tbs.len() - rest.len()])
+}
+
+/// Splits off the DER element at the start of `der`: its tag, contents and
+/// what follows it.
+fn der_element(der: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = der.split_first()?;
+    let (&first, mut rest) = rest.split_first()?;
+    let len = match first {
+        0..0x80 => first as usize,
+        _ => {
+            let octets = (first & 0x7f) as usize;
+            if octets == 0 || octets > 4 || rest.len() < octets {
+                return None;
+            }
+            let (len, after) = rest.split_at(octets);
+            rest = after;
+            len.iter().fold(0, |len, &b| len << 8 | b as usize)
+        }
+    };
+    (rest.len() >= len).then(|| {
+        let (contents, rest) = rest.split_at(len);
+        (tag, contents, rest)
+    })
+}
+
+/// The PEM certificates in the file at `path`; a bundle may have several.
+fn certificates(path: &Path) -> Result<Vec<String>, ConnectError> {
+    let certs = pem_blocks(path, "CERTIFICATE")?;
+    if certs.is_empty() {
+        return Err(invalid_file(path, "no PEM certificates in it"));
+    }
+    Ok(certs)
+}
+
+/// The PKCS#8 private key in the file at `path`, which may hold the
+/// certificate as well.
+#[cfg(not(target_os = "macos"))]
+fn private_key(path: &Path) -> Result<String, ConnectError> {
+    pem_blocks(path, "PRIVATE KEY")?
+        .into_iter()
+        .next()
+        .ok_or_else(|| invalid_file(path, "no PKCS#8 PEM private key in it"))
+}
+
+/// The PEM sections with `label` in the file at `path`.
+fn pem_blocks(path: &Path, label: &str) -> Result<Vec<String>, ConnectError> {
+    let begin = format!("-----BEGIN {}-----", label);
+    let end = format!("-----END {}-----", label);
+    let pem = fs::read_to_string(path).map_err(|e| invalid_file(path, e))?;
+    Ok(pem
+        .split_inclusive(&end)
+        .filter_map(|block| {
+            let start = block.find(&begin)?;
+            block.ends_with(&end).then(|| block[start..].to_string())
+        })
+        .collect())
+}
+
+fn invalid_file(path: &Path, reason: impl ToString) -> ConnectError {
+    ConnectError::TlsFile {
+        path: path.to_path_buf(),
+        reason: reason.to_string(),
+    }
+}
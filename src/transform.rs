@@ -0,0 +1,305 @@
+//! Rewriting the bodies of accepted messages before they reach the sinks.
+//! Shared by every transport, like [`crate::filter`].
+
+use crate::config::{
+    Config, Extract, JsonPath, OversizePolicy, Template, TemplateField, TemplatePart,
+};
+use crate::filter;
+use crate::message::WSMessage;
+use log::{debug, warn};
+use pulldown_cmark::{Event, Options, Parser, Tag, TagEnd};
+use serde_json::Value;
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// Applies the configured transforms to `msg`, or `None` if it should be
+/// dropped instead.
+pub(crate) fn apply(config: &Config, mut msg: WSMessage) -> Option<WSMessage> {
+    if let (Some(title_match), Some(title)) = (&config.title_match, &msg.title) {
+        if title_match.strip {
+            let title = title_match.prefix.replace(title, "");
+            msg.title = Some(title.trim_start().to_string());
+        }
+    }
+    if let Some(message) = &mut msg.message {
+        if config.strip_ansi || config.strip_control {
+            *message = strip_ansi(message);
+        }
+        if config.strip_control {
+            *message = strip_control(message);
+        }
+    }
+    if let (Some(path), Some(message)) = (&config.extract_json, &msg.message) {
+        msg.message = Some(extract_json(path, message)?);
+    }
+    if config.renders_markdown(&msg.topic) {
+        if let Some(message) = &msg.message {
+            msg.message = Some(plain_text(message));
+        }
+    }
+    if let (Some(extract), Some(message)) = (&config.extract, &msg.message) {
+        msg.message = Some(self::extract(extract, message)?);
+    }
+    if let Some(template) = &config.template {
+        msg.message = Some(render(template, &msg));
+    }
+    if let (Some(max), Some(message)) = (config.max_size, &mut msg.message) {
+        if message.len() > max && !fit(message, max, config.oversize) {
+            warn!("Dropping message of {} bytes, over MAX_SIZE", message.len());
+            return None;
+        }
+    }
+    Some(msg)
+}
+
+/// Cuts `message` down to `max` bytes as `policy` says; `false` if it
+/// should be dropped instead.
+fn fit(message: &mut String, max: usize, policy: OversizePolicy) -> bool {
+    let marker = match policy {
+        OversizePolicy::Reject => return false,
+        OversizePolicy::Truncate => String::new(),
+        OversizePolicy::TruncateWithMarker => {
+            format!("\n[truncated from {} bytes]", message.len())
+        }
+    };
+    let Some(mut end) = max.checked_sub(marker.len()) else {
+        return false;
+    };
+    while !message.is_char_boundary(end) {
+        end -= 1;
+    }
+    message.truncate(end);
+    message.push_str(&marker);
+    true
+}
+
+/// `text` without ANSI escape sequences: CSI ones like colors and cursor
+/// movement, OSC ones like titles and hyperlinks, and the rest.
+fn strip_ansi(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\u{1b}' => match chars.next() {
+                Some('[') => skip_csi(&mut chars),
+                Some(']' | 'P' | 'X' | '^' | '_') => skip_string(&mut chars),
+                // Intermediate bytes, then the final one.
+                Some(' '..='/') => {
+                    while chars.next_if(|c| (' '..='/').contains(c)).is_some() {}
+                    chars.next();
+                }
+                _ => {}
+            },
+            '\u{9b}' => skip_csi(&mut chars),
+            '\u{90}' | '\u{98}' | '\u{9d}' | '\u{9e}' | '\u{9f}' => skip_string(&mut chars),
+            c => stripped.push(c),
+        }
+    }
+    stripped
+}
+
+/// Up to and including the final byte of a CSI sequence.
+fn skip_csi(chars: &mut Peekable<Chars>) {
+    for c in chars.by_ref() {
+        if ('@'..='~').contains(&c) {
+            break;
+        }
+    }
+}
+
+/// Up to and including the terminator of an OSC or similar string: ST, or
+/// BEL as xterm allows.
+fn skip_string(chars: &mut Peekable<Chars>) {
+    while let Some(c) = chars.next() {
+        match c {
+            '\u{7}' | '\u{9c}' => break,
+            '\u{1b}' => {
+                chars.next_if_eq(&'\\');
+                break;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// `text` without control characters other than tabs and line breaks;
+/// carriage returns stay only before a line feed.
+fn strip_control(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let kept = match c {
+            '\n' | '\t' => true,
+            '\r' => chars.peek() == Some(&'\n'),
+            c => !c.is_control(),
+        };
+        if kept {
+            stripped.push(c);
+        }
+    }
+    stripped
+}
+
+/// `markdown` as plain text: the markup goes, the text stays, with the
+/// targets of links after them.
+fn plain_text(markdown: &str) -> String {
+    let options =
+        Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TABLES | Options::ENABLE_TASKLISTS;
+    let mut text = String::new();
+    // The next number of each list being rendered, `None` for bullets.
+    let mut lists: Vec<Option<u64>> = Vec::new();
+    let mut link = None;
+    for event in Parser::new_ext(markdown, options) {
+        match event {
+            Event::Start(Tag::List(start)) => lists.push(start),
+            Event::End(TagEnd::List(_)) => {
+                lists.pop();
+                if lists.is_empty() {
+                    end_block(&mut text);
+                } else {
+                    end_line(&mut text);
+                }
+            }
+            Event::Start(Tag::Item) => {
+                end_line(&mut text);
+                text.push_str(&"  ".repeat(lists.len().saturating_sub(1)));
+                match lists.last_mut() {
+                    Some(Some(n)) => {
+                        text.push_str(&format!("{}. ", n));
+                        *n += 1;
+                    }
+                    _ => text.push_str("- "),
+                }
+            }
+            Event::Start(Tag::Link { dest_url, .. }) => link = Some(dest_url),
+            Event::End(TagEnd::Link) => {
+                // Autolinks already show their target.
+                if let Some(url) = link.take().filter(|url| !text.ends_with(url.as_ref())) {
+                    text.push_str(&format!(" ({})", url));
+                }
+            }
+            Event::Text(s) | Event::Code(s) => text.push_str(&s),
+            Event::SoftBreak | Event::HardBreak => text.push('\n'),
+            Event::TaskListMarker(done) => text.push_str(if done { "[x] " } else { "[ ] " }),
+            Event::End(TagEnd::TableCell) => text.push('\t'),
+            Event::End(TagEnd::TableHead | TagEnd::TableRow) => {
+                if text.ends_with('\t') {
+                    text.pop();
+                }
+                end_line(&mut text);
+            }
+            Event::End(TagEnd::Item) => end_line(&mut text),
+            Event::End(
+                TagEnd::Paragraph
+                | TagEnd::Heading(_)
+                | TagEnd::CodeBlock
+                | TagEnd::BlockQuote(_)
+                | TagEnd::Table,
+            )
+            | Event::Rule => end_block(&mut text),
+            _ => {}
+        }
+    }
+    text.truncate(text.trim_end().len());
+    text
+}
+
+fn end_line(text: &mut String) {
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+}
+
+/// Ends the line and leaves an empty one after it, once.
+fn end_block(text: &mut String) {
+    end_line(text);
+    if !text.is_empty() && !text.ends_with("\n\n") {
+        text.push('\n');
+    }
+}
+
+fn extract(extract: &Extract, message: &str) -> Option<String> {
+    let Some(captures) = extract.pattern.captures(message) else {
+        debug!("message doesn't match EXTRACT");
+        return extract.fallback_all.then(|| message.to_string());
+    };
+    let matched = captures.get(1).or_else(|| captures.get(0))?;
+    Some(matched.as_str().to_string())
+}
+
+fn extract_json(path: &JsonPath, message: &str) -> Option<String> {
+    let json = match serde_json::from_str::<Value>(message) {
+        Ok(json) => json,
+        Err(e) => {
+            warn!(
+                "Dropping message, EXTRACT_JSON is set but it isn't JSON: {}",
+                e
+            );
+            return None;
+        }
+    };
+    let text = path.text(&json);
+    if text.is_none() {
+        debug!("message has no EXTRACT_JSON field");
+    }
+    text
+}
+
+pub(crate) fn render(template: &Template, msg: &WSMessage) -> String {
+    let mut text = String::new();
+    for part in &template.0 {
+        match part {
+            TemplatePart::Text(s) => text.push_str(s),
+            TemplatePart::Field(field) => match field {
+                TemplateField::Id => text.push_str(msg.id.as_deref().unwrap_or_default()),
+                TemplateField::Topic => text.push_str(&msg.topic),
+                TemplateField::Title => text.push_str(msg.title.as_deref().unwrap_or_default()),
+                TemplateField::Message => text.push_str(msg.message.as_deref().unwrap_or_default()),
+                TemplateField::Tags => {
+                    text.push_str(&msg.tags.as_deref().unwrap_or_default().join(","))
+                }
+                TemplateField::Time => {
+                    if let Some(time) = msg.time {
+                        text.push_str(&time.to_string());
+                    }
+                }
+                TemplateField::Date => {
+                    if let Some(time) = msg.time {
+                        text.push_str(&date(time));
+                    }
+                }
+                TemplateField::Priority => {
+                    text.push_str(&msg.priority.unwrap_or(filter::DEFAULT_PRIORITY).to_string())
+                }
+                TemplateField::Click => text.push_str(msg.click.as_deref().unwrap_or_default()),
+            },
+        }
+    }
+    text
+}
+
+/// `time` in Unix seconds as RFC 3339 in UTC.
+fn date(time: u64) -> String {
+    let (days, secs) = (time / 86400, time % 86400);
+    // Howard Hinnant's civil_from_days, for a proleptic Gregorian calendar
+    // with eras of 400 years starting on March 1.
+    let days = days + 719468;
+    let era = days / 146097;
+    let day_of_era = days % 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
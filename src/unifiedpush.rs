@@ -0,0 +1,198 @@
+//! Minimal UnifiedPush-style distributor. Local apps register a callback URL
+//! through the HTTP API and get a push endpoint on a topic of their own; those
+//! topics share the main ntfy connection and their messages are POSTed to the
+//! callback instead of reaching the clipboard.
+
+use crate::config::{Config, UnifiedPushConfig};
+use crate::message::WSMessage;
+use crate::tls;
+use log::{debug, error, info};
+use std::collections::hash_map::RandomState;
+use std::collections::BTreeMap;
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use tokio::sync::watch;
+use url::Url;
+
+static REGISTRY: OnceLock<Registry> = OnceLock::new();
+
+struct Registry {
+    /// For the callbacks.
+    client: reqwest::Client,
+    state: PathBuf,
+    /// The topic's HTTP URL; registered topics replace its last segment.
+    base: Url,
+    /// Topic → callback URL.
+    apps: Mutex<BTreeMap<String, String>>,
+    /// Bumped on every change, for [`Changes`].
+    changed: watch::Sender<()>,
+}
+
+/// Loads saved registrations. Called once when the pipeline starts.
+pub(crate) fn init(config: &Config, up: &UnifiedPushConfig) {
+    let Ok(base) = config.http_url() else {
+        return;
+    };
+    let client = match tls::other_client(config) {
+        Ok(client) => client,
+        Err(e) => {
+            error!("UnifiedPush distributor disabled: {}", e);
+            return;
+        }
+    };
+    let apps = match fs::read(&up.state) {
+        Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|e| {
+            error!("Ignoring corrupt UnifiedPush state {:?}: {}", up.state, e);
+            BTreeMap::new()
+        }),
+        Err(_) => BTreeMap::new(),
+    };
+    let registry = Registry {
+        client,
+        state: up.state.clone(),
+        base,
+        apps: Mutex::new(apps),
+        changed: watch::Sender::new(()),
+    };
+    if REGISTRY.set(registry).is_ok() {
+        info!(
+            "UnifiedPush distributor has {} registrations",
+            topics().len()
+        );
+    }
+}
+
+/// Topics to subscribe to along with the main one.
+pub(crate) fn topics() -> Vec<String> {
+    REGISTRY.get().map_or(Vec::new(), |registry| {
+        registry.apps.lock().unwrap().keys().cloned().collect()
+    })
+}
+
+/// Changes to the registered topics, so the connection can be reopened with
+/// the new set.
+pub(crate) struct Changes(Option<watch::Receiver<()>>);
+
+/// Watches for changes from now on. Taken before subscribing, so one made
+/// while connecting isn't missed.
+pub(crate) fn changes() -> Changes {
+    Changes(REGISTRY.get().map(|registry| registry.changed.subscribe()))
+}
+
+impl Changes {
+    /// Resolves once the topics changed since the last call, however many
+    /// times. Never resolves when the distributor is off.
+    pub(crate) async fn changed(&mut self) {
+        let Some(rx) = &mut self.0 else {
+            return std::future::pending().await;
+        };
+        // The sender lives in the registry, so this doesn't fail anyway.
+        if rx.changed().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+/// Registers `callback` and returns its push endpoint. Registering the same
+/// callback again returns the existing endpoint.
+pub(crate) fn register(callback: &str) -> io::Result<Option<String>> {
+    let Some(registry) = REGISTRY.get() else {
+        return Ok(None);
+    };
+    let mut apps = registry.apps.lock().unwrap();
+    let topic = match apps.iter().find(|(_, c)| *c == callback) {
+        Some((topic, _)) => topic.clone(),
+        None => {
+            let topic = new_topic();
+            apps.insert(topic.clone(), callback.to_string());
+            registry.save(&apps)?;
+            registry.changed.send_replace(());
+            info!("UnifiedPush registered {} on {}", callback, topic);
+            topic
+        }
+    };
+    Ok(Some(registry.endpoint(&topic)))
+}
+
+/// Removes `callback`. Returns `false` if it wasn't registered.
+pub(crate) fn unregister(callback: &str) -> io::Result<bool> {
+    let Some(registry) = REGISTRY.get() else {
+        return Ok(false);
+    };
+    let mut apps = registry.apps.lock().unwrap();
+    let before = apps.len();
+    apps.retain(|_, c| c != callback);
+    if apps.len() == before {
+        return Ok(false);
+    }
+    registry.save(&apps)?;
+    registry.changed.send_replace(());
+    info!("UnifiedPush unregistered {}", callback);
+    Ok(true)
+}
+
+/// Hands a message on a registered topic to its app. Returns `false` for
+/// messages that should go through the normal pipeline.
+pub(crate) fn deliver(msg: &WSMessage) -> bool {
+    let Some(registry) = REGISTRY.get() else {
+        return false;
+    };
+    let Some(callback) = registry.apps.lock().unwrap().get(&msg.topic).cloned() else {
+        return false;
+    };
+    let body = msg.message.clone().unwrap_or_default();
+    let client = registry.client.clone();
+    tokio::spawn(async move {
+        debug!(
+            "UnifiedPush delivering {} bytes to {}",
+            body.len(),
+            callback
+        );
+        let result = client.post(&callback).body(body).send().await;
+        match result {
+            Ok(response) if response.status().is_success() => {}
+            Ok(response) => error!(
+                "UnifiedPush app at {} responded with HTTP {}",
+                callback,
+                response.status().as_u16()
+            ),
+            Err(e) => error!(
+                "Failed to deliver UnifiedPush message to {}: {}",
+                callback, e
+            ),
+        }
+    });
+    true
+}
+
+impl Registry {
+    fn endpoint(&self, topic: &str) -> String {
+        let mut url = self.base.clone();
+        if let Ok(mut segments) = url.path_segments_mut() {
+            segments.pop().push(topic);
+        }
+        url.set_query(Some("up=1"));
+        url.to_string()
+    }
+
+    fn save(&self, apps: &BTreeMap<String, String>) -> io::Result<()> {
+        if let Some(dir) = self.state.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&self.state, serde_json::to_vec_pretty(apps)?)
+    }
+}
+
+/// ntfy topics are public, so the name is the only secret; `RandomState` is
+/// seeded from the OS RNG.
+fn new_topic() -> String {
+    let random = |n| {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u32(n);
+        hasher.finish()
+    };
+    format!("up{:016x}{:016x}", random(0), random(1))
+}
@@ -0,0 +1,52 @@
+//! Publishing mode: the local clipboard is read every
+//! [`WatchConfig::interval`] and new content is published to the topic, so
+//! this machine sends its copies as well as receiving the others'.
+//!
+//! Content the client wrote itself isn't published back, otherwise every
+//! received message would bounce between the machines subscribed to it.
+
+use crate::clip;
+use crate::config::{Config, WatchConfig};
+use crate::publish;
+use crate::repeats::Repeats;
+use log::{debug, info};
+use tokio::time;
+
+pub(crate) fn spawn(config: &Config, watch: &WatchConfig) {
+    let config = config.clone();
+    let interval = watch.interval;
+    tokio::spawn(async move {
+        let mut repeats = Repeats::new(module_path!());
+        let mut ticks = time::interval(interval);
+        // Whatever is on the clipboard at startup is old news.
+        let mut seen = clip::read().await.ok().flatten();
+        info!("publishing clipboard changes every {:?}", interval);
+        loop {
+            ticks.tick().await;
+            let content = match clip::read().await {
+                Ok(Some(content)) => content,
+                Ok(None) => continue,
+                Err(e) => {
+                    repeats.error(&e.to_string(), || {
+                        format!("Failed to read the clipboard: {}", e)
+                    });
+                    continue;
+                }
+            };
+            if seen.as_ref() == Some(&content) {
+                continue;
+            }
+            seen = Some(content.clone());
+            if clip::take_written(&content) {
+                debug!("clipboard holds received content, not publishing it");
+                continue;
+            }
+            match publish::publish(&config, &content).await {
+                Ok(()) => repeats.finish(),
+                Err(e) => repeats.error(&e.to_string(), || {
+                    format!("Failed to publish the clipboard: {}", e)
+                }),
+            }
+        }
+    });
+}
@@ -0,0 +1,179 @@
+//! Browser build: subscribes through the `WebSocket` API and writes received
+//! messages with the async Clipboard API.
+
+use crate::config::Config;
+use crate::error::{ClipboardError, ConnectError};
+use crate::filter;
+use crate::message;
+use crate::transform;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use log::{debug, error, info};
+use std::cell::RefCell;
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::{spawn_local, JsFuture};
+use web_sys::{CloseEvent, MessageEvent, WebSocket};
+
+const RECONNECT_DELAY_MS: i32 = 5000;
+
+type Handlers = (
+    Closure<dyn FnMut(MessageEvent)>,
+    Closure<dyn FnMut(CloseEvent)>,
+);
+
+struct Inner {
+    config: Config,
+    on_message: Option<js_sys::Function>,
+    socket: Option<WebSocket>,
+    handlers: Option<Handlers>,
+    closed: bool,
+}
+
+/// Subscribes to a topic from a browser tab and copies received messages to
+/// the clipboard. Browsers only allow clipboard writes while the page has
+/// focus.
+#[wasm_bindgen]
+pub struct WebClient {
+    inner: Rc<RefCell<Inner>>,
+}
+
+#[wasm_bindgen]
+impl WebClient {
+    /// `on_message(topic, message)` is called for every accepted message.
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        server: &str,
+        topic: &str,
+        token: Option<String>,
+        on_message: Option<js_sys::Function>,
+    ) -> Result<WebClient, JsError> {
+        let mut config = Config::new(topic);
+        config.server = server.to_string();
+        config.token = token.filter(|t| !t.is_empty());
+        let inner = Rc::new(RefCell::new(Inner {
+            config,
+            on_message,
+            socket: None,
+            handlers: None,
+            closed: false,
+        }));
+        connect(&inner)?;
+        Ok(WebClient { inner })
+    }
+
+    pub fn close(&self) {
+        let mut inner = self.inner.borrow_mut();
+        inner.closed = true;
+        if let Some(socket) = inner.socket.take() {
+            socket.set_onclose(None);
+            let _ = socket.close();
+        }
+        inner.handlers = None;
+    }
+}
+
+fn js_error(e: JsValue) -> String {
+    e.as_string().unwrap_or_else(|| format!("{:?}", e))
+}
+
+fn connect(inner: &Rc<RefCell<Inner>>) -> Result<(), ConnectError> {
+    let mut url = inner.borrow().config.url()?;
+    // Browsers can't set headers on WebSocket requests, so use ntfy's auth
+    // query parameter instead.
+    if let Some(token) = &inner.borrow().config.token {
+        let auth = URL_SAFE_NO_PAD.encode(format!("Bearer {token}"));
+        url.query_pairs_mut().append_pair("auth", &auth);
+    }
+
+    let socket = WebSocket::new(url.as_str()).map_err(|e| ConnectError::Web(js_error(e)))?;
+
+    let on_message = Closure::<dyn FnMut(MessageEvent)>::new({
+        let inner = inner.clone();
+        move |event: MessageEvent| {
+            let Some(text) = event.data().as_string() else {
+                return;
+            };
+            let config = &inner.borrow().config;
+            let decoded =
+                message::decode(config, &text).and_then(|msg| message::unpack(config, msg));
+            match decoded {
+                Ok(msg) => {
+                    let inner = inner.borrow();
+                    if !filter::accepts(&inner.config, &msg)
+                        || !filter::for_device(&inner.config, &msg)
+                    {
+                        return;
+                    }
+                    let Some(msg) = transform::apply(&inner.config, msg) else {
+                        return;
+                    };
+                    let Some(message) = msg.message else {
+                        return;
+                    };
+                    debug!("WS received message on {}", msg.topic);
+                    if let Some(callback) = &inner.on_message {
+                        let _ = callback.call2(
+                            &JsValue::NULL,
+                            &JsValue::from_str(&msg.topic),
+                            &JsValue::from_str(&message),
+                        );
+                    }
+                    spawn_local(async move {
+                        if let Err(e) = set_clip(&message).await {
+                            error!("Failed to set clipboard: {}", e);
+                        }
+                    });
+                }
+                Err(e) => error!("Error in WebSocket connection: {}", e),
+            }
+        }
+    });
+    let on_close = Closure::<dyn FnMut(CloseEvent)>::new({
+        let inner = inner.clone();
+        move |event: CloseEvent| {
+            info!("WS closed ({}), reconnecting...", event.code());
+            schedule_reconnect(inner.clone());
+        }
+    });
+    socket.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+    socket.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+
+    let mut state = inner.borrow_mut();
+    state.socket = Some(socket);
+    state.handlers = Some((on_message, on_close));
+    info!(
+        "connecting to {} with topic={}",
+        state.config.server, state.config.topic
+    );
+    Ok(())
+}
+
+fn schedule_reconnect(inner: Rc<RefCell<Inner>>) {
+    let Some(window) = web_sys::window() else {
+        return;
+    };
+    let retry = Closure::once_into_js(move || {
+        if inner.borrow().closed {
+            return;
+        }
+        if let Err(e) = connect(&inner) {
+            error!("Connection error: {}. Reconnecting...", e);
+            schedule_reconnect(inner);
+        }
+    });
+    let _ = window.set_timeout_with_callback_and_timeout_and_arguments_0(
+        retry.unchecked_ref(),
+        RECONNECT_DELAY_MS,
+    );
+}
+
+async fn set_clip(content: &str) -> Result<(), ClipboardError> {
+    let window = web_sys::window().ok_or(ClipboardError::Unsupported("no browser window"))?;
+    let promise = window.navigator().clipboard().write_text(content);
+    JsFuture::from(promise)
+        .await
+        .map_err(|e| ClipboardError::Web(js_error(e)))?;
+    Ok(())
+}
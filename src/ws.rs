@@ -0,0 +1,244 @@
+use crate::channel::Sender;
+use crate::config::Config;
+use crate::error::{ConnectError, ProtocolError, Result};
+use crate::events::{Event, EventBus};
+use crate::handshake;
+use crate::jitter;
+use crate::message::{self, WSMessage};
+use crate::net;
+use crate::pipeline;
+use crate::suspend;
+use crate::tls;
+use crate::unifiedpush;
+use futures_util::{SinkExt, StreamExt};
+use log::{debug, error, info};
+use tokio::net::TcpStream;
+use tokio::sync::watch;
+use tokio::time::{self, Duration, Instant};
+use tokio_tungstenite::tungstenite::protocol::{Message, WebSocketConfig};
+use tokio_tungstenite::{client_async_with_config, MaybeTlsStream, WebSocketStream};
+
+/// How long the server has to answer the ping sent on resume.
+pub(crate) const WAKE_PROBE: Duration = Duration::from_secs(10);
+/// Keepalives that may go missing before an adaptive idle timeout expires.
+const MISSED_KEEP_ALIVES: u32 = 3;
+/// Lower limit of an adaptive idle timeout, against chatty servers.
+const MIN_ADAPTIVE_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Connects, then serves the subscription until it closes or fails.
+pub async fn connect_and_run(config: &Config, events: &EventBus) -> Result<()> {
+    let (_, reloads) = watch::channel(config.clone());
+    let pipeline = pipeline::spawn(config, events, reloads);
+    let changes = unifiedpush::changes();
+    let ws_stream = connect(config, None).await?;
+    serve(
+        ws_stream,
+        changes,
+        config,
+        &mut String::new(),
+        &pipeline,
+        events,
+    )
+    .await
+}
+
+pub(crate) async fn connect(config: &Config, since: Option<&str>) -> Result<WsStream> {
+    let request = handshake::request(config, since)?;
+    debug!("request: {:?}", &request);
+    let stream = net::connect(config, &request)
+        .await
+        .map_err(|e| handshake::error(e.into()))?;
+    let limits = WebSocketConfig::default()
+        .max_message_size(Some(message::MAX_SIZE))
+        .max_frame_size(Some(message::MAX_SIZE));
+    let stream = tls::wrap(&config.tls, &request, stream).await?;
+    let (ws_stream, _) = client_async_with_config(request, stream, Some(limits))
+        .await
+        .map_err(handshake::error)?;
+    info!(
+        "connected to {} with topic={} and timeout={}",
+        config.server,
+        config.topic,
+        config.timeout.as_secs()
+    );
+    Ok(ws_stream)
+}
+
+/// What [`receive`] made of a message of the subscription.
+pub(crate) enum Received {
+    /// A message for the pipeline, with its id.
+    Message(Option<String>),
+    KeepAlive,
+    Other,
+}
+
+/// Decodes a message of the subscription and feeds it to the pipeline if it
+/// is one, as opposed to other events.
+pub(crate) async fn receive(
+    text: &str,
+    config: &Config,
+    pipeline: &Sender<WSMessage>,
+    events: &EventBus,
+) -> Received {
+    let msg = match message::decode(config, text) {
+        Ok(msg) => msg,
+        Err(e) => {
+            error!("Protocol anomaly: {} ({} bytes)", e, text.len());
+            events.emit(Event::ProtocolAnomaly {
+                reason: e.to_string(),
+                size: text.len(),
+            });
+            return Received::Other;
+        }
+    };
+    match msg.event.as_str() {
+        "message" => {
+            debug!("received message: {:?}", &msg);
+            let id = msg.id.clone();
+            pipeline.send(msg).await;
+            Received::Message(id)
+        }
+        "open" => {
+            info!("server confirmed the subscription to {}", msg.topic);
+            Received::Other
+        }
+        "keepalive" => {
+            debug!("received keepalive");
+            Received::KeepAlive
+        }
+        event => {
+            debug!("ignoring {} event", event);
+            Received::Other
+        }
+    }
+}
+
+/// The time without traffic after which the connection counts as dead:
+/// [`Config::timeout`], or with [`Config::adaptive_timeout`] a few of the
+/// server's keepalive intervals once two keepalives were seen.
+pub(crate) struct IdleTimeout {
+    timeout: Duration,
+    adaptive: bool,
+    last_keep_alive: Option<Instant>,
+}
+
+impl IdleTimeout {
+    pub(crate) fn new(config: &Config) -> Self {
+        IdleTimeout {
+            timeout: config.timeout,
+            adaptive: config.adaptive_timeout,
+            last_keep_alive: None,
+        }
+    }
+
+    pub(crate) fn get(&self) -> Duration {
+        self.timeout
+    }
+
+    pub(crate) fn keep_alive(&mut self) {
+        let now = Instant::now();
+        let Some(last) = self.last_keep_alive.replace(now) else {
+            return;
+        };
+        if !self.adaptive {
+            return;
+        }
+        let timeout = ((now - last) * MISSED_KEEP_ALIVES).max(MIN_ADAPTIVE_TIMEOUT);
+        // Only whole seconds, so jitter in the cadence doesn't flood the log.
+        if timeout.as_secs() != self.timeout.as_secs() {
+            debug!(
+                "idle timeout is now {}s, following the server's keepalives",
+                timeout.as_secs()
+            );
+        }
+        self.timeout = timeout;
+    }
+}
+
+/// Serves the subscription, keeping `since` at the last message's id.
+pub(crate) async fn serve(
+    mut ws_stream: WsStream,
+    mut changes: unifiedpush::Changes,
+    config: &Config,
+    since: &mut String,
+    pipeline: &Sender<WSMessage>,
+    events: &EventBus,
+) -> Result<()> {
+    let mut timeout = IdleTimeout::new(config);
+    // A random phase and period, so a fleet's checks don't line up.
+    let mut ping_interval = time::interval_at(
+        Instant::now() + jitter::random(timeout.get()),
+        timeout.get(),
+    );
+    let mut last_traffic = Instant::now();
+    let mut clock = suspend::Clock::new();
+    let mut clock_interval = time::interval(suspend::CHECK_INTERVAL);
+
+    loop {
+        tokio::select! {
+            Some(msg) = ws_stream.next() => {
+                last_traffic = Instant::now();
+                match msg {
+                    Ok(Message::Text(text)) => {
+                        match receive(&text, config, pipeline, events).await {
+                            Received::Message(Some(id)) => *since = id,
+                            Received::KeepAlive => {
+                                timeout.keep_alive();
+                                // Traffic was just seen, so that's the earliest
+                                // a changed timeout can expire.
+                                ping_interval.reset_after(jitter::around(timeout.get()));
+                            }
+                            _ => {}
+                        }
+                    }
+                    Ok(Message::Ping(ping)) => {
+                        ws_stream
+                            .send(Message::Pong(ping))
+                            .await
+                            .map_err(ProtocolError::from)?;
+                        debug!("WS received ping and sent pong");
+                    }
+                    Ok(Message::Pong(_)) => {
+                        debug!("WS received pong");
+                    }
+                    Ok(Message::Close(_)) => {
+                        debug!("WS received close message");
+                        return Ok(());
+                    }
+                    Err(e) => {
+                        return Err(ProtocolError::from(e).into());
+                    }
+                    _ => {}
+                }
+            },
+            _ = changes.changed() => {
+                debug!("UnifiedPush registrations changed, resubscribing");
+                return Ok(());
+            }
+            _ = ping_interval.tick() => {
+                if last_traffic.elapsed() > timeout.get() {
+                    return Err(ConnectError::Idle(timeout.get()).into());
+                }
+                ping_interval.reset_after(jitter::around(timeout.get()));
+            }
+            _ = clock_interval.tick() => {
+                let Some(slept) = clock.suspended() else {
+                    continue;
+                };
+                // Whatever came before the suspend says nothing about the
+                // connection now; without an answer to this ping it's dead.
+                info!("resumed after {:?} of suspend, checking the connection", slept);
+                ws_stream
+                    .send(Message::Ping(Default::default()))
+                    .await
+                    .map_err(ProtocolError::from)?;
+                last_traffic = Instant::now()
+                    .checked_sub(timeout.get())
+                    .unwrap_or(last_traffic);
+                ping_interval.reset_after(WAKE_PROBE);
+            }
+        }
+    }
+}
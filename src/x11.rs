@@ -0,0 +1,220 @@
+//! Native X11 clipboard backend, enabled with the default `x11` feature.
+//!
+//! Keeps one connection that owns the CLIPBOARD selection, and PRIMARY when
+//! asked to, and answers paste requests from its own thread, so writes don't
+//! spawn an `xclip` each. Like with any selection owner, the content is gone
+//! once the process exits.
+
+use crate::clip::Selection;
+use crate::error::ClipboardError;
+use crate::session;
+use log::{debug, error, info};
+use std::cell::RefCell;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use x11rb::connection::{Connection, RequestConnection};
+use x11rb::errors::ReplyOrIdError;
+use x11rb::protocol::xproto::{
+    AtomEnum, ConnectionExt, CreateWindowAux, EventMask, PropMode, SelectionNotifyEvent,
+    SelectionRequestEvent, Window, WindowClass, SELECTION_NOTIFY_EVENT,
+};
+use x11rb::protocol::Event;
+use x11rb::rust_connection::RustConnection;
+use x11rb::wrapper::ConnectionExt as _;
+use x11rb::{COPY_DEPTH_FROM_PARENT, COPY_FROM_PARENT, CURRENT_TIME, NONE};
+
+x11rb::atom_manager! {
+    Atoms: AtomsCookie {
+        CLIPBOARD,
+        TARGETS,
+        UTF8_STRING,
+        TEXT,
+        TEXT_PLAIN: b"text/plain;charset=utf-8",
+    }
+}
+
+thread_local! {
+    /// Dropped on failure so the next write reconnects. Per thread, so one
+    /// wedged in a write is left behind with its thread.
+    static OWNER: RefCell<Option<Owner>> = const { RefCell::new(None) };
+}
+
+struct Owner {
+    conn: Arc<RustConnection>,
+    window: Window,
+    atoms: Atoms,
+    content: Arc<Mutex<Vec<u8>>>,
+}
+
+pub(crate) fn set_clip(content: &str, selection: Selection) -> Result<(), ClipboardError> {
+    let current = match OWNER.take() {
+        Some(current) => current,
+        None => Owner::connect()?,
+    };
+    if !current.set(content, selection)? {
+        let name = match selection {
+            Selection::Clipboard => "CLIPBOARD",
+            Selection::Primary => "PRIMARY",
+        };
+        return Err(ClipboardError::Write {
+            command: "x11",
+            source: io::Error::other(format!("another client kept the {} selection", name)),
+        });
+    }
+    OWNER.set(Some(current));
+    Ok(())
+}
+
+impl Owner {
+    fn connect() -> Result<Self, ClipboardError> {
+        let (conn, screen) = RustConnection::connect(session::var("DISPLAY").as_deref())?;
+        let conn = Arc::new(conn);
+        let root = conn.setup().roots[screen].root;
+        let window = conn.generate_id()?;
+        conn.create_window(
+            COPY_DEPTH_FROM_PARENT,
+            window,
+            root,
+            0,
+            0,
+            1,
+            1,
+            0,
+            WindowClass::INPUT_ONLY,
+            COPY_FROM_PARENT,
+            // For the DestroyNotify that stops the serving thread.
+            &CreateWindowAux::new().event_mask(EventMask::STRUCTURE_NOTIFY),
+        )
+        .map_err(ReplyOrIdError::from)?;
+        let atoms = Atoms::new(&*conn)
+            .map_err(ReplyOrIdError::from)?
+            .reply()
+            .map_err(ReplyOrIdError::from)?;
+        let content = Arc::new(Mutex::new(Vec::new()));
+        let (serve_conn, serve_content) = (conn.clone(), content.clone());
+        thread::spawn(move || serve(&serve_conn, window, &atoms, &serve_content));
+        info!("connected to X11 for clipboard writes");
+        Ok(Owner {
+            conn,
+            window,
+            atoms,
+            content,
+        })
+    }
+
+    /// Takes the selection with `content`; `false` if another client kept it.
+    fn set(&self, content: &str, selection: Selection) -> Result<bool, ReplyOrIdError> {
+        // Both selections are answered with the latest content.
+        *self.content.lock().unwrap() = content.as_bytes().to_vec();
+        let selection = match selection {
+            Selection::Clipboard => self.atoms.CLIPBOARD,
+            Selection::Primary => AtomEnum::PRIMARY.into(),
+        };
+        self.conn
+            .set_selection_owner(self.window, selection, CURRENT_TIME)?;
+        let reply = self.conn.get_selection_owner(selection)?.reply()?;
+        Ok(reply.owner == self.window)
+    }
+}
+
+/// Destroying the window ends the serving thread, which drops the last
+/// handle to the connection and so closes it.
+impl Drop for Owner {
+    fn drop(&mut self) {
+        let destroyed = self
+            .conn
+            .destroy_window(self.window)
+            .map_err(ReplyOrIdError::from)
+            .and_then(|_| Ok(self.conn.flush()?));
+        if let Err(e) = destroyed {
+            debug!("Failed to destroy the X11 clipboard window: {}", e);
+        }
+    }
+}
+
+fn serve(conn: &RustConnection, window: Window, atoms: &Atoms, content: &Mutex<Vec<u8>>) {
+    loop {
+        match conn.wait_for_event() {
+            Ok(Event::DestroyNotify(event)) if event.window == window => {
+                debug!("X11 clipboard window destroyed");
+                return;
+            }
+            Ok(Event::SelectionRequest(request)) => {
+                if let Err(e) = answer(conn, atoms, content, &request) {
+                    error!("Failed to answer X11 clipboard request: {}", e);
+                }
+            }
+            Ok(Event::SelectionClear(_)) => debug!("another X11 client took the clipboard"),
+            Ok(_) => {}
+            Err(e) => {
+                error!("X11 clipboard connection lost: {}", e);
+                return;
+            }
+        }
+    }
+}
+
+/// Stores the content (or the supported targets) on the requestor's window
+/// and tells it so. Content too large for one request would need the INCR
+/// protocol, which isn't implemented, so such requests are refused.
+fn answer(
+    conn: &RustConnection,
+    atoms: &Atoms,
+    content: &Mutex<Vec<u8>>,
+    request: &SelectionRequestEvent,
+) -> Result<(), ReplyOrIdError> {
+    // Obsolete clients don't name a property.
+    let property = if request.property == NONE {
+        request.target
+    } else {
+        request.property
+    };
+    let text = [
+        atoms.UTF8_STRING,
+        atoms.TEXT_PLAIN,
+        atoms.TEXT,
+        AtomEnum::STRING.into(),
+    ];
+    let stored = if request.target == atoms.TARGETS {
+        let mut targets = vec![atoms.TARGETS];
+        targets.extend(text);
+        conn.change_property32(
+            PropMode::REPLACE,
+            request.requestor,
+            property,
+            AtomEnum::ATOM,
+            &targets,
+        )?;
+        true
+    } else if text.contains(&request.target) {
+        let content = content.lock().unwrap();
+        if content.len() + 64 < conn.maximum_request_bytes() {
+            conn.change_property8(
+                PropMode::REPLACE,
+                request.requestor,
+                property,
+                request.target,
+                &content,
+            )?;
+            true
+        } else {
+            error!("Clipboard content too large for X11 without INCR");
+            false
+        }
+    } else {
+        false
+    };
+    let notify = SelectionNotifyEvent {
+        response_type: SELECTION_NOTIFY_EVENT,
+        sequence: 0,
+        time: request.time,
+        requestor: request.requestor,
+        selection: request.selection,
+        target: request.target,
+        property: if stored { property } else { NONE },
+    };
+    conn.send_event(false, request.requestor, EventMask::NO_EVENT, notify)?;
+    conn.flush()?;
+    Ok(())
+}